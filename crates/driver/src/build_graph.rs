@@ -1,5 +1,5 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     path::PathBuf,
     sync::Arc,
     time::Instant,
@@ -9,9 +9,10 @@ use crate::{async_read_json_file, read_json_file, write_json_file, BuildGraphArg
 use anyhow::{anyhow, Result};
 use bzl_gen_build_shared_types::{
     directive::{
-        AttrStringListConfig, BinaryRefAndPath, BinaryRefConfig, EntityDirectiveConfig,
-        ManualRefConfig,
+        AttrScalarConfig, AttrStringListConfig, BinaryRefAndPath, BinaryRefConfig, DirectiveOrigin,
+        EntityDirectiveConfig, IneffectiveDirective, ManualRefConfig,
     },
+    entity_pattern::EntityPattern,
     internal_types::tree_node::TreeNode,
     *,
 };
@@ -40,6 +41,9 @@ pub struct GraphNode {
     pub binary_ref_configs: Vec<BinaryRefConfig>,
     #[serde(default, skip_serializing_if = "GraphNodeMetadata::is_empty")]
     pub node_metadata: GraphNodeMetadata,
+    /// The configuration the sources of the node belong to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub configuration: Option<String>,
     pub node_type: NodeType,
     pub node_label: String,
 }
@@ -54,12 +58,18 @@ pub struct GraphNodeMetadata {
     pub manual_refs: Vec<ManualRefConfig>,
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub attr_string_lists: Vec<AttrStringListConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attr_scalars: Vec<AttrScalarConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub target_names: Vec<String>,
 }
 impl GraphNodeMetadata {
     pub fn is_empty(&self) -> bool {
         self.binary_refs.is_empty()
             && self.manual_refs.is_empty()
             && self.attr_string_lists.is_empty()
+            && self.attr_scalars.is_empty()
+            && self.target_names.is_empty()
     }
 }
 
@@ -70,6 +80,8 @@ impl<'a> From<&'a NodeExternalState> for GraphNodeMetadata {
             binary_refs: nes.binary_refs.clone(),
             manual_refs: nes.manual_refs.clone(),
             attr_string_lists: nes.attr_string_lists.clone(),
+            attr_scalars: nes.attr_scalars.clone(),
+            target_names: nes.target_names.clone(),
         }
     }
 }
@@ -78,6 +90,68 @@ impl<'a> From<&'a NodeExternalState> for GraphNodeMetadata {
 pub struct GraphMapping {
     #[serde(serialize_with = "bzl_gen_build_shared_types::serde_helpers::ordered_map")]
     pub build_mapping: HashMap<String, GraphNode>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub excluded_files: Vec<String>,
+}
+
+/// Directives that had no effect anywhere they were applied, written out by `--lint-directives`.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct DirectiveLintReport {
+    pub ineffective_directives: Vec<IneffectiveDirective>,
+}
+
+/// Collects directive lint results across tree nodes. A directive applied to many nodes, such as
+/// one from `path_directives`, is only ineffective when it changed nothing on every one of them.
+#[derive(Default)]
+struct DirectiveLint {
+    applied: HashMap<(String, DirectiveOrigin), usize>,
+    ineffective: HashMap<(String, DirectiveOrigin), (usize, String)>,
+    unmatched: Vec<IneffectiveDirective>,
+}
+
+impl DirectiveLint {
+    fn add_tree_node(
+        &mut self,
+        directive_origins: BTreeMap<String, BTreeSet<DirectiveOrigin>>,
+        ineffective_directives: Vec<IneffectiveDirective>,
+    ) {
+        for (directive, origins) in directive_origins {
+            for origin in origins {
+                *self.applied.entry((directive.clone(), origin)).or_default() += 1;
+            }
+        }
+        for d in ineffective_directives {
+            self.ineffective
+                .entry((d.directive, d.origin))
+                .or_insert_with(|| (0, d.reason))
+                .0 += 1;
+        }
+    }
+
+    /// Records a directive that had no effect regardless of which nodes it was applied to.
+    fn add_unmatched(&mut self, d: IneffectiveDirective) {
+        self.unmatched.push(d);
+    }
+
+    fn report(self) -> DirectiveLintReport {
+        let applied = self.applied;
+        let mut ineffective_directives: Vec<IneffectiveDirective> = self
+            .ineffective
+            .into_iter()
+            .filter(|(k, (count, _))| applied.get(k).copied().unwrap_or_default() <= *count)
+            .map(|((directive, origin), (_, reason))| IneffectiveDirective {
+                directive,
+                origin,
+                reason,
+            })
+            .chain(self.unmatched)
+            .collect();
+        ineffective_directives.sort();
+        ineffective_directives.dedup();
+        DirectiveLintReport {
+            ineffective_directives,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -111,18 +185,24 @@ impl Default for NodeType {
 struct NodeExternalState {
     pub name: Arc<String>,
     pub node_type: NodeType,
+    pub configuration: Option<String>,
     pub binary_refs: Vec<BinaryRefAndPath>,
     pub manual_refs: Vec<ManualRefConfig>,
     pub attr_string_lists: Vec<AttrStringListConfig>,
+    pub attr_scalars: Vec<AttrScalarConfig>,
+    pub target_names: Vec<String>,
 }
 impl NodeExternalState {
     pub fn empty(name: Arc<String>, node_type: NodeType) -> Self {
         Self {
             name,
             node_type,
+            configuration: None,
             binary_refs: Default::default(),
             manual_refs: Default::default(),
             attr_string_lists: Default::default(),
+            attr_scalars: Default::default(),
+            target_names: Default::default(),
         }
     }
 }
@@ -552,14 +632,16 @@ fn parent_dir(k: &str) -> &str {
 }
 async fn load_initial_graph(
     extracted_mappings: &ExtractedMappings,
-    config_entity_directives: &Vec<directive::EntityDirectiveConfig>,
+    config_entity_directives: &Vec<(directive::EntityDirectiveConfig, DirectiveOrigin)>,
     all_defs: Arc<HashMap<Arc<String>, u64>>,
     concurrent_io_operations: &'static Semaphore,
+    lint: &mut DirectiveLint,
 ) -> Result<GraphState> {
     let mut load_i = Vec::with_capacity(extracted_mappings.relative_path_to_extractmapping.len());
 
     for (_k, p) in extracted_mappings.relative_path_to_extractmapping.iter() {
         let pb = PathBuf::from(&p.path);
+        let configuration = p.configuration.clone();
         let all_defs = all_defs.clone();
         load_i.push(tokio::spawn(async move {
             let c = concurrent_io_operations.acquire().await.unwrap();
@@ -589,6 +671,7 @@ async fn load_initial_graph(
 
                 (
                     e.label_or_repo_path,
+                    configuration,
                     (
                         refs,
                         defs,
@@ -597,6 +680,9 @@ async fn load_initial_graph(
                         e.binary_ref_directives,
                         e.manual_ref_directives,
                         e.attr_string_list_directives,
+                        e.attr_scalar_directives,
+                        e.target_name_directives,
+                        (e.directive_origins, e.ineffective_directives),
                     ),
                 )
             })
@@ -622,8 +708,19 @@ async fn load_initial_graph(
             &mut self,
             d: &EntityDirectiveConfig,
             all_defs: &HashMap<Arc<String>, u64>,
-        ) {
-            if let Some(aon) = all_defs.get(&d.act_on) {
+        ) -> Result<bool> {
+            let act_on: Vec<u64> = if EntityPattern::is_pattern(&d.act_on) {
+                let pattern = EntityPattern::new(&d.act_on)?;
+                all_defs
+                    .iter()
+                    .filter(|(k, _)| pattern.matches(k))
+                    .map(|(_, v)| *v)
+                    .collect()
+            } else {
+                all_defs.get(&d.act_on).into_iter().copied().collect()
+            };
+            let matched = !act_on.is_empty();
+            for aon in act_on {
                 let target = d
                     .pointing_at
                     .iter()
@@ -631,10 +728,11 @@ async fn load_initial_graph(
                     .copied();
                 match d.command {
                     EntityDirective::Link => {
-                        self.add_link_map.entry(*aon).or_default().extend(target);
+                        self.add_link_map.entry(aon).or_default().extend(target);
                     }
                 }
             }
+            Ok(matched)
         }
         // This is to ensure that the order of links being visited doesn't matter
         // and if we say A -> B
@@ -671,14 +769,21 @@ async fn load_initial_graph(
     }
     let mut entity_links: EntityLinksMaps = Default::default();
 
-    for ed in config_entity_directives.iter() {
-        entity_links.add_directive(&ed, all_defs.as_ref());
+    for (ed, origin) in config_entity_directives.iter() {
+        if !entity_links.add_directive(&ed, all_defs.as_ref())? {
+            lint.add_unmatched(IneffectiveDirective {
+                directive: Directive::EntityDirective(ed.clone()).to_string(),
+                origin: origin.clone(),
+                reason: "act_on matches no definitions".to_string(),
+            });
+        }
     }
 
     let mut idx: usize = 0;
     for li in load_i {
         let (
             k,
+            configuration,
             (
                 compile_refs,
                 defs,
@@ -687,6 +792,9 @@ async fn load_initial_graph(
                 binary_ref_directives,
                 manual_ref_directives,
                 attr_string_list_directives,
+                attr_scalar_directives,
+                target_name_directives,
+                (directive_origins, ineffective_directives),
             ),
         ) = li.await??;
 
@@ -696,9 +804,12 @@ async fn load_initial_graph(
         let node_external_state = NodeExternalState {
             name: m,
             node_type: NodeType::RealNode,
+            configuration,
             binary_refs: binary_ref_directives,
             manual_refs: manual_ref_directives,
             attr_string_lists: attr_string_list_directives,
+            attr_scalars: attr_scalar_directives,
+            target_names: target_name_directives,
         };
         reverse_map.insert(idx, Arc::new(node_external_state));
 
@@ -737,8 +848,18 @@ async fn load_initial_graph(
 
         // Honor the entity directives
         for d in entity_directives {
-            entity_links.add_directive(&d, all_defs.as_ref());
+            if !entity_links.add_directive(&d, all_defs.as_ref())? {
+                let directive = Directive::EntityDirective(d).to_string();
+                for origin in directive_origins.get(&directive).into_iter().flatten() {
+                    lint.add_unmatched(IneffectiveDirective {
+                        directive: directive.clone(),
+                        origin: origin.clone(),
+                        reason: "act_on matches no definitions".to_string(),
+                    });
+                }
+            }
         }
+        lint.add_tree_node(directive_origins, ineffective_directives);
         idx += 1;
     }
 
@@ -841,7 +962,9 @@ pub async fn build_graph(
 
     let all_defs = Arc::new(all_defs);
 
-    let mut configured_entity_directives: Vec<directive::EntityDirectiveConfig> = Vec::default();
+    let mut configured_entity_directives: Vec<(directive::EntityDirectiveConfig, DirectiveOrigin)> =
+        Vec::default();
+    let mut lint = DirectiveLint::default();
 
     let mut circular_allow_list: Vec<String> = vec![];
     for (_k, v) in project_conf.configurations.iter() {        
@@ -856,13 +979,30 @@ pub async fn build_graph(
                         Directive::SrcDirective(_) => (),   // handled elsewhere
                         Directive::ManualRef(_) => (),      // handled elsewhere
                         Directive::AttrStringList(_) => (), // handled elsewhere
+                        Directive::AttrScalar(_) => (),     // handled elsewhere
+                        Directive::TargetName(_) => (),     // handled elsewhere
+                        Directive::Ignore => (),            // handled elsewhere
+                        Directive::NoBinary => (),          // handled elsewhere
                         Directive::EntityDirective(ed) => {
-                            configured_entity_directives.push(ed.clone())
+                            configured_entity_directives.push((ed.clone(), directives.origin()))
                         }
                     }
                 }
             }
-            Err(e) => return Err(anyhow!("{:#?}", e)),
+            Err(e) => return Err(anyhow!("{:?}", e)),
+        }
+        if !extracted_mappings
+            .relative_path_to_extractmapping
+            .keys()
+            .any(|k| k.starts_with(&directives.prefix))
+        {
+            for directive in directives.directive_strings.iter() {
+                lint.add_unmatched(IneffectiveDirective {
+                    directive: directive.clone(),
+                    origin: directives.origin(),
+                    reason: "the prefix matches no files".to_string(),
+                });
+            }
         }
     }
 
@@ -873,6 +1013,7 @@ pub async fn build_graph(
         &configured_entity_directives,
         all_defs,
         concurrent_io_operations,
+        &mut lint,
     )
     .await?;
 
@@ -911,17 +1052,27 @@ pub async fn build_graph(
             .entry(k_name.as_ref().clone())
             .or_insert_with(|| GraphNode::default());
 
+        let mut configurations: BTreeSet<&String> = node_state.configuration.iter().collect();
         for child_node in child_nodes.into_iter() {
             let node_state = graph
                 .reverse_map
                 .get(&child_node)
                 .expect("Graph invalid if missing");
             if node_state.node_type == NodeType::RealNode {
+                configurations.extend(node_state.configuration.iter());
                 output_node
                     .child_nodes
                     .insert(node_state.name.as_ref().clone(), node_state.as_ref().into());
             }
         }
+        if configurations.len() > 1 {
+            return Err(anyhow!(
+                "Sources of the configurations {:?} were collapsed into {}, the cycle between them has to be broken",
+                configurations,
+                k_name
+            ));
+        }
+        output_node.configuration = configurations.into_iter().next().cloned();
 
         for outbound_edge in outbound_compile_edges.iter() {
             if let Some(t) = graph.get_node_label(outbound_edge) {
@@ -951,9 +1102,23 @@ pub async fn build_graph(
         output_node.runtime_dependencies.sort();
     }
 
-    let out = GraphMapping { build_mapping };
+    let out = GraphMapping {
+        build_mapping,
+        excluded_files: extracted_mappings.excluded_files.clone(),
+    };
     write_json_file(extract.graph_out.as_path(), &out)?;
 
+    let lint_report = lint.report();
+    if !lint_report.ineffective_directives.is_empty() {
+        info!(
+            "{} directives had no effect",
+            lint_report.ineffective_directives.len()
+        );
+    }
+    if let Some(lint_out) = &extract.lint_directives {
+        write_json_file(lint_out.as_path(), &lint_report)?;
+    }
+
     Ok(())
 }
 
@@ -1105,4 +1270,56 @@ mod tests {
 
         assert!(graph.consumed_nodes.is_empty());
     }
+
+    #[test]
+    fn test_directive_lint() {
+        let prefix = DirectiveOrigin::PathDirective {
+            prefix: "com/foo".to_string(),
+        };
+        let file = DirectiveOrigin::File {
+            path: "com/foo/bar/Baz.scala".to_string(),
+            line: Some(2),
+        };
+        let ineffective = |directive: &str, origin: &DirectiveOrigin| IneffectiveDirective {
+            directive: directive.to_string(),
+            origin: origin.clone(),
+            reason: "not referenced".to_string(),
+        };
+        let origins = |directives: &[(&str, &DirectiveOrigin)]| {
+            let mut m: BTreeMap<String, BTreeSet<DirectiveOrigin>> = BTreeMap::default();
+            for (d, o) in directives {
+                m.entry(d.to_string()).or_default().insert((*o).clone());
+            }
+            m
+        };
+
+        let mut lint = DirectiveLint::default();
+        // The path directive removed a ref from the second node, so it did something.
+        lint.add_tree_node(
+            origins(&[
+                ("unref:com.a.A", &prefix),
+                ("unref:com.b.B", &prefix),
+                ("unref:com.c.C", &file),
+            ]),
+            vec![
+                ineffective("unref:com.a.A", &prefix),
+                ineffective("unref:com.b.B", &prefix),
+                ineffective("unref:com.c.C", &file),
+            ],
+        );
+        lint.add_tree_node(
+            origins(&[("unref:com.a.A", &prefix), ("unref:com.b.B", &prefix)]),
+            vec![ineffective("unref:com.b.B", &prefix)],
+        );
+
+        assert_eq!(
+            lint.report(),
+            DirectiveLintReport {
+                ineffective_directives: vec![
+                    ineffective("unref:com.b.B", &prefix),
+                    ineffective("unref:com.c.C", &file),
+                ]
+            }
+        );
+    }
 }
@@ -12,10 +12,10 @@ use crate::{
 };
 use anyhow::{anyhow, Context, Result};
 use bzl_gen_build_shared_types::{
-    api::extracted_data::ExtractedData, build_config::SourceConfig,
+    api::extracted_data::ExtractedData, build_config::SourceConfig, directive::DirectiveOrigin,
     internal_types::tree_node::TreeNode, module_config::ModuleConfig, Directive, ProjectConf,
 };
-use globset::{Glob, GlobSet, GlobSetBuilder};
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
 use ignore::{DirEntry, WalkBuilder};
 use log::info;
 use serde::{Deserialize, Serialize};
@@ -25,12 +25,18 @@ use tokio::sync::Semaphore;
 pub struct ExtractedMapping {
     pub path: String,
     pub content_sha: String,
+    // The key of the configuration the sources were extracted with, none for external ones.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub configuration: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ExtractedMappings {
     #[serde(default, serialize_with = "crate::serde_helpers::ordered_map")]
     pub relative_path_to_extractmapping: HashMap<String, ExtractedMapping>,
+    // Files skipped by `exclude_globs` or an `ignore` directive, relative to the working directory.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub excluded_files: Vec<String>,
 }
 
 lazy_static::lazy_static! {
@@ -57,20 +63,28 @@ pub struct Extractor {
 
 #[derive(Debug)]
 pub struct ExtractConfig {
+    configuration: String,
     extractor: Extractor,
     sha_to_extract_root: PathBuf,
     module_config: &'static ModuleConfig,
     file_extensions: Vec<OsString>,
 }
 
+// Returns none for a file that opted out with an `ignore` directive.
 async fn process_file(
     relative_path: PathBuf,
     working_directory: &'static PathBuf,
     path: PathBuf,
     concurrent_io_operations: &'static Semaphore,
     opt: Arc<ExtractConfig>,
-) -> Result<(ProcessedFile, Duration)> {
+) -> Result<Option<(ProcessedFile, Duration)>> {
     let _c = concurrent_io_operations.acquire().await?;
+    let header = leading_comment_block(&path)
+        .await
+        .with_context(|| format!("Unable to read source file {:?}", path))?;
+    if has_ignore_directive(&header) {
+        return Ok(None);
+    }
     let sha256 = {
         let r = Sha256Value::from_path(path.as_path()).await.map_err(|e| {
             anyhow!(
@@ -103,7 +117,7 @@ async fn process_file(
     };
 
     if processed_file.extract_path.exists() {
-        Ok((processed_file, st.elapsed()))
+        Ok(Some((processed_file, st.elapsed())))
     } else {
         use tokio::process::Command;
         let mut command = Command::new(opt.extractor.path.as_path());
@@ -138,7 +152,7 @@ async fn process_file(
                 processed_file.extract_path
             ));
         }
-        Ok((processed_file, st.elapsed()))
+        Ok(Some((processed_file, st.elapsed())))
     }
 }
 
@@ -172,6 +186,59 @@ pub fn to_globset(test_globs: &Vec<String>) -> Result<GlobSet> {
     }
 }
 
+pub fn to_exclude_globset(exclude_globs: &[String]) -> Result<GlobSet> {
+    let mut globset_builder = GlobSetBuilder::new();
+    for glob in exclude_globs.iter() {
+        // `*` must not cross directories, so `src/*.py` leaves `src/sub/a.py` alone.
+        globset_builder.add(
+            GlobBuilder::new(glob.as_str())
+                .literal_separator(true)
+                .build()?,
+        );
+    }
+    Ok(globset_builder.build()?)
+}
+
+// read the blank and comment lines at the top of the file, the only place an `ignore` may appear
+async fn leading_comment_block(path: &Path) -> Result<String> {
+    use tokio::io::AsyncBufReadExt;
+    let mut reader = tokio::io::BufReader::new(tokio::fs::File::open(path).await?);
+    let mut header = String::new();
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        if reader.read_until(b'\n', &mut buf).await? == 0 {
+            break;
+        }
+        let line = String::from_utf8_lossy(&buf);
+        let trimmed = line.trim();
+        if !(trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with("//")) {
+            break;
+        }
+        header.push_str(&line);
+    }
+    Ok(header)
+}
+
+// check whether the comment block at the top of the file carries `bzl_gen_build: ignore`
+fn has_ignore_directive(content: &str) -> bool {
+    for line in content.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        if !(trimmed.starts_with('#') || trimmed.starts_with("//")) {
+            return false;
+        }
+        let directive = Directive::extract_directive(trimmed, "#")
+            .or_else(|| Directive::extract_directive(trimmed, "//"));
+        if directive.map(|d| d.trim() == "ignore").unwrap_or(false) {
+            return true;
+        }
+    }
+    false
+}
+
 pub fn path_is_match(
     path: &Path,
     test_globs: &Vec<String>,
@@ -186,21 +253,27 @@ pub fn path_is_match(
     }
 }
 
+// Returns the results of `on_entry` along with the relative paths of the files
+// that were skipped because of `exclude_globs`.
+#[allow(clippy::too_many_arguments)]
 async fn walk_directories<A, F, R>(
     working_directory: &PathBuf,
     child_path: String,
     file_extensions: &Vec<OsString>,
     test_globs: &Vec<String>,
+    exclude_globs: &[String],
     source_config: SourceConfig,
     extract_config: Option<Arc<ExtractConfig>>,
     on_entry: F,
-) -> Result<Vec<A>>
+) -> Result<(Vec<A>, Vec<PathBuf>)>
 where
     F: Fn(DirEntry, PathBuf, Option<Arc<ExtractConfig>>) -> R,
     R: futures::Future<Output = Result<A>> + Send + 'static,
 {
     let mut results: Vec<A> = Vec::default();
+    let mut excluded: Vec<PathBuf> = Vec::default();
     let globset = to_globset(test_globs)?;
+    let exclude_globset = to_exclude_globset(exclude_globs)?;
     for entry in WalkBuilder::new(working_directory.join(child_path))
         .build()
         .into_iter()
@@ -212,9 +285,13 @@ where
             .path()
             .strip_prefix(working_directory.as_path())?
             .to_path_buf();
+        if exclude_globset.is_match(&relative_path) {
+            excluded.push(relative_path);
+            continue;
+        }
         results.push(on_entry(entry, relative_path, extract_config.clone()).await?);
     }
-    Ok(results)
+    Ok((results, excluded))
 }
 
 async fn async_extract_def_refs(
@@ -223,25 +300,30 @@ async fn async_extract_def_refs(
     concurrent_io_operations: &'static Semaphore,
     opt: Arc<ExtractConfig>,
     source_config: SourceConfig,
-) -> Result<((PathBuf, Duration), Vec<ProcessedFile>)> {
+) -> Result<((PathBuf, Duration), Vec<ProcessedFile>, Vec<PathBuf>)> {
     let test_globs = &opt.module_config.test_globs;
+    let exclude_globs = &opt.module_config.exclude_globs;
     let file_extensions = &opt.file_extensions;
-    let results = walk_directories(
+    let (results, mut excluded) = walk_directories(
         working_directory,
         child_path,
         &file_extensions,
         test_globs,
+        exclude_globs,
         source_config,
         Some(opt.clone()),
         |entry, relative_path, opt_config| async move {
             match opt_config {
-                Some(config) => Ok(tokio::spawn(process_file(
-                    relative_path,
-                    working_directory,
-                    entry.into_path(),
-                    concurrent_io_operations,
-                    config,
-                ))),
+                Some(config) => Ok((
+                    relative_path.clone(),
+                    tokio::spawn(process_file(
+                        relative_path,
+                        working_directory,
+                        entry.into_path(),
+                        concurrent_io_operations,
+                        config,
+                    )),
+                )),
                 None => Err(anyhow::anyhow!("ExtractConfig not found")),
             }
         },
@@ -251,23 +333,31 @@ async fn async_extract_def_refs(
     let mut max_duration = Duration::ZERO;
     let mut max_target = PathBuf::from("");
     let mut processed_files = Vec::default();
-    for r in results {
-        let (e, dur) = r.await??;
+    for (relative_path, r) in results {
+        let (e, dur) = match r.await?? {
+            Some(processed) => processed,
+            None => {
+                excluded.push(relative_path);
+                continue;
+            }
+        };
         if dur > max_duration {
             max_duration = dur;
             max_target = e.file_path.clone();
         }
         processed_files.push(e);
     }
-    Ok(((max_target, max_duration), processed_files))
+    Ok(((max_target, max_duration), processed_files, excluded))
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn merge_defrefs(
     concurrent_io_operations: &Semaphore,
     path_sha_to_merged_defrefs: &'static Path,
     entry: String,
     project_conf: &'static ProjectConf,
     mut work_items: Vec<ProcessedFile>,
+    configuration: Option<String>,
     sha_of_conf_config: Arc<String>,
     no_aggregate_source: bool,
 ) -> Result<(String, ExtractedMapping)> {
@@ -306,16 +396,26 @@ async fn merge_defrefs(
             }
         }
 
-        let directive_strings: Vec<String> = project_conf
+        for directive_conf in project_conf
             .path_directives
             .iter()
             .filter(|directive| entry.starts_with(&directive.prefix))
-            .flat_map(|e| e.directive_strings.iter())
-            .cloned()
-            .collect();
-
-        let directives = Directive::from_strings(&directive_strings)?;
-        existing.apply_directives(&directives);
+        {
+            let origin = directive_conf.origin();
+            let directives =
+                Directive::from_strings_with_origin(&directive_conf.directive_strings, &origin)?;
+            if directives.contains(&Directive::Ignore) {
+                return Err(anyhow!(
+                    "The ignore directive is only supported inline, use exclude_globs in the module config to skip {}",
+                    entry
+                ));
+            }
+            let directives: Vec<(Directive, DirectiveOrigin)> = directives
+                .into_iter()
+                .map(|d| (d, origin.clone()))
+                .collect();
+            existing.apply_directives_with_origins(&directives);
+        }
 
         async_write_json_file(&treenode_path, &existing).await?;
         drop(c);
@@ -326,6 +426,7 @@ async fn merge_defrefs(
         ExtractedMapping {
             path: treenode_path.to_string_lossy().to_string(),
             content_sha: format!("{}", merged_sha),
+            configuration,
         },
     ))
 }
@@ -351,6 +452,7 @@ fn extract_configs(
             v.file_extensions.iter().map(|ex| ex.into()).collect();
 
         cfgs.push(ExtractConfig {
+            configuration: conf_key.clone(),
             extractor,
             sha_to_extract_root: sha_to_extract_root.to_path_buf(),
             module_config: v,
@@ -393,6 +495,7 @@ async fn inner_load_external(
         format!("sha256__{}", sha256),
         project_conf,
         work_items,
+        None,
         sha_of_conf_config,
         _opt.no_aggregate_source,
     )
@@ -444,7 +547,11 @@ async fn run_extractors_on_data<'a>(
     concurrent_io_operations: &'static Semaphore,
     sha_to_extract_root: &'a Path,
     extractors: &'a Extractors,
-) -> Result<(Vec<Vec<ProcessedFile>>, (PathBuf, Duration))> {
+) -> Result<(
+    Vec<(String, Vec<ProcessedFile>)>,
+    Vec<PathBuf>,
+    (PathBuf, Duration),
+)> {
     let cfgs: Vec<ExtractConfig> =
         extract_configs(opt, project_conf, sha_to_extract_root, extractors)?;
     let cfg_refs: Vec<Arc<ExtractConfig>> = cfgs.into_iter().map(|cfg| Arc::new(cfg)).collect();
@@ -464,32 +571,39 @@ async fn run_extractors_on_data<'a>(
         );
     }
 
-    let mut async_join_handle: Vec<
-        tokio::task::JoinHandle<Result<((PathBuf, Duration), Vec<ProcessedFile>)>>,
-    > = Vec::default();
+    let mut async_join_handle: Vec<(
+        String,
+        tokio::task::JoinHandle<Result<((PathBuf, Duration), Vec<ProcessedFile>, Vec<PathBuf>)>>,
+    )> = Vec::default();
     for (path, extract_config, source_config) in all_visiting_paths.into_iter() {
-        async_join_handle.push(tokio::spawn(async_extract_def_refs(
-            &opt.working_directory,
-            path,
-            concurrent_io_operations,
-            extract_config.clone(),
-            source_config,
-        )));
+        async_join_handle.push((
+            extract_config.configuration.clone(),
+            tokio::spawn(async_extract_def_refs(
+                &opt.working_directory,
+                path,
+                concurrent_io_operations,
+                extract_config.clone(),
+                source_config,
+            )),
+        ));
     }
 
-    let mut results: Vec<Vec<ProcessedFile>> = Vec::with_capacity(async_join_handle.len());
+    let mut results: Vec<(String, Vec<ProcessedFile>)> =
+        Vec::with_capacity(async_join_handle.len());
+    let mut excluded_files: Vec<PathBuf> = Vec::default();
     let mut max_duration = Duration::ZERO;
     let mut max_target: PathBuf = PathBuf::from("");
 
-    while let Some(nxt) = async_join_handle.pop() {
-        let ((cur_t, dur), files) = nxt.await??;
+    while let Some((configuration, nxt)) = async_join_handle.pop() {
+        let ((cur_t, dur), files, excluded) = nxt.await??;
         if dur > max_duration {
             max_duration = dur;
             max_target = cur_t;
         }
-        results.push(files);
+        results.push((configuration, files));
+        excluded_files.extend(excluded);
     }
-    Ok((results, (max_target, max_duration)))
+    Ok((results, excluded_files, (max_target, max_duration)))
 }
 
 async fn load_extractors(extract: &'static Extract) -> Result<Extractors> {
@@ -587,7 +701,7 @@ pub async fn extract_defrefs(
             Vec::default()
         };
 
-    let (expanded, (inner_max_path, inner_max_duration)) = probe_files.await??;
+    let (expanded, excluded_files, (inner_max_path, inner_max_duration)) = probe_files.await??;
     info!(
         "Extraction phase took: {:?}, longest one {:?} - took: {:#?}",
         st.elapsed(),
@@ -598,7 +712,7 @@ pub async fn extract_defrefs(
     let st = Instant::now();
 
     let mut merge_work: Vec<_> = Vec::default();
-    for processed_files in expanded {
+    for (configuration, processed_files) in expanded {
         let mut work: HashMap<String, Vec<ProcessedFile>> = HashMap::default();
 
         for processed_file in processed_files.into_iter() {
@@ -623,6 +737,7 @@ pub async fn extract_defrefs(
                 entry,
                 project_conf,
                 files,
+                Some(configuration.clone()),
                 sha_of_conf_config.clone(),
                 opt.no_aggregate_source,
             )))
@@ -634,12 +749,32 @@ pub async fn extract_defrefs(
 
     while let Some(r) = merge_work.pop() {
         let (k, v) = r.await.map_err(|e| anyhow!("{:#?}", e))??;
-        result.insert(k, v);
+        let configuration = v.configuration.clone();
+        if let Some(previous) = result.insert(k.clone(), v) {
+            // Aggregated targets are keyed by directory, so sources of several configurations in
+            // one directory would share a single target. Not supported, see the README.
+            if previous.configuration != configuration {
+                return Err(anyhow!(
+                    "{} has sources of both the {:?} and {:?} configurations, which can't share an aggregated target: use --no-aggregate-source, or a run per configuration",
+                    k,
+                    previous.configuration.unwrap_or_default(),
+                    configuration.unwrap_or_default()
+                ));
+            }
+        }
     }
     info!("Merging operations took: {:?}", st.elapsed());
 
+    let mut excluded_files: Vec<String> = excluded_files
+        .into_iter()
+        .map(|p| p.to_string_lossy().to_string())
+        .collect();
+    excluded_files.sort();
+    excluded_files.dedup();
+
     let extracted_mappings = ExtractedMappings {
         relative_path_to_extractmapping: result,
+        excluded_files,
     };
 
     write_json_file(extract.extracted_mappings.as_path(), &extracted_mappings)?;
@@ -657,22 +792,24 @@ mod tests {
         let child_path = "com".to_string();
         let py_exts = vec![std::ffi::OsString::from("py")];
         let test_globs = vec!["**/test*.py".to_string(), "**/*test.py".to_string()];
-        let result0 = walk_directories(
+        let (result0, _) = walk_directories(
             &working_directory,
             child_path.clone(),
             &py_exts,
             &test_globs,
+            &[],
             SourceConfig::Main,
             None,
             |_entry, relative_path, _| async move { Ok(relative_path) },
         )
         .await?;
         assert_eq!(result0, vec![PathBuf::from("com/example/hello.py")]);
-        let result2 = walk_directories(
+        let (result2, _) = walk_directories(
             &working_directory,
             child_path.clone(),
             &py_exts,
             &test_globs,
+            &[],
             SourceConfig::Test,
             None,
             |_entry, relative_path, _| async move { Ok(relative_path) },
@@ -681,4 +818,70 @@ mod tests {
         assert_eq!(result2, vec![PathBuf::from("com/example/hello_test.py")]);
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_walk_directories_excludes() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let working_directory = dir.path().to_path_buf();
+        let pkg = working_directory.join("app");
+        fs::create_dir_all(pkg.join("fixtures"))?;
+        fs::write(pkg.join("main.py"), "import os\n")?;
+        fs::write(pkg.join("fixtures/gen.py"), "import os\n")?;
+        let (mut results, mut excluded) = walk_directories(
+            &working_directory,
+            "app".to_string(),
+            &vec![std::ffi::OsString::from("py")],
+            &vec![],
+            &["app/fixtures/**".to_string()],
+            SourceConfig::Main,
+            None,
+            |_entry, relative_path, _| async move { Ok(relative_path) },
+        )
+        .await?;
+        results.sort();
+        excluded.sort();
+        assert_eq!(results, vec![PathBuf::from("app/main.py")]);
+        assert_eq!(excluded, vec![PathBuf::from("app/fixtures/gen.py")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_exclude_globs_stay_within_a_directory() -> Result<(), Box<dyn std::error::Error>> {
+        let globset = to_exclude_globset(&["src/*.py".to_string()])?;
+        assert!(globset.is_match("src/a.py"));
+        assert!(!globset.is_match("src/sub/b.py"));
+        let globset = to_exclude_globset(&["src/**/*.py".to_string()])?;
+        assert!(globset.is_match("src/sub/b.py"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_leading_comment_block() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("a.py");
+        fs::write(
+            &path,
+            "#!/usr/bin/env python\n\n# bzl_gen_build: ignore\nimport os\n# trailing\n",
+        )?;
+        let header = leading_comment_block(&path).await?;
+        assert_eq!(header, "#!/usr/bin/env python\n\n# bzl_gen_build: ignore\n");
+        assert!(has_ignore_directive(&header));
+        Ok(())
+    }
+
+    #[test]
+    fn test_has_ignore_directive() {
+        assert!(has_ignore_directive(
+            "#!/usr/bin/env python\n\n# bzl_gen_build: ignore\nimport os\n"
+        ));
+        assert!(has_ignore_directive(
+            "// bzl_gen_build: ignore\npackage com.foo;\n"
+        ));
+        assert!(!has_ignore_directive(
+            "import os\n# bzl_gen_build: ignore\n"
+        ));
+        assert!(!has_ignore_directive(
+            "# bzl_gen_build: unref: os\nimport os\n"
+        ));
+    }
 }
@@ -1,7 +1,10 @@
 pub mod build_graph;
 pub mod extract_defrefs;
 pub mod extract_defs;
+pub mod merge_build_file;
 pub mod print_build;
+pub mod repo_mapping;
+pub mod run_summary;
 pub mod sha256_value;
 
 use std::{
@@ -64,12 +67,36 @@ pub struct BuildGraphArgs {
 
     #[clap(long)]
     graph_out: PathBuf,
+
+    /// Write a report of directives that had no effect to this file
+    #[clap(long)]
+    lint_directives: Option<PathBuf>,
 }
 
 #[derive(Debug, Args)]
 pub struct PrintBuildArgs {
     #[clap(long)]
     graph_data: PathBuf,
+
+    /// Write a JSON summary of the BUILD files created, modified, deleted and left untouched to this file
+    #[clap(long)]
+    summary: Option<PathBuf>,
+
+    /// Write the same summary in a human readable form to this file
+    #[clap(long)]
+    summary_text: Option<PathBuf>,
+
+    /// Never delete BUILD files that weren't emitted, even ones bzl_gen_build wrote earlier
+    #[clap(long)]
+    no_delete: bool,
+
+    /// JSON written by `bazel mod dump_repo_mapping ''`, used to replace canonical repository names in labels with apparent ones
+    #[clap(long, conflicts_with = "module_file")]
+    repo_mapping: Option<PathBuf>,
+
+    /// MODULE.bazel to work out apparent repository names from, when there's no --repo-mapping
+    #[clap(long)]
+    module_file: Option<PathBuf>,
 }
 
 #[derive(Parser, Debug)]
@@ -98,6 +125,26 @@ pub struct Opt {
     #[clap(long)]
     overwrite: Option<String>,
 
+    /// merge into existing BUILD files, only updating srcs/deps/runtime_deps of generated targets
+    #[clap(long, conflicts_with_all = ["append", "overwrite"])]
+    merge: bool,
+
+    /// rewrite generated sections even if they were edited by hand since they were generated
+    #[clap(long)]
+    force: bool,
+
+    /// name of the BUILD files to write, by default BUILD.bazel unless the package already has a BUILD file
+    #[clap(long)]
+    build_file_name: Option<String>,
+
+    /// write BUILD files under this directory, mirroring the repo layout, instead of into the repo
+    #[clap(long)]
+    output_root: Option<PathBuf>,
+
+    /// write and delete nothing, fail if any BUILD file is out of date or was edited by hand
+    #[clap(long)]
+    check: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -0,0 +1,841 @@
+use std::ops::Range;
+
+use anyhow::{Context, Result};
+use ast::{Constant, Expr, Ranged, Stmt};
+use bzl_gen_build_python_utilities::PythonProgram;
+use rustpython_parser::ast;
+
+/// Attributes the generator owns on a target that already exists in a BUILD file. Everything
+/// else on the target is left as written.
+pub const OWNED_ATTRIBUTES: [&str; 3] = ["srcs", "deps", "runtime_deps"];
+
+/// Merges generated BUILD content into an existing BUILD file.
+///
+/// Generated targets are matched to existing ones by name, and only the `OWNED_ATTRIBUTES` of
+/// a matching target are replaced. Like Gazelle, an attribute followed by a `# keep` comment is
+/// left alone, and list entries marked `# keep` survive even when the generator no longer
+/// produces them. Targets that don't exist yet are appended, along with any loads they need,
+/// and the `previously_generated` targets the generator no longer produces are removed.
+///
+/// The file is parsed with the same parser as the rest of the generator, comments are found in
+/// the source between the nodes it returns.
+pub fn merge_build_file(
+    existing: &str,
+    generated: &str,
+    previously_generated: &[String],
+) -> Result<String> {
+    let existing = ParsedFile::new(existing).with_context(|| "Parsing the existing BUILD file")?;
+    let generated = ParsedFile::new(generated)?;
+
+    let mut edits: Vec<(Range<usize>, String)> = Vec::default();
+    let mut new_loads: Vec<String> = Vec::default();
+    let mut new_targets: Vec<&str> = Vec::default();
+
+    for call in generated.calls.iter() {
+        if call.function == "load" {
+            new_loads.extend(existing.missing_load(&generated, call));
+            continue;
+        }
+        let name = match call.name() {
+            Some(name) => name,
+            None => continue,
+        };
+        match existing.target(name) {
+            Some(existing_call) => {
+                edits.extend(existing.merge_owned_attributes(existing_call, &generated, call))
+            }
+            None => new_targets.push(&generated.src[call.start..call.end]),
+        }
+    }
+
+    for name in previously_generated.iter() {
+        if generated.target(name).is_none() {
+            if let Some(stale) = existing.target(name) {
+                edits.push((existing.statement_range(stale), String::default()));
+            }
+        }
+    }
+
+    if !new_loads.is_empty() {
+        edits.push(existing.load_insertion(new_loads));
+    }
+
+    // Apply from the end so earlier offsets stay valid, and edits at the same offset in the
+    // order they were made.
+    edits.reverse();
+    edits.sort_by_key(|(range, _)| std::cmp::Reverse(range.start));
+    let mut out = existing.src.to_string();
+    for (range, text) in edits {
+        out.replace_range(range, &text);
+    }
+
+    for target in new_targets {
+        if !out.is_empty() {
+            if !out.ends_with('\n') {
+                out.push('\n');
+            }
+            if !out.ends_with("\n\n") {
+                out.push('\n');
+            }
+        }
+        out.push_str(target);
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// The `OWNED_ATTRIBUTES` of the given targets as the file has them, leaving out what is marked
+/// `# keep`, so that hand edits to what the generator merged in can be detected.
+pub fn owned_attributes_text(src: &str, names: &[String]) -> Result<String> {
+    let parsed = ParsedFile::new(src)?;
+    let mut out = String::default();
+    for name in names.iter() {
+        out.push_str(&format!("{}\n", name));
+        let Some(call) = parsed.target(name) else {
+            continue;
+        };
+        for attr in OWNED_ATTRIBUTES {
+            let Some(item) = call.attribute(attr).filter(|a| a.keep.is_none()) else {
+                continue;
+            };
+            let value = match &item.entries {
+                Some(entries) => entries
+                    .iter()
+                    .filter(|e| e.keep.is_none())
+                    .map(|e| format!("{},", parsed.text(e.value.clone())))
+                    .collect(),
+                None => parsed.text(item.value.clone()).to_string(),
+            };
+            out.push_str(&format!("{} = {}\n", attr, value));
+        }
+    }
+    Ok(out)
+}
+
+// An argument of a call or an entry of a list.
+#[derive(Debug, Clone)]
+struct Item {
+    key: Option<String>,
+    start: usize,
+    value: Range<usize>,
+    // after the trailing comma, or the end of the value when there is none
+    end: usize,
+    has_comma: bool,
+    // the `# keep` comment attached to this item, if any
+    keep: Option<String>,
+    // the value, when it is a string literal
+    string: Option<String>,
+    // the entries of the value, when it is a list literal
+    entries: Option<Vec<Item>>,
+}
+
+// A top-level call such as `load(...)` or `py_library(...)`.
+#[derive(Debug)]
+struct Call {
+    function: String,
+    start: usize,
+    // offset of the opening and closing parenthesis
+    open: usize,
+    close: usize,
+    end: usize,
+    args: Vec<Item>,
+}
+
+impl Call {
+    fn attribute(&self, key: &str) -> Option<&Item> {
+        self.args.iter().find(|a| a.key.as_deref() == Some(key))
+    }
+
+    fn name(&self) -> Option<&str> {
+        self.attribute("name").and_then(|a| a.string.as_deref())
+    }
+}
+
+struct ParsedFile<'a> {
+    src: &'a str,
+    calls: Vec<Call>,
+}
+
+impl<'a> ParsedFile<'a> {
+    fn new(src: &'a str) -> Result<ParsedFile<'a>> {
+        let program = PythonProgram::parse(src, "BUILD")?;
+        let mut calls = Vec::default();
+        for stmt in program.body.iter() {
+            let Stmt::Expr(ast::StmtExpr { value, .. }) = stmt else {
+                continue;
+            };
+            let Expr::Call(call) = value.as_ref() else {
+                continue;
+            };
+            let function = match call.func.as_ref() {
+                Expr::Name(ast::ExprName { id, .. }) => id.to_string(),
+                Expr::Attribute(ast::ExprAttribute { attr, .. }) => attr.to_string(),
+                _ => continue,
+            };
+            let start = offset(call.func.start());
+            let end = offset(call.range.end());
+            let open =
+                offset(call.func.end()) + src[offset(call.func.end())..].find('(').unwrap_or(0);
+            let mut values: Vec<(Option<String>, &Expr)> =
+                call.args.iter().map(|a| (None, a)).collect();
+            values.extend(
+                call.keywords
+                    .iter()
+                    .map(|k| (k.arg.as_ref().map(|a| a.to_string()), &k.value)),
+            );
+            values.sort_by_key(|(_, v)| offset(v.start()));
+            calls.push(Call {
+                function,
+                start,
+                open,
+                close: end - 1,
+                end,
+                args: items(src, open, end - 1, &values),
+            });
+        }
+        Ok(ParsedFile { src, calls })
+    }
+
+    fn text(&self, range: Range<usize>) -> &'a str {
+        &self.src[range]
+    }
+
+    fn value_text(&self, item: &Item) -> String {
+        item.string
+            .clone()
+            .unwrap_or_else(|| self.text(item.value.clone()).to_string())
+    }
+
+    fn target(&self, name: &str) -> Option<&Call> {
+        self.calls
+            .iter()
+            .find(|c| c.function != "load" && c.name() == Some(name))
+    }
+
+    // Normalized symbols loaded from `from`, by the existing load statements.
+    fn loaded_symbols(&self, from: &str) -> Vec<String> {
+        self.calls
+            .iter()
+            .filter(|c| c.function == "load")
+            .filter(|c| c.args.first().and_then(|a| a.string.as_deref()) == Some(from))
+            .flat_map(|c| c.args.iter().skip(1).map(|a| self.load_symbol(a)))
+            .collect()
+    }
+
+    fn load_symbol(&self, item: &Item) -> String {
+        let value = self.value_text(item);
+        match &item.key {
+            Some(key) => format!("{}={}", key, value),
+            None => value,
+        }
+    }
+
+    // A load statement for the symbols of `load` this file doesn't load yet.
+    fn missing_load(&self, generated: &ParsedFile, load: &Call) -> Option<String> {
+        let from = load.args.first()?;
+        let existing = self.loaded_symbols(from.string.as_deref()?);
+        let missing: Vec<&str> = load
+            .args
+            .iter()
+            .skip(1)
+            .filter(|a| !existing.contains(&generated.load_symbol(a)))
+            .map(|a| generated.text(a.start..a.value.end))
+            .collect();
+        if missing.is_empty() {
+            None
+        } else {
+            Some(format!(
+                "load({}, {})",
+                generated.text(from.value.clone()),
+                missing.join(", ")
+            ))
+        }
+    }
+
+    // New loads go after the last existing load, or after any leading comments.
+    fn load_insertion(&self, loads: Vec<String>) -> (Range<usize>, String) {
+        if let Some(last_load) = self.calls.iter().rev().find(|c| c.function == "load") {
+            return (
+                last_load.end..last_load.end,
+                format!("\n{}", loads.join("\n")),
+            );
+        }
+        let mut pos = 0;
+        for line in self.src.split_inclusive('\n') {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                pos += line.len();
+            } else {
+                break;
+            }
+        }
+        let mut text = String::default();
+        if pos > 0 && !self.src[..pos].ends_with('\n') {
+            text.push('\n');
+        }
+        text.push_str(&loads.join("\n"));
+        text.push('\n');
+        if pos < self.src.len() {
+            text.push('\n');
+        }
+        (pos..pos, text)
+    }
+
+    // The lines of a top-level call, along with a blank line that would be left doubled.
+    fn statement_range(&self, call: &Call) -> Range<usize> {
+        let start = line_start(self.src, call.start);
+        let mut end = self.src[call.end..]
+            .find('\n')
+            .map(|idx| call.end + idx + 1)
+            .unwrap_or(self.src.len());
+        let blank_before = start == 0 || self.src[..start].ends_with("\n\n");
+        if blank_before && self.src[end..].starts_with('\n') {
+            end += 1;
+        }
+        start..end
+    }
+
+    fn merge_owned_attributes(
+        &self,
+        existing: &Call,
+        generated: &ParsedFile,
+        call: &Call,
+    ) -> Vec<(Range<usize>, String)> {
+        // A call written on one line is laid out again one attribute per line when attributes
+        // have to be added to it.
+        let compact = existing
+            .args
+            .first()
+            .map(|a| line_start(self.src, a.start) <= existing.start)
+            .unwrap_or(true);
+        let call_indent = line_indent(self.src, existing.start);
+        let attr_indent = format!("{}    ", call_indent);
+        let indent_of = |item: &Item| -> String {
+            if compact {
+                attr_indent.clone()
+            } else {
+                line_indent(self.src, item.start).to_string()
+            }
+        };
+
+        let mut values: Vec<Option<String>> = existing
+            .args
+            .iter()
+            .map(|a| Some(self.text(a.value.clone()).to_string()))
+            .collect();
+        let mut inserted: Vec<String> = Vec::default();
+
+        for attr in OWNED_ATTRIBUTES {
+            let existing_idx = existing
+                .args
+                .iter()
+                .position(|a| a.key.as_deref() == Some(attr));
+            let generated_attr = call.attribute(attr);
+            match (
+                existing_idx.map(|idx| (idx, &existing.args[idx])),
+                generated_attr,
+            ) {
+                (Some((_, e)), _) if e.keep.is_some() => (),
+                (Some((idx, e)), Some(g)) => {
+                    values[idx] = Some(self.merged_value(e, generated, g, &indent_of(e)));
+                }
+                (Some((idx, e)), None) => {
+                    let kept = self.kept_entries(e);
+                    values[idx] = if kept.is_empty() {
+                        None
+                    } else {
+                        Some(render_list(kept, &indent_of(e)))
+                    };
+                }
+                (None, Some(g)) => {
+                    inserted.push(format!("{} = {}", attr, generated.text(g.value.clone())))
+                }
+                (None, None) => (),
+            }
+        }
+
+        if compact && !inserted.is_empty() {
+            let mut text = "\n".to_string();
+            for (item, value) in existing.args.iter().zip(values.iter()) {
+                let Some(value) = value else {
+                    continue;
+                };
+                text.push_str(&attr_indent);
+                if let Some(key) = &item.key {
+                    text.push_str(&format!("{} = ", key));
+                }
+                text.push_str(&format!("{},", value));
+                if let Some(comment) = &item.keep {
+                    text.push_str(&format!("  {}", comment));
+                }
+                text.push('\n');
+            }
+            for attr in inserted {
+                text.push_str(&format!("{}{},\n", attr_indent, attr));
+            }
+            text.push_str(call_indent);
+            return vec![(existing.open + 1..existing.close, text)];
+        }
+
+        let mut edits: Vec<(Range<usize>, String)> = Vec::default();
+        for (item, value) in existing.args.iter().zip(values.iter()) {
+            match value {
+                Some(value) if value.as_str() != self.text(item.value.clone()) => {
+                    edits.push((item.value.clone(), value.clone()))
+                }
+                Some(_) => (),
+                None => edits.push((self.removal_range(item), String::default())),
+            }
+        }
+
+        if !inserted.is_empty() {
+            let last_remaining = existing
+                .args
+                .iter()
+                .zip(values.iter())
+                .rev()
+                .find(|(_, v)| v.is_some());
+            if let Some((last, _)) = last_remaining {
+                if !last.has_comma {
+                    edits.push((last.end..last.end, ",".to_string()));
+                }
+            }
+            let text: String = inserted
+                .iter()
+                .map(|attr| format!("{}{},\n", attr_indent, attr))
+                .collect();
+            let close_line = line_start(self.src, existing.close);
+            if self.src[close_line..existing.close].trim().is_empty() {
+                edits.push((close_line..close_line, text));
+            } else {
+                edits.push((existing.close..existing.close, format!("\n{}", text)));
+            }
+        }
+        edits
+    }
+
+    // Entries of a list attribute marked `# keep`, with their comment.
+    fn kept_entries(&self, item: &Item) -> Vec<(String, Option<String>)> {
+        item.entries
+            .iter()
+            .flatten()
+            .filter(|e| e.keep.is_some())
+            .map(|e| (self.text(e.value.clone()).to_string(), e.keep.clone()))
+            .collect()
+    }
+
+    fn merged_value(
+        &self,
+        existing: &Item,
+        generated: &ParsedFile,
+        item: &Item,
+        indent: &str,
+    ) -> String {
+        let generated_value = generated.text(item.value.clone());
+        let mut kept = self.kept_entries(existing);
+        if kept.is_empty() {
+            return generated_value.to_string();
+        }
+        match &item.entries {
+            Some(entries) => {
+                let mut merged: Vec<(String, Option<String>)> = Vec::default();
+                for entry in entries {
+                    let text = generated.text(entry.value.clone()).to_string();
+                    let keep = match kept.iter().position(|(k, _)| k == &text) {
+                        Some(idx) => kept.remove(idx).1,
+                        None => None,
+                    };
+                    merged.push((text, keep));
+                }
+                merged.extend(kept);
+                render_list(merged, indent)
+            }
+            None => format!("{} + {}", render_list(kept, indent), generated_value),
+        }
+    }
+
+    // The whole line of an argument on its own line, otherwise just the argument.
+    fn removal_range(&self, item: &Item) -> Range<usize> {
+        let start = line_start(self.src, item.start);
+        if !self.src[start..item.start].trim().is_empty() {
+            return item.start..item.end;
+        }
+        let line_end = self.src[item.end..]
+            .find('\n')
+            .map(|idx| item.end + idx + 1)
+            .unwrap_or(self.src.len());
+        let rest = self.src[item.end..line_end].trim();
+        if rest.is_empty() || rest.starts_with('#') {
+            start..line_end
+        } else {
+            start..item.end
+        }
+    }
+}
+
+fn offset(size: ast::TextSize) -> usize {
+    size.into()
+}
+
+// The items between the brackets at `open` and `close`, from their values in source order. The
+// source between two values only holds the comma and comments: a comment on the same line as a
+// value belongs to it, other comments to the next one.
+fn items(src: &str, open: usize, close: usize, values: &[(Option<String>, &Expr)]) -> Vec<Item> {
+    let starts: Vec<usize> = values
+        .iter()
+        .map(|(key, value)| {
+            let value_start = offset(value.start());
+            match key {
+                Some(key) => src[..value_start]
+                    .rfind(key.as_str())
+                    .unwrap_or(value_start),
+                // **kwargs
+                None if src[open..value_start].trim_end().ends_with("**") => {
+                    src[..value_start].rfind("**").unwrap_or(value_start)
+                }
+                None => value_start,
+            }
+        })
+        .collect();
+
+    let mut out: Vec<Item> = Vec::default();
+    let mut leading_keep = keep_comments(src, open + 1, starts.first().copied().unwrap_or(close))
+        .into_iter()
+        .last()
+        .map(|(comment, _)| comment);
+    for (idx, (key, value)) in values.iter().enumerate() {
+        let value_range = offset(value.start())..offset(value.end());
+        let next = starts.get(idx + 1).copied().unwrap_or(close);
+        let comma = src[value_range.end..next]
+            .find(',')
+            .filter(|c| !src[value_range.end..value_range.end + c].contains('#'))
+            .map(|c| value_range.end + c + 1);
+        let mut keep = leading_keep.take();
+        for (comment, same_line) in keep_comments(src, value_range.end, next) {
+            if same_line {
+                keep = Some(comment);
+            } else {
+                leading_keep = Some(comment);
+            }
+        }
+        let string = match value {
+            Expr::Constant(ast::ExprConstant {
+                value: Constant::Str(s),
+                ..
+            }) => Some(s.to_string()),
+            _ => None,
+        };
+        let entries = match value {
+            Expr::List(ast::ExprList { elts, .. }) => {
+                let elts: Vec<(Option<String>, &Expr)> = elts.iter().map(|e| (None, e)).collect();
+                Some(items(src, value_range.start, value_range.end - 1, &elts))
+            }
+            _ => None,
+        };
+        out.push(Item {
+            key: key.clone(),
+            start: starts[idx],
+            end: comma.unwrap_or(value_range.end),
+            has_comma: comma.is_some(),
+            value: value_range,
+            keep,
+            string,
+            entries,
+        });
+    }
+    out
+}
+
+// The `# keep` comments between `from` and `to`, and whether each is on the line of `from`.
+fn keep_comments(src: &str, from: usize, to: usize) -> Vec<(String, bool)> {
+    let mut comments = Vec::default();
+    let mut same_line = true;
+    let mut pos = from;
+    while pos < to {
+        let rest = &src[pos..to];
+        match rest.find(['#', '\n']) {
+            Some(idx) if rest[idx..].starts_with('\n') => {
+                same_line = false;
+                pos += idx + 1;
+            }
+            Some(idx) => {
+                let comment_end = rest[idx..]
+                    .find('\n')
+                    .map(|e| idx + e)
+                    .unwrap_or(rest.len());
+                let comment = &rest[idx..comment_end];
+                if is_keep_comment(comment) {
+                    comments.push((comment.trim_end().to_string(), same_line));
+                }
+                pos += comment_end;
+            }
+            None => break,
+        }
+    }
+    comments
+}
+
+fn is_keep_comment(comment: &str) -> bool {
+    let body = comment.trim_start_matches('#').trim();
+    body == "keep" || body.starts_with("keep:")
+}
+
+fn line_start(src: &str, offset: usize) -> usize {
+    src[..offset].rfind('\n').map(|idx| idx + 1).unwrap_or(0)
+}
+
+fn line_indent(src: &str, offset: usize) -> &str {
+    let start = line_start(src, offset);
+    let line = &src[start..offset];
+    &line[..line.len() - line.trim_start().len()]
+}
+
+fn render_list(entries: Vec<(String, Option<String>)>, indent: &str) -> String {
+    if entries.is_empty() {
+        return "[]".to_string();
+    }
+    let mut out = "[\n".to_string();
+    for (entry, keep) in entries {
+        out.push_str(&format!("{}    {},", indent, entry));
+        if let Some(comment) = keep {
+            out.push_str(&format!("  {}", comment));
+        }
+        out.push('\n');
+    }
+    out.push_str(indent);
+    out.push(']');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_updates_owned_attributes() {
+        let existing = r#"# Hand written header
+load("@rules_python//python:defs.bzl", "py_library")
+
+py_library(
+    name = "foo",
+    srcs = ["foo.py"],
+    tags = ["manual"],
+    visibility = ["//visibility:public"],
+    deps = [
+        "//old:dep",
+        "//extra:dep",  # keep
+    ],
+)
+
+genrule(
+    name = "hand_written",
+    outs = ["out.txt"],
+    cmd = "touch $@",
+)
+"#;
+        let generated = r#"load("@rules_python//python:defs.bzl", "py_binary", "py_library")
+
+py_library(
+    name = "foo",
+    srcs = [
+        "foo.py",
+        "foo_util.py",
+    ],
+    visibility = ["//visibility:public"],
+    deps = [
+        "//extra:dep",
+        "//new:dep",
+    ],
+)
+
+py_binary(
+    name = "bar",
+    srcs = ["bar.py"],
+    visibility = ["//visibility:public"],
+    deps = [":foo"],
+)
+"#;
+        let expected = r#"# Hand written header
+load("@rules_python//python:defs.bzl", "py_library")
+load("@rules_python//python:defs.bzl", "py_binary")
+
+py_library(
+    name = "foo",
+    srcs = [
+        "foo.py",
+        "foo_util.py",
+    ],
+    tags = ["manual"],
+    visibility = ["//visibility:public"],
+    deps = [
+        "//extra:dep",  # keep
+        "//new:dep",
+    ],
+)
+
+genrule(
+    name = "hand_written",
+    outs = ["out.txt"],
+    cmd = "touch $@",
+)
+
+py_binary(
+    name = "bar",
+    srcs = ["bar.py"],
+    visibility = ["//visibility:public"],
+    deps = [":foo"],
+)
+"#;
+        assert_eq!(
+            merge_build_file(existing, generated, &[]).unwrap(),
+            expected
+        );
+        // Merging again changes nothing.
+        assert_eq!(
+            merge_build_file(expected, generated, &[]).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn merge_honors_keep_markers() {
+        let existing = r#"java_library(
+    name = "foo",
+    srcs = glob(["*.java"]),  # keep
+    jvm_flags = ["-Xmx1g"],
+    runtime_deps = [
+        "//gone:dep",
+        # keep
+        "//kept:dep",
+    ],
+    deps = ["//old:dep"]
+)
+"#;
+        let generated = r#"java_library(
+    name = "foo",
+    srcs = ["Foo.java"],
+    exports = ["//x"],
+    deps = [
+        "//a",
+        "//b",
+    ],
+)
+"#;
+        let expected = r#"java_library(
+    name = "foo",
+    srcs = glob(["*.java"]),  # keep
+    jvm_flags = ["-Xmx1g"],
+    runtime_deps = [
+        "//kept:dep",  # keep
+    ],
+    deps = [
+        "//a",
+        "//b",
+    ]
+)
+"#;
+        assert_eq!(
+            merge_build_file(existing, generated, &[]).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn merge_adds_missing_attributes() {
+        let existing = "py_library(name = \"foo\", tags = [\"manual\"])\n";
+        let generated = r#"py_library(
+    name = "foo",
+    srcs = ["foo.py"],
+    deps = ["//a"],
+)
+"#;
+        let expected = r#"py_library(
+    name = "foo",
+    tags = ["manual"],
+    srcs = ["foo.py"],
+    deps = ["//a"],
+)
+"#;
+        assert_eq!(
+            merge_build_file(existing, generated, &[]).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn merge_into_empty_file() {
+        let generated = r#"load("@rules_python//python:defs.bzl", "py_library")
+
+py_library(
+    name = "foo",
+    srcs = ["foo.py"],
+)
+"#;
+        assert_eq!(merge_build_file("", generated, &[]).unwrap(), generated);
+    }
+
+    #[test]
+    fn merge_removes_targets_no_longer_generated() {
+        let existing = r#"load("@rules_python//python:defs.bzl", "py_library")
+
+py_library(
+    name = "gone",
+    srcs = ["gone.py"],
+)
+
+genrule(
+    name = "gone_too",
+    outs = ["out.txt"],
+    cmd = "touch $@",
+)
+
+py_library(
+    name = "foo",
+    srcs = ["foo.py"],
+)
+"#;
+        let generated = r#"py_library(
+    name = "foo",
+    srcs = ["foo.py"],
+)
+"#;
+        let expected = r#"load("@rules_python//python:defs.bzl", "py_library")
+
+genrule(
+    name = "gone_too",
+    outs = ["out.txt"],
+    cmd = "touch $@",
+)
+
+py_library(
+    name = "foo",
+    srcs = ["foo.py"],
+)
+"#;
+        // Only targets the generator produced before are removed.
+        let previously_generated = vec!["gone".to_string(), "foo".to_string()];
+        assert_eq!(
+            merge_build_file(existing, generated, &previously_generated).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn owned_attributes_leave_out_kept_values() {
+        let src = r#"py_library(
+    name = "foo",
+    srcs = glob(["*.py"]),  # keep
+    tags = ["manual"],
+    deps = [
+        "//a",
+        "//b",  # keep
+    ],
+)
+"#;
+        let names = vec!["foo".to_string(), "missing".to_string()];
+        assert_eq!(
+            owned_attributes_text(src, &names).unwrap(),
+            "foo\ndeps = \"//a\",\nmissing\n"
+        );
+    }
+}
@@ -1,30 +1,41 @@
 use std::{
     cmp::Ordering,
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     path::{Path, PathBuf},
     sync::Arc,
+    time::Instant,
 };
 
 use crate::{
     async_read_json_file,
     build_graph::{GraphMapping, GraphNode, GraphNodeMetadata},
     extract_defrefs::{self, path_is_match},
-    to_directory, Opt, PrintBuildArgs,
+    merge_build_file::{merge_build_file, owned_attributes_text},
+    repo_mapping::RepoMapping,
+    run_summary::{diff_targets, FileChange, RunSummary},
+    sha256_value::Sha256Value,
+    to_directory, write_json_file, Opt, PrintBuildArgs,
 };
 use anyhow::{anyhow, Context, Result};
 use ast::{Expr, Stmt};
-use bzl_gen_build_python_utilities::{ast_builder, PythonProgram};
+use bzl_gen_build_python_utilities::{ast_builder, starlark::starlark_calls, PythonProgram};
 use bzl_gen_build_shared_types::{
     build_config::{SourceConfig, TargetNameStrategy, WriteMode},
+    directive::{AttrScalarConfig, AttrValue, ManualRefConfig},
+    entity_pattern::EntityPattern,
+    label_rewrite::LabelRewriter,
     module_config::{maybe_add_buildifier_disable, ModuleConfig},
+    template::{Template, TemplateVariables},
     *,
 };
 use futures::{stream, StreamExt};
+use globset::{Glob, GlobSet, GlobSetBuilder};
 use ignore::WalkBuilder;
+use log::warn;
 use rustpython_parser::ast;
 
 use futures::Future;
-use tokio::{io::AsyncWriteExt, sync::Semaphore};
+use tokio::sync::Semaphore;
 
 lazy_static::lazy_static! {
     static ref BUILD_BAZEL: std::ffi::OsString = std::ffi::OsString::from("BUILD.bazel");
@@ -130,6 +141,10 @@ impl MaybeLabel {
 
                 // Handle workspace
                 if let Some(ws) = workspace {
+                    // @foo//:foo is just @foo
+                    if package_name.is_empty() && rule_name == ws && is_short_repo_name(&ws) {
+                        return format!("@{}", ws);
+                    }
                     label.push('@');
                     label.push_str(&ws);
                 }
@@ -160,41 +175,58 @@ impl MaybeLabel {
     fn to_expr(self) -> Expr {
         ast_builder::with_constant_str(self.to_string())
     }
+
+    // Labels in the main repository pointing into `package` become :name, whether they are
+    // written //pkg:name, @//pkg:name or @@//pkg:name.
+    fn relative_to(self, package: &str) -> MaybeLabel {
+        match self {
+            MaybeLabel::Label {
+                workspace,
+                package_name,
+                rule_name,
+            } if matches!(workspace.as_deref(), None | Some("") | Some("@"))
+                && package_name.join("/") == package =>
+            {
+                MaybeLabel::Relative { rule_name }
+            }
+            other => other,
+        }
+    }
+}
+
+// Repository names buildifier shortens @foo//:foo to @foo for.
+fn is_short_repo_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl MaybeLabel {
+    // Sorts like buildifier sorts string lists: plain strings, then relative labels, then labels
+    // in the main repository, then labels in other repositories. Within each group values are
+    // compared by their parts split on `:` and `.`, e.g. `//a/b:c` sorts before `//a/b/d`.
+    // Lists are sorted with `sort_by_cached_key` so the key is only built once per entry.
+    fn sort_key(&self) -> (u8, Vec<String>, String) {
+        let value = self.clone().to_string();
+        let phase = if value.starts_with(':') {
+            1
+        } else if value.starts_with("//") {
+            2
+        } else if value.starts_with('@') {
+            3
+        } else {
+            0
+        };
+        let split = value
+            .replace(':', ".")
+            .split('.')
+            .map(str::to_string)
+            .collect();
+        (phase, split, value)
+    }
 }
 
 impl Ord for MaybeLabel {
     fn cmp(&self, other: &Self) -> Ordering {
-        match (self, other) {
-            (
-                MaybeLabel::Label {
-                    workspace,
-                    package_name,
-                    rule_name,
-                },
-                MaybeLabel::Label {
-                    workspace: w1,
-                    package_name: p1,
-                    rule_name: r1,
-                },
-            ) => (workspace, package_name, rule_name).cmp(&(w1, p1, r1)),
-            (MaybeLabel::Label { .. }, MaybeLabel::JustString(_)) => Ordering::Greater,
-            (
-                MaybeLabel::Label {
-                    workspace: _,
-                    package_name: _,
-                    rule_name: _,
-                },
-                MaybeLabel::Relative { .. },
-            ) => Ordering::Greater,
-            (MaybeLabel::JustString(_), MaybeLabel::Label { .. }) => Ordering::Less,
-            (MaybeLabel::JustString(s), MaybeLabel::Relative { rule_name }) => s.cmp(rule_name),
-            (MaybeLabel::JustString(a), MaybeLabel::JustString(b)) => a.cmp(b),
-            (MaybeLabel::Relative { .. }, MaybeLabel::Label { .. }) => Ordering::Less,
-            (MaybeLabel::Relative { rule_name }, MaybeLabel::JustString(s)) => rule_name.cmp(s),
-            (MaybeLabel::Relative { rule_name }, MaybeLabel::Relative { rule_name: r1 }) => {
-                rule_name.cmp(r1)
-            }
-        }
+        self.sort_key().cmp(&other.sort_key())
     }
 }
 
@@ -213,6 +245,9 @@ struct TargetEntry {
     pub target_type: Arc<String>,
     pub extra_kv_pairs: Vec<(String, Vec<String>)>,
     pub extra_k_strs: Vec<(String, String)>,
+    pub extra_k_scalars: Vec<(String, AttrValue)>,
+    /// values only added under a condition, by attribute then config_setting label
+    pub extra_kv_selects: Vec<(String, BTreeMap<String, Vec<String>>)>,
 }
 
 impl TargetEntry {
@@ -271,6 +306,38 @@ impl TargetEntry {
         });
     }
 
+    // select({...}) with a trailing empty default, unless one was given explicitly
+    fn select_expr(conditions: &BTreeMap<String, Vec<String>>) -> Expr {
+        const DEFAULT_CONDITION: &str = "//conditions:default";
+        let to_list = |values: &Vec<String>| {
+            let mut normv: Vec<MaybeLabel> = values
+                .iter()
+                .map(|item| MaybeLabel::from_str(item))
+                .collect();
+            normv.sort_by_cached_key(MaybeLabel::sort_key);
+            normv.dedup_by(|a, b| Ord::cmp(&*a, &*b).is_eq());
+            ast_builder::as_py_list(normv.into_iter().map(|d| d.to_expr()).collect())
+        };
+        let mut entries: Vec<(Expr, Expr)> = conditions
+            .iter()
+            .filter(|(c, _)| c.as_str() != DEFAULT_CONDITION)
+            .map(|(c, values)| (ast_builder::with_constant_str(c.clone()), to_list(values)))
+            .collect();
+        let default_values = conditions
+            .get(DEFAULT_CONDITION)
+            .cloned()
+            .unwrap_or_default();
+        entries.push((
+            ast_builder::with_constant_str(DEFAULT_CONDITION.to_string()),
+            to_list(&default_values),
+        ));
+        ast_builder::gen_py_function_call(
+            Arc::new("select".to_string()),
+            vec![ast_builder::as_py_dict(entries)],
+            Vec::default(),
+        )
+    }
+
     pub fn emit_build_function_call(&self) -> Result<Stmt> {
         let mut kw_args: Vec<(Arc<String>, Expr)> = Default::default();
 
@@ -303,18 +370,46 @@ impl TargetEntry {
             }
             let mut normv: Vec<MaybeLabel> =
                 v.iter().map(|item| MaybeLabel::from_str(item)).collect();
-            normv.sort();
+            normv.sort_by_cached_key(MaybeLabel::sort_key);
+            normv.dedup_by(|a, b| Ord::cmp(&*a, &*b).is_eq());
 
-            kw_args.push((
-                Arc::new(k.clone()),
-                ast_builder::as_py_list(normv.into_iter().map(|d| d.to_expr()).collect()),
-            ));
+            let list = ast_builder::as_py_list(normv.into_iter().map(|d| d.to_expr()).collect());
+            let expr = match self.extra_kv_selects.iter().find(|(sk, _)| sk == k) {
+                Some((_, conditions)) => {
+                    ast_builder::with_binop_add(list, Self::select_expr(conditions))
+                }
+                None => list,
+            };
+
+            kw_args.push((Arc::new(k.clone()), expr));
+        }
+
+        for (k, conditions) in &self.extra_kv_selects {
+            if !self.extra_kv_pairs.iter().any(|(pk, _)| pk == k) {
+                kw_args.push((Arc::new(k.clone()), Self::select_expr(conditions)));
+            }
         }
 
         for (k, v) in &self.extra_k_strs {
             kw_args.push((Arc::new(k.clone()), MaybeLabel::from_str(v).to_expr()));
         }
 
+        for (k, v) in &self.extra_k_scalars {
+            if kw_args.iter().any(|(existing, _)| existing.as_str() == k) {
+                return Err(anyhow!(
+                    "attribute {} is set more than once in: {}",
+                    k,
+                    self.name
+                ));
+            }
+            let expr = match v {
+                AttrValue::String(s) => ast_builder::with_constant_str(s.clone()),
+                AttrValue::Bool(b) => ast_builder::with_constant_bool(*b),
+                AttrValue::Int(i) => ast_builder::with_constant_int(*i),
+            };
+            kw_args.push((Arc::new(k.clone()), expr));
+        }
+
         Self::sort_like_buildifier(&mut kw_args);
         Ok(ast_builder::as_stmt_expr(
             ast_builder::gen_py_function_call(self.target_type.clone(), Vec::default(), kw_args),
@@ -335,18 +430,15 @@ impl SrcType {
     pub fn to_statement(&self) -> Expr {
         match self {
             SrcType::Glob { include, exclude } => {
-                let mut kw_args: Vec<(Arc<String>, Expr)> = Default::default();
-
-                kw_args.push((
-                    Arc::new("include".to_string()),
-                    ast_builder::as_py_list(
-                        include
-                            .iter()
-                            .map(|e| ast_builder::with_constant_str(e.clone()))
-                            .collect(),
-                    ),
-                ));
+                // buildifier's canonical form passes the includes positionally
+                let include = ast_builder::as_py_list(
+                    include
+                        .iter()
+                        .map(|e| ast_builder::with_constant_str(e.clone()))
+                        .collect(),
+                );
 
+                let mut kw_args: Vec<(Arc<String>, Expr)> = Default::default();
                 if !exclude.is_empty() {
                     kw_args.push((
                         Arc::new("exclude".to_string()),
@@ -361,7 +453,7 @@ impl SrcType {
 
                 ast_builder::gen_py_function_call(
                     Arc::new("glob".to_string()),
-                    Vec::default(),
+                    vec![include],
                     kw_args,
                 )
             }
@@ -369,13 +461,39 @@ impl SrcType {
             SrcType::List(files) => {
                 let mut srcs: Vec<MaybeLabel> =
                     files.iter().map(|src| MaybeLabel::from_str(&src)).collect();
-                srcs.sort();
+                srcs.sort_by_cached_key(MaybeLabel::sort_key);
+                srcs.dedup_by(|a, b| Ord::cmp(&*a, &*b).is_eq());
                 ast_builder::as_py_list(srcs.into_iter().map(|e| e.to_expr()).collect())
             }
         }
     }
 }
 
+// The order buildifier puts load statements in: loads from an explicit repository first, then by
+// package, with loads that have no package first, then by file name.
+fn compare_load_labels(a: &str, b: &str) -> Ordering {
+    let a_repo = a.starts_with('@');
+    let b_repo = b.starts_with('@');
+    if a_repo != b_repo {
+        return b_repo.cmp(&a_repo);
+    }
+    let split = |label: &str| -> (String, String) {
+        match label.split_once(':') {
+            Some((package, file)) => (package.to_string(), file.to_string()),
+            None => (String::default(), label.to_string()),
+        }
+    };
+    let (a_package, a_file) = split(a);
+    let (b_package, b_file) = split(b);
+    if a_package == b_package {
+        a_file.cmp(&b_file)
+    } else if a_package.is_empty() || b_package.is_empty() {
+        b_package.is_empty().cmp(&a_package.is_empty())
+    } else {
+        a_package.cmp(&b_package)
+    }
+}
+
 #[derive(Debug, Default)]
 struct TargetEntries {
     pub entries: Vec<TargetEntry>,
@@ -408,16 +526,7 @@ impl TargetEntries {
             Some(t) => format!("BZL_GEN_BUILD_{}_GENERATED_CODE", t),
             None => "BZL_GEN_BUILD_GENERATED_CODE".to_string(),
         };
-        Ok(format!(
-            "# ---- BEGIN {} ---- no_hash
-
-{}
-
-# ---- END {} ---- no_hash
-
-",
-            marker, &program, marker
-        ))
+        Ok(generated_section(&marker, &program.to_string()))
     }
 
     /// Emits two blocks for tagged overwrite: load block (BZL_GEN_BUILD_LOAD_<tag>_GENERATED_CODE)
@@ -425,27 +534,9 @@ impl TargetEntries {
     pub fn emit_build_file_tagged(&self, tag: &str) -> Result<(String, String)> {
         let (load_program, targets_program) = self.to_ast_split()?;
         let load_marker = format!("BZL_GEN_BUILD_LOAD_{}_GENERATED_CODE", tag);
-        let load_block = format!(
-            "# ---- BEGIN {} ---- no_hash
-
-{}
-
-# ---- END {} ---- no_hash
-
-",
-            load_marker, &load_program, load_marker
-        );
+        let load_block = generated_section(&load_marker, &load_program.to_string());
         let targets_marker = format!("BZL_GEN_BUILD_{}_GENERATED_CODE", tag);
-        let targets_block = format!(
-            "# ---- BEGIN {} ---- no_hash
-
-{}
-
-# ---- END {} ---- no_hash
-
-",
-            targets_marker, &targets_program, targets_marker
-        );
+        let targets_block = generated_section(&targets_marker, &targets_program.to_string());
         Ok((load_block, targets_block))
     }
 
@@ -472,7 +563,7 @@ impl TargetEntries {
 
         let mut all_load_statements: Vec<(Arc<String>, Vec<Arc<String>>)> =
             all_load_statements.into_iter().collect();
-        all_load_statements.sort();
+        all_load_statements.sort_by(|(a, _), (b, _)| compare_load_labels(a, b));
 
         for (load_from, load_v) in all_load_statements {
             load_stmts.push(TargetEntries::load_statement(load_from, load_v));
@@ -505,21 +596,34 @@ impl TargetEntries {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn generate_targets<F, R>(
     opt: &'static Opt,
     project_conf: &'static ProjectConf,
+    configuration: Option<&str>,
     source_conf: SourceConfig,
     graph_nodes: &Vec<GraphNode>,
     element: &String,
+    excluded_files: &[String],
+    target_names: &HashMap<String, String>,
+    name_strategies: &HashMap<String, (&'static str, &'static TargetNameStrategy)>,
+    label_rules: &LabelRules,
     emitted_files: &mut Vec<PathBuf>,
+    file_changes: &mut Vec<FileChange>,
+    banned: &mut BannedUses,
     on_child: F,
 ) -> Result<(TargetEntries, Option<&'static ModuleConfig>)>
 where
-    F: Fn(PathBuf, TargetEntries, bool) -> R,
-    R: Future<Output = Result<i32>> + Send + 'static,
+    F: Fn(PathBuf, TargetEntries, &'static ModuleConfig) -> R,
+    R: Future<Output = Result<Option<FileChange>>> + Send + 'static,
 {
     let mut module_config: Option<&ModuleConfig> = None;
-    for (_k, v) in project_conf.configurations.iter() {
+    // Nodes of older graphs don't know their configuration, the roots have to tell.
+    for (_k, v) in project_conf
+        .configurations
+        .iter()
+        .filter(|(k, _)| configuration.is_none() || configuration == Some(k.as_str()))
+    {
         let paths = if source_conf == SourceConfig::Main {
             v.main_roots.clone()
         } else {
@@ -559,6 +663,26 @@ where
     let mut t: TargetEntries = Default::default();
     let test_globs = &module_config.test_globs;
     let globset = extract_defrefs::to_globset(test_globs)?;
+    let test_attributes = module_config
+        .test_attributes
+        .iter()
+        .map(|a| {
+            let glob = globset::Glob::new(&a.glob)?.compile_matcher();
+            let directives = Directive::from_strings(&a.attributes)
+                .with_context(|| format!("Parsing test_attributes for {}", a.glob))?;
+            if let Some(d) = directives
+                .iter()
+                .find(|d| !matches!(d, Directive::AttrStringList(_) | Directive::AttrScalar(_)))
+            {
+                return Err(anyhow!(
+                    "Only attr directives can be used in test_attributes, found {:?} for {}",
+                    d,
+                    a.glob
+                ));
+            }
+            Ok((glob, directives))
+        })
+        .collect::<Result<Vec<_>>>()?;
     for graph_node in graph_nodes {
         let node_file = opt.working_directory.join(&graph_node.node_label);
         let node_file_name = to_file_name(&node_file);
@@ -567,6 +691,9 @@ where
             .strip_prefix(opt.working_directory.as_path())?
             .to_path_buf();
         let mut extra_kv_pairs: HashMap<String, Vec<String>> = HashMap::default();
+        let mut extra_scalars: BTreeMap<String, AttrValue> = BTreeMap::default();
+        let mut extra_kv_selects: BTreeMap<String, BTreeMap<String, Vec<String>>> =
+            BTreeMap::default();
         let (build_config, use_rglob) = if source_conf == SourceConfig::Test {
             (&module_config.build_config.test, !opt.no_aggregate_source)
         } else {
@@ -582,24 +709,50 @@ where
             ));
         };
 
-        let target_name_strategy = build_config.target_name_strategy;
-        let target_name = if !opt.no_aggregate_source {
+        let target_name_strategy = &build_config.target_name_strategy;
+        let target_name = if let Some(name) = target_names.get(&graph_node.node_label) {
+            name.clone()
+        } else if !opt.no_aggregate_source {
             base_name.clone()
         } else {
             to_name_from_file_name(&node_file_name, target_name_strategy)?
         };
 
+        #[allow(clippy::too_many_arguments)]
         fn add_non_empty(
             opt: &'static Opt,
+            project_conf: &'static ProjectConf,
             key: &str,
             labels: &Vec<String>,
             extra_kv_pairs: &mut HashMap<String, Vec<String>>,
-            target_name_strategy: TargetNameStrategy,
+            configuration: Option<&str>,
+            target_name_strategy: &TargetNameStrategy,
+            target_names: &HashMap<String, String>,
+            name_strategies: &HashMap<String, (&'static str, &'static TargetNameStrategy)>,
         ) {
             if !labels.is_empty() {
                 let vals = labels
                     .iter()
-                    .map(|e| to_label(&opt, e, target_name_strategy))
+                    .map(|e| match name_strategies.get(e) {
+                        // Targets of other configurations are named the way theirs says, and
+                        // may have a secondary target for this one to depend on.
+                        Some((c, strategy)) if Some(*c) != configuration => {
+                            let label = to_label(opt, e, strategy, target_names);
+                            let secondary = configuration.and_then(|configuration| {
+                                project_conf
+                                    .configurations
+                                    .get(*c)?
+                                    .build_config
+                                    .secondary_rule_for
+                                    .get(configuration)
+                            });
+                            match secondary {
+                                Some(secondary) => secondary_label(&label, secondary),
+                                None => label,
+                            }
+                        }
+                        _ => to_label(&opt, e, target_name_strategy, target_names),
+                    })
                     .collect();
                 extra_kv_pairs.insert(key.to_string(), vals);
             }
@@ -607,23 +760,27 @@ where
 
         add_non_empty(
             opt,
+            project_conf,
             "deps",
             &graph_node.dependencies,
             &mut extra_kv_pairs,
+            graph_node.configuration.as_deref(),
             target_name_strategy,
+            target_names,
+            name_strategies,
         );
         add_non_empty(
             opt,
+            project_conf,
             "runtime_deps",
             &graph_node.runtime_dependencies,
             &mut extra_kv_pairs,
+            graph_node.configuration.as_deref(),
             target_name_strategy,
+            target_names,
+            name_strategies,
         );
 
-        for (k, lst) in build_config.extra_key_to_list.iter() {
-            append_key_values(&mut extra_kv_pairs, k.clone(), &lst);
-        }
-
         for directive in project_conf
             .path_directives
             .iter()
@@ -637,22 +794,14 @@ where
                             // Other directive types are actioned much earlier in the pipeline.
                             Directive::SrcDirective(_) => (), // no op.
                             Directive::EntityDirective(_) => (), // no op
-                            Directive::ManualRef(manual_ref) => match manual_ref.command {
-                                directive::ManualRefDirective::RuntimeRef => {
-                                    let t = extra_kv_pairs
-                                        .entry("runtime_deps".to_string())
-                                        .or_default();
-                                    t.push(manual_ref.target_value.clone())
-                                }
-                                directive::ManualRefDirective::Ref => {
-                                    let t = extra_kv_pairs.entry("deps".to_string()).or_default();
-                                    t.push(manual_ref.target_value.clone())
-                                }
-                                directive::ManualRefDirective::DataRef => {
-                                    let t = extra_kv_pairs.entry("data".to_string()).or_default();
-                                    t.push(manual_ref.target_value.clone())
-                                }
-                            },
+                            Directive::Ignore => (),          // no op
+                            Directive::NoBinary => (),        // no op
+                            Directive::TargetName(_) => (),   // carried on the node metadata
+                            Directive::ManualRef(manual_ref) => add_manual_ref(
+                                &mut extra_kv_pairs,
+                                &mut extra_kv_selects,
+                                manual_ref,
+                            ),
                             Directive::AttrStringList(attr) => {
                                 append_key_values(
                                     &mut extra_kv_pairs,
@@ -660,10 +809,13 @@ where
                                     &attr.values,
                                 );
                             }
+                            Directive::AttrScalar(attr) => {
+                                insert_attr_scalar(&mut extra_scalars, attr)?;
+                            }
                         }
                     }
                 }
-                Err(err) => return Err(anyhow!("{:#?}", err)),
+                Err(err) => return Err(anyhow!("{:?}", err)),
             }
         }
 
@@ -685,7 +837,10 @@ where
             ));
         };
 
-        let filegroup_target_name = format!("{}_files", target_name);
+        let filegroup_target_name = module_config
+            .build_config
+            .child_filegroup
+            .target_name(&base_name);
 
         let mut parent_include_src = Vec::default();
 
@@ -695,52 +850,92 @@ where
             module_config,
             &target_name,
         )?;
-        apply_manual_refs(&mut extra_kv_pairs, &graph_node.node_metadata);
+        apply_manual_refs(
+            &mut extra_kv_pairs,
+            &mut extra_kv_selects,
+            &graph_node.node_metadata,
+        );
         apply_attr_string_lists(&mut extra_kv_pairs, &graph_node.node_metadata);
-        // before we give extra_kv_pairs away to make the main target,
-        // we need to clone deps here for a later use in secondaries.
-        let deps = extra_kv_pairs.get("deps").cloned().unwrap_or_else(Vec::new);
-        if use_rglob {
-            let target = TargetEntry {
-                name: target_name.clone(),
-                extra_kv_pairs: extra_kv_pairs
-                    .into_iter()
-                    .map(|(k, mut v)| {
-                        v.sort();
-                        v.dedup();
-                        (k, v)
-                    })
-                    .collect(),
-                required_load,
-                visibility: None,
-                srcs: Some(SrcType::Glob {
+        apply_attr_scalars(&mut extra_scalars, &graph_node.node_metadata)?;
+        // where the node's dependencies come from, to report banned labels
+        let mut node_sources: Vec<String> = vec![graph_node.node_label.clone()];
+        node_sources.extend(graph_node.child_nodes.keys().cloned());
+        // (name, file the name comes from, srcs) of each target to generate for the node
+        let mut main_targets: Vec<(String, String, SrcType)> = Vec::default();
+        if use_rglob && module_config.per_file_tests {
+            let (test_files, library_files) = split_test_files(
+                &target_folder,
+                element,
+                primary_extension,
+                excluded_files,
+                &globset,
+            )
+            .await?;
+            if !library_files.is_empty() {
+                // the other sources make up the package library, unless the directory is also a
+                // main root and they already have a library of their own
+                if !module_config
+                    .main_roots
+                    .iter()
+                    .any(|r| element.starts_with(r.as_str()))
+                {
+                    let sources: Vec<String> = library_files
+                        .iter()
+                        .map(|f| format!("{}/{}", element, f))
+                        .collect();
+                    let mut library_kv_pairs = extra_kv_pairs.clone();
+                    label_rules.apply(
+                        &sources,
+                        &mut library_kv_pairs,
+                        &mut BTreeMap::default(),
+                        banned,
+                    );
+                    t.entries.push(test_library_target(
+                        module_config,
+                        &target_name,
+                        library_files,
+                        &library_kv_pairs,
+                        element,
+                    )?);
+                }
+                append_key_values(
+                    &mut extra_kv_pairs,
+                    "deps".to_string(),
+                    &vec![format!("//{}:{}", element, target_name)],
+                );
+            }
+            for file_name in test_files {
+                let name = to_name_from_file_name(&file_name, target_name_strategy)?;
+                if name == target_name {
+                    return Err(anyhow!(
+                        "The test target for {} would have the name of the package library {}",
+                        file_name,
+                        target_name
+                    ));
+                }
+                main_targets.push((name, file_name.clone(), SrcType::List(vec![file_name])));
+            }
+        } else if use_rglob {
+            main_targets.push((
+                target_name.clone(),
+                node_file_name.clone(),
+                SrcType::Glob {
                     include: vec![format!("**/*.{}", primary_extension)],
-                    exclude: Vec::default(),
-                }),
-                target_type: Arc::new(build_config.function_name.clone()),
-                extra_k_strs: Vec::default(),
-            };
-
-            t.entries.push(target);
+                    exclude: relative_excludes(element, excluded_files),
+                },
+            ));
         } else {
             match graph_node.node_type {
                 crate::build_graph::NodeType::Synthetic => {}
                 crate::build_graph::NodeType::RealNode => {
                     if !opt.no_aggregate_source {
                         parent_include_src.push(format!(":{}", filegroup_target_name));
-                        let filegroup_target = TargetEntry {
-                            name: filegroup_target_name.clone(),
-                            extra_kv_pairs: Vec::default(),
-                            required_load: HashMap::default(),
-                            visibility: None,
-                            srcs: Some(SrcType::Glob {
-                                include: vec![format!("**/*.{}", primary_extension)],
-                                exclude: Vec::default(),
-                            }),
-                            target_type: Arc::new("filegroup".to_string()),
-                            extra_k_strs: Vec::default(),
-                        };
-                        t.entries.push(filegroup_target);
+                        t.entries.push(filegroup_target(
+                            module_config,
+                            filegroup_target_name.clone(),
+                            element,
+                            excluded_files,
+                        ));
                     } else {
                         if path_is_match(&relative_path, test_globs, &globset, &source_conf) {
                             parent_include_src.push(format!("{}", node_file_name));
@@ -764,33 +959,33 @@ where
             }
             for (directory, metadatas) in child_nodes.iter() {
                 if let Some(folder_name) = directory.split('/').filter(|e| !e.is_empty()).last() {
-                    parent_include_src.push(format!("//{}:{}_files", directory, folder_name));
-
-                    let filegroup_target = TargetEntry {
-                        name: format!("{}_files", folder_name),
-                        extra_kv_pairs: Vec::default(),
-                        required_load: HashMap::default(),
-                        visibility: None,
-                        srcs: Some(SrcType::Glob {
-                            include: vec![format!("**/*.{}", primary_extension)],
-                            exclude: Vec::default(),
-                        }),
-                        target_type: Arc::new("filegroup".to_string()),
-                        extra_k_strs: Vec::default(),
-                    };
+                    let filegroup_name = module_config
+                        .build_config
+                        .child_filegroup
+                        .target_name(folder_name);
+                    parent_include_src.push(format!("//{}:{}", directory, filegroup_name));
+
                     let mut t = TargetEntries {
-                        entries: vec![filegroup_target],
+                        entries: vec![filegroup_target(
+                            module_config,
+                            filegroup_name,
+                            directory,
+                            excluded_files,
+                        )],
                     };
 
                     for metadata in metadatas {
-                        apply_manual_refs(&mut extra_kv_pairs, metadata);
+                        apply_manual_refs(&mut extra_kv_pairs, &mut extra_kv_selects, metadata);
                         apply_attr_string_lists(&mut extra_kv_pairs, metadata);
+                        apply_attr_scalars(&mut extra_scalars, metadata)?;
                         apply_binaries(&mut t, metadata, module_config, &directory)?;
                     }
 
-                    let sub_target = opt.working_directory.join(directory).join("BUILD.bazel");
-                    emitted_files.push(sub_target.clone());
-                    on_child(sub_target, t, module_config.disable_format).await?;
+                    let sub_target = build_file_path(opt, &opt.working_directory.join(directory));
+                    if let Some(change) = on_child(sub_target.clone(), t, module_config).await? {
+                        emitted_files.push(sub_target);
+                        file_changes.push(change);
+                    }
                 } else {
                     return Err(anyhow!(
                         "Unable to extract folder name for node: {}",
@@ -799,6 +994,59 @@ where
                 }
             }
 
+            main_targets.push((
+                target_name.clone(),
+                node_file_name.clone(),
+                SrcType::List(parent_include_src.clone()),
+            ));
+        }
+
+        for (target_name, node_file_name, srcs) in main_targets {
+            let mut extra_kv_pairs = extra_kv_pairs.clone();
+            let mut extra_scalars = extra_scalars.clone();
+            let mut extra_kv_selects = extra_kv_selects.clone();
+            let srcs_list = match &srcs {
+                SrcType::List(srcs) => srcs.clone(),
+                SrcType::Glob { .. } => Vec::default(),
+            };
+            if use_rglob && module_config.per_file_tests {
+                let relative_file = format!("{}/{}", element, node_file_name);
+                for (glob, directives) in test_attributes.iter() {
+                    if glob.is_match(&relative_file) {
+                        apply_test_attributes(&mut extra_kv_pairs, &mut extra_scalars, directives);
+                    }
+                }
+            }
+
+            let variables = template_variables(
+                &target_name,
+                element,
+                &node_file_name,
+                &srcs_list,
+                &extra_kv_pairs,
+            );
+            for (k, lst) in build_config.extra_key_to_list.iter() {
+                let values = expand_templates(lst, &variables)?;
+                append_key_values(&mut extra_kv_pairs, k.clone(), &values);
+            }
+            let extra_k_strs =
+                expand_value_templates(&build_config.extra_key_to_value, &variables)?;
+            let sources = if use_rglob && module_config.per_file_tests {
+                vec![format!("{}/{}", element, node_file_name)]
+            } else {
+                node_sources.clone()
+            };
+            label_rules.apply(&sources, &mut extra_kv_pairs, &mut extra_kv_selects, banned);
+            relative_labels(element, &mut extra_kv_pairs, &mut extra_kv_selects);
+            // secondaries see the deps the main target ends up with
+            let variables = template_variables(
+                &target_name,
+                element,
+                &node_file_name,
+                &srcs_list,
+                &extra_kv_pairs,
+            );
+
             let target = TargetEntry {
                 name: target_name.clone(),
                 extra_kv_pairs: extra_kv_pairs
@@ -809,32 +1057,35 @@ where
                         (k, v)
                     })
                     .collect(),
-                required_load,
+                required_load: required_load.clone(),
                 visibility: None,
-                srcs: Some(SrcType::List(parent_include_src.clone())),
+                srcs: Some(srcs),
                 target_type: Arc::new(build_config.function_name.clone()),
-                extra_k_strs: Vec::default(),
+                extra_k_strs,
+                extra_k_scalars: extra_scalars.into_iter().collect(),
+                extra_kv_selects: sorted_selects(extra_kv_selects),
             };
 
             t.entries.push(target);
-        }
 
-        apply_secondary_rules(
-            &mut t,
-            module_config,
-            &target_name,
-            &parent_include_src,
-            &deps,
-        );
+            apply_secondary_rules(&mut t, module_config, &target_name, &variables)?;
+        }
     } // end for graph_nodes
 
     fn to_label(
         opt: &'static Opt,
         entry: &str,
-        target_name_strategy: TargetNameStrategy,
+        target_name_strategy: &TargetNameStrategy,
+        target_names: &HashMap<String, String>,
     ) -> String {
-        if entry.starts_with('@') {
+        if entry.starts_with('@') || entry.starts_with("//") {
             entry.to_string()
+        } else if let Some(name) = target_names.get(entry) {
+            if !opt.no_aggregate_source {
+                format!("//{}:{}", entry, name)
+            } else {
+                format!("//{}:{}", to_directory(entry.to_string()), name)
+            }
         } else {
             if !opt.no_aggregate_source {
                 format!("//{}", entry)
@@ -850,19 +1101,179 @@ where
         }
     }
 
+    // The label of the secondary target named `secondary` next to the target of `label`.
+    fn secondary_label(label: &str, secondary: &str) -> String {
+        if label.contains(':') {
+            format!("{}_{}", label, secondary)
+        } else {
+            let name = label.rsplit('/').next().unwrap_or_default();
+            format!("{}:{}_{}", label, name, secondary)
+        }
+    }
+
     fn to_name_from_file_name(
         file_name: &str,
-        target_name_strategy: TargetNameStrategy,
+        target_name_strategy: &TargetNameStrategy,
     ) -> Result<String> {
-        match target_name_strategy {
-            TargetNameStrategy::SourceFileStem => match Path::new(&file_name).file_stem() {
+        fn file_stem(file_name: &str) -> Result<String> {
+            match Path::new(&file_name).file_stem() {
                 Some(s) => Ok(s.to_string_lossy().to_string()),
                 None => Err(anyhow!("can't get file_stem of {}", file_name)),
-            },
+            }
+        }
+        match target_name_strategy {
+            TargetNameStrategy::SourceFileStem => file_stem(file_name),
+            TargetNameStrategy::Template(template) => {
+                Ok(template.replace("{stem}", &file_stem(file_name)?))
+            }
             TargetNameStrategy::Auto => Ok(file_name.replace(".", "_")),
         }
     }
 
+    // The files of the directory with the primary extension, split into the ones matching
+    // test_globs and the others. Subdirectories with sources are packages of their own.
+    async fn split_test_files(
+        target_folder: &Path,
+        element: &str,
+        primary_extension: &str,
+        excluded_files: &[String],
+        globset: &GlobSet,
+    ) -> Result<(Vec<String>, Vec<String>)> {
+        let excluded = relative_excludes(element, excluded_files);
+        let mut test_files = Vec::default();
+        let mut library_files = Vec::default();
+        let mut entries = tokio::fs::read_dir(target_folder)
+            .await
+            .with_context(|| format!("Listing test files in {:?}", target_folder))?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if !entry.file_type().await?.is_file()
+                || path.extension().and_then(|e| e.to_str()) != Some(primary_extension)
+            {
+                continue;
+            }
+            let file_name = to_file_name(&path);
+            if excluded.contains(&file_name) {
+                continue;
+            }
+            // without test_globs every file is a test
+            if globset.is_empty() || globset.is_match(format!("{}/{}", element, file_name)) {
+                test_files.push(file_name);
+            } else {
+                library_files.push(file_name);
+            }
+        }
+        test_files.sort();
+        library_files.sort();
+        Ok((test_files, library_files))
+    }
+
+    // The testonly library of the sources next to the per-file tests, built like a main target.
+    fn test_library_target(
+        module_config: &ModuleConfig,
+        target_name: &str,
+        srcs: Vec<String>,
+        extra_kv_pairs: &HashMap<String, Vec<String>>,
+        element: &str,
+    ) -> Result<TargetEntry> {
+        let build_config = match &module_config.build_config.main {
+            Some(bc) => bc,
+            None => return Err(anyhow!("No main config specified for the test library")),
+        };
+        let mut required_load = HashMap::default();
+        for h in build_config.headers.iter() {
+            required_load.insert(
+                Arc::new(h.load_from.clone()),
+                vec![Arc::new(h.load_value.clone())],
+            );
+        }
+        let mut extra_kv_pairs: HashMap<String, Vec<String>> = extra_kv_pairs
+            .iter()
+            .filter(|(k, _)| *k == "deps" || *k == "runtime_deps")
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        relative_labels(element, &mut extra_kv_pairs, &mut BTreeMap::default());
+        Ok(TargetEntry {
+            name: target_name.to_string(),
+            extra_kv_pairs: extra_kv_pairs
+                .into_iter()
+                .map(|(k, mut v)| {
+                    v.sort();
+                    v.dedup();
+                    (k, v)
+                })
+                .collect(),
+            required_load,
+            visibility: None,
+            srcs: Some(SrcType::List(srcs)),
+            target_type: Arc::new(build_config.function_name.clone()),
+            extra_k_strs: Vec::default(),
+            extra_k_scalars: vec![("testonly".to_string(), AttrValue::Bool(true))],
+            extra_kv_selects: Vec::default(),
+        })
+    }
+
+    // test_attributes of a file win over the attributes set for the whole directory, only attr
+    // directives are allowed there
+    fn apply_test_attributes(
+        extra_kv_pairs: &mut HashMap<String, Vec<String>>,
+        extra_scalars: &mut BTreeMap<String, AttrValue>,
+        directives: &[Directive],
+    ) {
+        for directive in directives {
+            match directive {
+                Directive::AttrStringList(attr) => {
+                    append_key_values(extra_kv_pairs, attr.attr_name.clone(), &attr.values)
+                }
+                Directive::AttrScalar(attr) => {
+                    extra_scalars.insert(attr.attr_name.clone(), attr.value.clone());
+                }
+                _ => (),
+            }
+        }
+    }
+
+    // The sources of a directory for its parent target, see ChildFilegroupConfig.
+    fn filegroup_target(
+        module_config: &ModuleConfig,
+        name: String,
+        directory: &str,
+        excluded_files: &[String],
+    ) -> TargetEntry {
+        let config = &module_config.build_config.child_filegroup;
+        let mut required_load = HashMap::default();
+        for h in config.headers.iter() {
+            required_load.insert(
+                Arc::new(h.load_from.clone()),
+                vec![Arc::new(h.load_value.clone())],
+            );
+        }
+        TargetEntry {
+            name,
+            extra_kv_pairs: Vec::default(),
+            required_load,
+            visibility: None,
+            srcs: Some(SrcType::Glob {
+                include: config.globs(&module_config.file_extensions),
+                exclude: relative_excludes(directory, excluded_files),
+            }),
+            target_type: Arc::new(config.function_name.clone()),
+            extra_k_strs: Vec::default(),
+            extra_k_scalars: Vec::default(),
+            extra_kv_selects: Vec::default(),
+        }
+    }
+
+    // Files excluded from extraction that live under `directory`, relative to it.
+    fn relative_excludes(directory: &str, excluded_files: &[String]) -> Vec<String> {
+        let prefix = format!("{}/", directory);
+        excluded_files
+            .iter()
+            .filter_map(|f| f.strip_prefix(prefix.as_str()))
+            .map(|f| f.to_string())
+            .collect()
+    }
+
     fn to_file_name(path: &Path) -> String {
         path.file_name().unwrap().to_str().unwrap().to_string()
     }
@@ -876,7 +1287,24 @@ where
         if !node_metadata.binary_refs.is_empty() {
             let build_config = match &module_config.build_config.binary_application {
                 Some(bc) => bc,
-                None => return Err(anyhow!("No binary config specified")),
+                // Binaries asked for with a directive need one, the ones the extractor adds
+                // by itself, e.g. for Python __main__ blocks, are left out
+                None if node_metadata.binary_refs.iter().any(|b| !b.inferred) => {
+                    return Err(anyhow!("No binary config specified"))
+                }
+                None => {
+                    let names: Vec<&str> = node_metadata
+                        .binary_refs
+                        .iter()
+                        .map(|b| b.binary_refs.binary_name.as_str())
+                        .collect();
+                    warn!(
+                        "Not generating binaries {} for {}, the configuration has no binary_application",
+                        names.join(", "),
+                        lib_target
+                    );
+                    return Ok(());
+                }
             };
             let mut required_load = HashMap::default();
 
@@ -908,6 +1336,8 @@ where
                     name: bin.binary_refs.binary_name.clone(),
                     extra_kv_pairs: Vec::default(),
                     extra_k_strs: k_strs,
+                    extra_k_scalars: Vec::default(),
+                    extra_kv_selects: Vec::default(),
                     required_load: required_load.clone(),
                     visibility: None,
                     srcs: None,
@@ -918,31 +1348,78 @@ where
         Ok(())
     }
 
+    fn add_manual_ref(
+        extra_kv_pairs: &mut HashMap<String, Vec<String>>,
+        extra_kv_selects: &mut BTreeMap<String, BTreeMap<String, Vec<String>>>,
+        manual_ref: &ManualRefConfig,
+    ) {
+        let key = match &manual_ref.command {
+            directive::ManualRefDirective::RuntimeRef => "runtime_deps",
+            directive::ManualRefDirective::Ref => "deps",
+            directive::ManualRefDirective::DataRef => "data",
+        };
+        match &manual_ref.condition {
+            Some(condition) => extra_kv_selects
+                .entry(key.to_string())
+                .or_default()
+                .entry(condition.clone())
+                .or_default()
+                .push(manual_ref.target_value.clone()),
+            None => extra_kv_pairs
+                .entry(key.to_string())
+                .or_default()
+                .push(manual_ref.target_value.clone()),
+        }
+    }
+
     fn apply_manual_refs(
         extra_kv_pairs: &mut HashMap<String, Vec<String>>,
+        extra_kv_selects: &mut BTreeMap<String, BTreeMap<String, Vec<String>>>,
         node_metadata: &GraphNodeMetadata,
     ) {
         for manual_ref in node_metadata.manual_refs.iter() {
-            match &manual_ref.command {
-                directive::ManualRefDirective::RuntimeRef => {
-                    extra_kv_pairs
-                        .entry("runtime_deps".to_string())
-                        .or_default()
-                        .push(manual_ref.target_value.clone());
-                }
-                directive::ManualRefDirective::Ref => {
-                    extra_kv_pairs
-                        .entry("deps".to_string())
-                        .or_default()
-                        .push(manual_ref.target_value.clone());
+            add_manual_ref(extra_kv_pairs, extra_kv_selects, manual_ref);
+        }
+    }
+
+    fn sorted_selects(
+        extra_kv_selects: BTreeMap<String, BTreeMap<String, Vec<String>>>,
+    ) -> Vec<(String, BTreeMap<String, Vec<String>>)> {
+        extra_kv_selects
+            .into_iter()
+            .map(|(k, mut conditions)| {
+                for v in conditions.values_mut() {
+                    v.sort();
+                    v.dedup();
                 }
-                directive::ManualRefDirective::DataRef => {
-                    extra_kv_pairs
-                        .entry("data".to_string())
-                        .or_default()
-                        .push(manual_ref.target_value.clone());
+                (k, conditions)
+            })
+            .collect()
+    }
+
+    // Writes deps on targets in the package being generated as :name, like buildifier's lint
+    // asks for. The secondary rules' ${deps} are expanded from these too.
+    fn relative_labels(
+        element: &str,
+        extra_kv_pairs: &mut HashMap<String, Vec<String>>,
+        extra_kv_selects: &mut BTreeMap<String, BTreeMap<String, Vec<String>>>,
+    ) {
+        let relative = |values: &mut Vec<String>| {
+            for v in values.iter_mut() {
+                if let relative @ MaybeLabel::Relative { .. } =
+                    MaybeLabel::from_str(v).relative_to(element)
+                {
+                    *v = relative.to_string();
                 }
             }
+        };
+        for key in ["deps", "runtime_deps", "data"] {
+            if let Some(values) = extra_kv_pairs.get_mut(key) {
+                relative(values);
+            }
+            if let Some(conditions) = extra_kv_selects.get_mut(key) {
+                conditions.values_mut().for_each(relative);
+            }
         }
     }
 
@@ -966,13 +1443,40 @@ where
         }
     }
 
+    fn insert_attr_scalar(
+        extra_scalars: &mut BTreeMap<String, AttrValue>,
+        attr: &AttrScalarConfig,
+    ) -> Result<()> {
+        match extra_scalars.get(&attr.attr_name) {
+            Some(existing) if existing != &attr.value => Err(anyhow!(
+                "Conflicting values for attribute {}: {} and {}",
+                attr.attr_name,
+                existing,
+                attr.value
+            )),
+            _ => {
+                extra_scalars.insert(attr.attr_name.clone(), attr.value.clone());
+                Ok(())
+            }
+        }
+    }
+
+    fn apply_attr_scalars(
+        extra_scalars: &mut BTreeMap<String, AttrValue>,
+        node_metadata: &GraphNodeMetadata,
+    ) -> Result<()> {
+        for attr in node_metadata.attr_scalars.iter() {
+            insert_attr_scalar(extra_scalars, attr)?;
+        }
+        Ok(())
+    }
+
     fn apply_secondary_rules(
         target_entries: &mut TargetEntries,
         module_config: &ModuleConfig,
         parent_target_name: &str,
-        parent_include_src: &Vec<String>,
-        parent_deps: &Vec<String>,
-    ) {
+        variables: &TemplateVariables,
+    ) -> Result<()> {
         for (k, build_config) in module_config.build_config.secondary_rules.iter() {
             let sec_target_name = format!("{}_{}", parent_target_name, k);
             let mut required_load = HashMap::default();
@@ -985,12 +1489,7 @@ where
             }
             let mut extra_kv_pairs: HashMap<String, Vec<String>> = HashMap::default();
             for (k, lst) in &build_config.extra_key_to_list {
-                let vs = lst
-                    .iter()
-                    .flat_map(|v| {
-                        eval_extra_var(v, parent_target_name, parent_include_src, parent_deps)
-                    })
-                    .collect::<Vec<_>>();
+                let vs = expand_templates(lst, variables)?;
                 match k.as_str() {
                     "srcs" => srcs = Some(SrcType::List(vs)),
                     _ => append_key_values(&mut extra_kv_pairs, k.clone(), &vs),
@@ -1006,45 +1505,72 @@ where
                         (k, v)
                     })
                     .collect(),
-                extra_k_strs: build_config
-                    .extra_key_to_value
-                    .clone()
-                    .into_iter()
-                    .map(|(k, v)| (k, v))
-                    .collect(),
+                extra_k_strs: expand_value_templates(&build_config.extra_key_to_value, variables)?,
+                extra_k_scalars: Vec::default(),
+                extra_kv_selects: Vec::default(),
                 required_load: required_load.clone(),
                 visibility: None,
                 srcs: srcs,
                 target_type: Arc::new(build_config.function_name.clone()),
             });
         }
+        Ok(())
     }
 
-    // This expands `${name}` etc appearing inside of the extra_key_to_list value
-    // with the name of the parent target.
-    fn eval_extra_var(
-        value: &String,
-        parent_target_name: &str,
-        parent_include_src: &Vec<String>,
-        parent_deps: &Vec<String>,
-    ) -> Vec<String> {
-        if value.contains("${name}") {
-            vec![value.replace("${name}", parent_target_name)]
-        } else if value.contains("${srcs}") {
-            parent_include_src
-                .clone()
-                .into_iter()
-                .map(|v| value.replace("${srcs}", &v))
-                .collect()
-        } else if value.contains("${deps}") {
-            parent_deps
-                .clone()
-                .into_iter()
-                .map(|v| value.replace("${deps}", &fully_qualified_label(&v)))
-                .collect()
-        } else {
-            vec![value.to_string()]
+    // What the templates in extra_key_to_list and extra_key_to_value of a target can refer to:
+    // `${name}` of the (parent) target, `${package}`, the `${stem}` of its source file or
+    // directory, and the `${srcs}`, `${deps}` and `${runtime_deps}` lists of the main target.
+    fn template_variables(
+        target_name: &str,
+        package: &str,
+        node_file_name: &str,
+        srcs: &[String],
+        extra_kv_pairs: &HashMap<String, Vec<String>>,
+    ) -> TemplateVariables {
+        let labels = |key: &str| -> Vec<String> {
+            let mut labels: Vec<String> = extra_kv_pairs
+                .get(key)
+                .map(|v| v.iter().map(fully_qualified_label).collect())
+                .unwrap_or_default();
+            labels.sort();
+            labels.dedup();
+            labels
+        };
+        let stem = Path::new(node_file_name)
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_else(|| node_file_name.to_string());
+        let mut variables = TemplateVariables::default();
+        variables.insert("name", target_name.to_string());
+        variables.insert("package", package.to_string());
+        variables.insert("stem", stem);
+        variables.insert_list("srcs", srcs.to_vec());
+        variables.insert_list("deps", labels("deps"));
+        variables.insert_list("runtime_deps", labels("runtime_deps"));
+        variables
+    }
+
+    fn expand_templates(values: &[String], variables: &TemplateVariables) -> Result<Vec<String>> {
+        let mut expanded = Vec::default();
+        for value in values {
+            expanded.extend(Template::parse(value)?.expand(variables)?);
         }
+        Ok(expanded)
+    }
+
+    fn expand_value_templates(
+        key_to_value: &HashMap<String, String>,
+        variables: &TemplateVariables,
+    ) -> Result<Vec<(String, String)>> {
+        key_to_value
+            .iter()
+            .map(|(k, v)| {
+                let value = Template::parse(v)
+                    .and_then(|t| t.expand_one(variables))
+                    .with_context(|| format!("Expanding {} = {}", k, v))?;
+                Ok((k.clone(), value))
+            })
+            .collect()
     }
 
     fn fully_qualified_label(value: &String) -> String {
@@ -1062,6 +1588,185 @@ where
     Ok((t, Some(module_config)))
 }
 
+/// Wraps generated code in BEGIN/END markers that carry a hash of the code, so that a later run
+/// can tell whether the section was edited by hand.
+fn generated_section(marker: &str, code: &str) -> String {
+    let body = format!("\n{}\n\n", code);
+    let hash = generated_section_hash(&body);
+    format!(
+        "# ---- BEGIN {} ---- sha256:{}\n{}# ---- END {} ---- sha256:{}\n",
+        marker, hash, body, marker, hash
+    )
+}
+
+// Hashes the code between the markers, ignoring whitespace, quote style and trailing commas so
+// that reformatting the file with buildifier doesn't count as an edit. String literals are
+// hashed as written, apart from their quotes.
+fn generated_section_hash(body: &str) -> Sha256Value {
+    let mut normalized = String::with_capacity(body.len());
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' | '\'' => {
+                normalized.push('"');
+                while let Some(s) = chars.next() {
+                    if s == c {
+                        normalized.push('"');
+                        break;
+                    }
+                    normalized.push(s);
+                    if s == '\\' {
+                        normalized.extend(chars.next());
+                    }
+                }
+            }
+            // a quote in a comment doesn't start a string
+            '#' => {
+                normalized.push(c);
+                for s in chars.by_ref().take_while(|s| *s != '\n') {
+                    if !s.is_whitespace() {
+                        normalized.push(s);
+                    }
+                }
+                normalized.push('\n');
+            }
+            _ if c.is_whitespace() => (),
+            _ => {
+                if (c == ')' || c == ']') && normalized.ends_with(',') {
+                    normalized.pop();
+                }
+                normalized.push(c);
+            }
+        }
+    }
+    Sha256Value::from(normalized.as_bytes())
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct GeneratedSection<'a> {
+    marker: &'a str,
+    // None for sections written before markers carried a hash (`no_hash`)
+    hash: Option<&'a str>,
+    body: &'a str,
+}
+
+fn generated_sections(content: &str) -> Vec<GeneratedSection<'_>> {
+    let mut sections = Vec::default();
+    let mut rest = content;
+    while let Some(begin_pos) = rest.find("# ---- BEGIN ") {
+        let line_start = begin_pos + "# ---- BEGIN ".len();
+        let line_end = rest[line_start..]
+            .find('\n')
+            .map(|p| line_start + p)
+            .unwrap_or(rest.len());
+        let line = &rest[line_start..line_end];
+        rest = &rest[line_end..];
+        let Some((marker, tail)) = line.split_once(" ---- ") else {
+            continue;
+        };
+        let end_marker = format!("# ---- END {} ---- ", marker);
+        if let Some(end_pos) = rest.find(&end_marker) {
+            sections.push(GeneratedSection {
+                marker,
+                hash: tail.trim().strip_prefix("sha256:"),
+                body: rest[..end_pos]
+                    .strip_prefix('\n')
+                    .unwrap_or(&rest[..end_pos]),
+            });
+            rest = &rest[end_pos + end_marker.len()..];
+        }
+    }
+    sections
+}
+
+/// Fails if a generated section in `content` no longer matches the hash in its markers,
+/// i.e. it was edited by hand since it was generated. With `force` only a warning is logged.
+fn verify_generated_sections(path: &Path, content: &str, force: bool) -> Result<()> {
+    for section in generated_sections(content) {
+        let Some(hash) = section.hash else {
+            continue;
+        };
+        let unchanged = if section.marker == MERGED_TARGETS_MARKER {
+            merged_targets_hash(content, &merged_target_names(section.body))
+                .with_context(|| format!("Parsing {:?}", path))?
+                .to_string()
+                == hash
+        } else {
+            generated_section_hash(section.body).to_string() == hash
+        };
+        if unchanged {
+            continue;
+        }
+        if force {
+            warn!(
+                "Overwriting hand edits to the {} section of {:?}",
+                section.marker, path
+            );
+        } else {
+            return Err(anyhow!(
+                "The {} section of {:?} was edited by hand since it was generated, pass --force to overwrite it",
+                section.marker,
+                path
+            ));
+        }
+    }
+    Ok(())
+}
+
+// The BUILD file to write in `directory`: the configured `--build-file-name`, otherwise whichever
+// of BUILD.bazel or BUILD the package already has, so we never add a second one.
+fn build_file_path(opt: &Opt, directory: &Path) -> PathBuf {
+    match &opt.build_file_name {
+        Some(name) => directory.join(name),
+        None => {
+            let build_bazel = directory.join(BUILD_BAZEL.as_os_str());
+            let build_no_ext = directory.join(BUILD_NO_EXT.as_os_str());
+            if !build_bazel.exists() && build_no_ext.is_file() {
+                build_no_ext
+            } else {
+                build_bazel
+            }
+        }
+    }
+}
+
+// Writes the BUILD file unless it already has exactly this content, and reports what changed.
+// With `--output-root` the repo is left alone, and every file is written to the same relative
+// path under the output root instead.
+async fn write_build_file(opt: &Opt, path: &Path, content: String) -> Result<FileChange> {
+    let previous = tokio::fs::read_to_string(path).await.ok();
+    if opt.check {
+        // only report what would change
+    } else if let Some(output_root) = &opt.output_root {
+        let output_path = output_root.join(path.strip_prefix(&opt.working_directory)?);
+        if let Some(parent) = output_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&output_path, &content)
+            .await
+            .with_context(|| format!("Attempting to write file data to {:?}", output_path))?;
+    } else if previous.as_deref() != Some(content.as_str()) {
+        tokio::fs::write(path, &content)
+            .await
+            .with_context(|| format!("Attempting to write file data to {:?}", path))?;
+    }
+    Ok(match previous {
+        Some(previous) if previous == content => FileChange::Untouched(path.to_path_buf()),
+        None => FileChange::Created(path.to_path_buf()),
+        Some(previous) => {
+            FileChange::Modified(path.to_path_buf(), diff_targets(&previous, &content))
+        }
+    })
+}
+
+// Reads the BUILD file about to be rewritten, checking that its generated sections weren't
+// edited by hand.
+async fn read_existing_build_file(path: &Path, force: bool) -> Result<String> {
+    let existing = tokio::fs::read_to_string(path).await.unwrap_or_default();
+    verify_generated_sections(path, &existing, force)?;
+    Ok(existing)
+}
+
 /// Replaces or appends the tagged section in existing BUILD file content.
 /// Tag is used in markers: BEGIN/END BZL_GEN_BUILD_<tag>_GENERATED_CODE.
 /// When section is missing: if `insert_at_start_if_missing` then prepend (for load block), else append.
@@ -1071,24 +1776,67 @@ fn replace_tag_section(
     new_block: &str,
     insert_at_start_if_missing: bool,
 ) -> String {
-    let begin_marker = format!(
-        "# ---- BEGIN BZL_GEN_BUILD_{}_GENERATED_CODE ---- no_hash",
-        tag
-    );
-    let end_marker = format!(
-        "# ---- END BZL_GEN_BUILD_{}_GENERATED_CODE ---- no_hash",
-        tag
-    );
+    replace_marked_section(
+        existing,
+        &format!("BZL_GEN_BUILD_{}_GENERATED_CODE", tag),
+        new_block,
+        insert_at_start_if_missing,
+    )
+}
+
+/// Removes the sections of the tag, and its load section, when there are any.
+fn remove_tag_section(existing: &str, tag: &str) -> String {
+    let mut content = existing.to_string();
+    for marker in [format!("LOAD_{}", tag), tag.to_string()] {
+        let marker = format!("BZL_GEN_BUILD_{}_GENERATED_CODE", marker);
+        if content.contains(&format!("# ---- BEGIN {} ---- ", marker)) {
+            content = replace_marked_section(&content, &marker, "", false);
+        }
+    }
+    content
+}
+
+/// Replaces the untagged BZL_GEN_BUILD_GENERATED_CODE block left by an earlier append, or
+/// appends one, so running in append mode again doesn't duplicate every target.
+fn replace_appended_section(existing: &str, t: &TargetEntries) -> Result<String> {
+    let marker = "BZL_GEN_BUILD_GENERATED_CODE";
+    if t.entries.is_empty() {
+        if !existing.contains(&format!("# ---- BEGIN {} ---- ", marker)) {
+            return Ok(existing.to_string());
+        }
+        return Ok(replace_marked_section(existing, marker, "", false));
+    }
+    Ok(replace_marked_section(
+        existing,
+        marker,
+        &t.emit_build_file(None)?,
+        false,
+    ))
+}
+
+fn replace_marked_section(
+    existing: &str,
+    marker: &str,
+    new_block: &str,
+    insert_at_start_if_missing: bool,
+) -> String {
+    let begin_marker = format!("# ---- BEGIN {} ---- ", marker);
+    let end_marker = format!("# ---- END {} ---- ", marker);
     if let Some(begin_pos) = existing.find(&begin_marker) {
         let after_begin = begin_pos + begin_marker.len();
         if let Some(end_pos) = existing[after_begin..].find(&end_marker) {
-            let end_pos = after_begin + end_pos + end_marker.len();
+            let end_pos = after_begin + end_pos;
+            let end_pos = existing[end_pos..]
+                .find('\n')
+                .map(|p| end_pos + p)
+                .unwrap_or(existing.len());
             let mut out = String::with_capacity(
                 existing.len() - (end_pos - begin_pos) + new_block.len(),
             );
             out.push_str(&existing[..begin_pos]);
             out.push_str(new_block.trim_end());
-            if !new_block.ends_with('\n') {
+            // keep a single trailing newline when the block was the last thing in the file
+            if !new_block.ends_with('\n') || end_pos == existing.len() {
                 out.push('\n');
             }
             out.push_str(&existing[end_pos..]);
@@ -1113,167 +1861,289 @@ fn replace_tag_section(
     }
 }
 
+const MERGED_TARGETS_MARKER: &str = "BZL_GEN_BUILD_MERGED_TARGETS";
+
+// The targets an earlier merge wrote, listed one per line in the merged targets section.
+fn merged_target_names(body: &str) -> Vec<String> {
+    body.lines()
+        .filter_map(|l| l.trim().strip_prefix('#'))
+        .map(|l| l.trim().to_string())
+        .filter(|l| !l.is_empty())
+        .collect()
+}
+
+// Hashes what the generator owns on the merged targets, so hand edits to it can be detected
+// like they are in the other generated sections.
+fn merged_targets_hash(content: &str, names: &[String]) -> Result<Sha256Value> {
+    Ok(generated_section_hash(&owned_attributes_text(
+        content, names,
+    )?))
+}
+
+/// Merges the targets into existing BUILD file content, removing the targets an earlier merge
+/// wrote that aren't generated anymore. The merged targets are listed in a section at the end
+/// of the file, which also carries the hash of what the generator owns on them.
+fn merge_generated_targets(existing: &str, t: &TargetEntries) -> Result<String> {
+    let previously_generated = generated_sections(existing)
+        .into_iter()
+        .find(|s| s.marker == MERGED_TARGETS_MARKER)
+        .map(|s| merged_target_names(s.body))
+        .unwrap_or_default();
+    let without_section = replace_marked_section(existing, MERGED_TARGETS_MARKER, "", false);
+    let without_section = match without_section.trim_end() {
+        "" => String::default(),
+        content => format!("{}\n", content),
+    };
+    let generated = if t.entries.is_empty() {
+        String::default()
+    } else {
+        t.to_ast()?.to_string()
+    };
+    let merged = merge_build_file(&without_section, &generated, &previously_generated)?;
+    let names: Vec<String> = t.entries.iter().map(|e| e.name.clone()).collect();
+    if names.is_empty() {
+        return Ok(merged);
+    }
+    let hash = merged_targets_hash(&merged, &names)?;
+    let body: String = names.iter().map(|n| format!("# {}\n", n)).collect();
+    Ok(format!(
+        "{}\n\n# ---- BEGIN {} ---- sha256:{}\n{}# ---- END {} ---- sha256:{}\n",
+        merged.trim_end(),
+        MERGED_TARGETS_MARKER,
+        hash,
+        body,
+        MERGED_TARGETS_MARKER,
+        hash
+    ))
+}
+
+// Writes each of the targets to the BUILD file the way its write mode asks for, in order. When
+// that leaves nothing to write, the file isn't emitted, and is deleted like any other stale
+// BUILD file if the generator wrote it.
+async fn write_targets(
+    opt: &Opt,
+    path: &Path,
+    sections: &[(WriteMode, &TargetEntries)],
+    disable_format: bool,
+) -> Result<Option<FileChange>> {
+    let mut content = read_existing_build_file(path, opt.force).await?;
+    for (write_mode, t) in sections {
+        content = match write_mode {
+            WriteMode::Append => replace_appended_section(&content, t)?,
+            WriteMode::Overwrite if t.entries.is_empty() => String::default(),
+            WriteMode::Overwrite => t.emit_build_file(None)?,
+            WriteMode::OverwriteTag(tag) if t.entries.is_empty() => {
+                remove_tag_section(&content, tag)
+            }
+            WriteMode::OverwriteTag(tag) => {
+                let (load_block, targets_block) = t.emit_build_file_tagged(tag)?;
+                replace_tag_section(
+                    &replace_tag_section(&content, &format!("LOAD_{}", tag), &load_block, true),
+                    tag,
+                    &targets_block,
+                    false,
+                )
+            }
+            WriteMode::Merge => merge_generated_targets(&content, t)
+                .with_context(|| format!("Merging targets into {:?}", path))?,
+        };
+    }
+    if content.trim().is_empty() {
+        return Ok(None);
+    }
+    content = maybe_add_buildifier_disable(&content, disable_format);
+    Ok(Some(write_build_file(opt, path, content).await?))
+}
+
 // Performs the side effect of writing BUILD file
+#[allow(clippy::too_many_arguments)]
 async fn print_file(
     opt: &'static Opt,
     project_conf: &'static ProjectConf,
     mut graph_nodes: Vec<GraphNode>,
     concurrent_io_operations: &'static Semaphore,
     element: String,
-) -> Result<Vec<PathBuf>> {
+    excluded_files: Vec<String>,
+    target_names: Arc<HashMap<String, String>>,
+    name_strategies: Arc<HashMap<String, (&'static str, &'static TargetNameStrategy)>>,
+    label_rules: Arc<LabelRules>,
+) -> Result<(Vec<PathBuf>, Vec<FileChange>, BannedUses)> {
     graph_nodes.sort_by(|a, b| a.node_label.cmp(&b.node_label));
     let mut emitted_files: Vec<PathBuf> = Vec::default();
+    let mut file_changes: Vec<FileChange> = Vec::default();
+    let mut banned = BannedUses::default();
     let target_folder = opt.working_directory.join(&element);
-    let target_file = target_folder.join("BUILD.bazel");
+    let target_file = build_file_path(opt, &target_folder);
     emitted_files.push(target_file.clone());
-    let (t1, mc1) = generate_targets(
-        opt,
-        project_conf,
-        SourceConfig::Main,
-        &graph_nodes,
-        &element,
-        &mut emitted_files,
-        |sub_target: PathBuf, t: TargetEntries, disable_format: bool| async move {
-            let _handle = concurrent_io_operations.acquire().await?;
-            if let Some(tag) = opt.overwrite.as_deref() {
-                let existing = tokio::fs::read_to_string(&sub_target)
-                    .await
-                    .unwrap_or_default();
-                let (load_block, targets_block) = t.emit_build_file_tagged(tag)?;
-                let content = replace_tag_section(
-                    &replace_tag_section(
-                        &existing,
-                        &format!("LOAD_{}", tag),
-                        &load_block,
-                        true,
-                    ),
-                    tag,
-                    &targets_block,
-                    false,
-                );
-                let content = maybe_add_buildifier_disable(&content, disable_format);
-                tokio::fs::write(sub_target.clone(), content)
-                    .await
-                    .with_context(|| format!("Attempting to write file data to {:?}", sub_target))?;
-            } else {
-                let content = maybe_add_buildifier_disable(t.emit_build_file(None)?, disable_format);
-                tokio::fs::write(sub_target.clone(), content)
-                    .await
-                    .with_context(|| format!("Attempting to write file data to {:?}", sub_target))?;
+    let on_child = |sub_target: PathBuf, t: TargetEntries, module_config: &'static ModuleConfig| async move {
+        let _handle = concurrent_io_operations.acquire().await?;
+        let write_mode = module_config
+            .build_config
+            .child_filegroup
+            .write_mode
+            .clone()
+            .or_else(|| module_config.tag.clone().map(WriteMode::OverwriteTag))
+            .unwrap_or_else(|| WriteMode::new(opt.append, opt.merge, opt.overwrite.clone()));
+        write_targets(
+            opt,
+            &sub_target,
+            &[(write_mode, &t)],
+            module_config.disable_format,
+        )
+        .await
+    };
+
+    let mut nodes_by_configuration: BTreeMap<Option<String>, Vec<GraphNode>> = BTreeMap::default();
+    for graph_node in graph_nodes {
+        nodes_by_configuration
+            .entry(graph_node.configuration.clone())
+            .or_default()
+            .push(graph_node);
+    }
+    // Targets of configurations without a tag of their own go where the write mode of the run
+    // puts them.
+    let mut targets_by_tag: BTreeMap<Option<String>, TargetEntries> = BTreeMap::default();
+    let mut disable_format = false;
+    for (configuration, graph_nodes) in nodes_by_configuration {
+        let (t1, mc1) = generate_targets(
+            opt,
+            project_conf,
+            configuration.as_deref(),
+            SourceConfig::Main,
+            &graph_nodes,
+            &element,
+            &excluded_files,
+            &target_names,
+            &name_strategies,
+            &label_rules,
+            &mut emitted_files,
+            &mut file_changes,
+            &mut banned,
+            on_child,
+        )
+        .await?;
+        let (t2, mc2) = generate_targets(
+            opt,
+            project_conf,
+            configuration.as_deref(),
+            SourceConfig::Test,
+            &graph_nodes,
+            &element,
+            &excluded_files,
+            &target_names,
+            &name_strategies,
+            &label_rules,
+            &mut emitted_files,
+            &mut file_changes,
+            &mut banned,
+            on_child,
+        )
+        .await?;
+        let module_config = mc1.or(mc2);
+        disable_format |= module_config.map(|mc| mc.disable_format).unwrap_or(false);
+        let tag = module_config
+            .and_then(|mc| mc.tag.clone())
+            .or_else(|| opt.overwrite.clone());
+        let t = TargetEntries::combine(t1, t2);
+        let entry = targets_by_tag.entry(tag).or_default();
+        *entry = TargetEntries::combine(std::mem::take(entry), t);
+    }
+    // Sections of the tagged configurations covering the directory that have nothing to
+    // generate any more are removed, along with the targets of sources that went away.
+    for module_config in project_conf.configurations.values() {
+        let Some(tag) = &module_config.tag else {
+            continue;
+        };
+        if module_config
+            .main_roots
+            .iter()
+            .chain(module_config.test_roots.iter())
+            .any(|r| element.starts_with(r.as_str()))
+        {
+            targets_by_tag.entry(Some(tag.clone())).or_default();
+        }
+    }
+    let sections: Vec<(WriteMode, &TargetEntries)> = targets_by_tag
+        .iter()
+        .map(|(tag, t)| {
+            let write_mode = match tag {
+                Some(tag) => WriteMode::OverwriteTag(tag.clone()),
+                None => WriteMode::new(opt.append, opt.merge, None),
+            };
+            (write_mode, t)
+        })
+        .collect();
+    if !banned.is_empty() {
+        return Ok((emitted_files, file_changes, banned));
+    }
+    let handle = concurrent_io_operations.acquire().await?;
+    let change = write_targets(opt, &target_file, &sections, disable_format).await?;
+    drop(handle);
+    match change {
+        Some(change) => file_changes.push(change),
+        None => emitted_files.retain(|f| f != &target_file),
+    }
+
+    Ok((emitted_files, file_changes, banned))
+}
+
+// Targets renamed with a `target_name` directive, keyed by node label, so that
+// labels pointing at them can be resolved from any package.
+fn target_name_overrides(
+    build_mapping: &HashMap<String, GraphNode>,
+) -> Result<HashMap<String, String>> {
+    let mut target_names: HashMap<String, String> = HashMap::default();
+    for (entry, graph_node) in build_mapping.iter() {
+        match graph_node.node_metadata.target_names.as_slice() {
+            [] => (),
+            [name] => {
+                target_names.insert(entry.clone(), name.clone());
             }
-            Ok(0)
-        },
-    )
-    .await?;
-    let (t2, mc2) = generate_targets(
-        opt,
-        project_conf,
-        SourceConfig::Test,
-        &graph_nodes,
-        &element,
-        &mut emitted_files,
-        |sub_target: PathBuf, t: TargetEntries, disable_format: bool| async move {
-            let _handle = concurrent_io_operations.acquire().await?;
-            if let Some(tag) = opt.overwrite.as_deref() {
-                let existing = tokio::fs::read_to_string(&sub_target)
-                    .await
-                    .unwrap_or_default();
-                let (load_block, targets_block) = t.emit_build_file_tagged(tag)?;
-                let content = replace_tag_section(
-                    &replace_tag_section(
-                        &existing,
-                        &format!("LOAD_{}", tag),
-                        &load_block,
-                        true,
-                    ),
-                    tag,
-                    &targets_block,
-                    false,
-                );
-                let content = maybe_add_buildifier_disable(&content, disable_format);
-                tokio::fs::write(sub_target.clone(), content)
-                    .await
-                    .with_context(|| format!("Attempting to write file data to {:?}", sub_target))?;
-            } else {
-                let content = maybe_add_buildifier_disable(t.emit_build_file(None)?, disable_format);
-                tokio::fs::write(sub_target.clone(), content)
-                    .await
-                    .with_context(|| format!("Attempting to write file data to {:?}", sub_target))?;
+            names => {
+                return Err(anyhow!(
+                    "Conflicting target_name directives for {}: {:?}",
+                    entry,
+                    names
+                ))
             }
-            Ok(0)
-        },
-    )
-    .await?;
-    let t = TargetEntries::combine(t1, t2);
-    let disable_format = mc1
-        .or(mc2)
-        .map(|mc| mc.disable_format)
-        .unwrap_or(false);
-    let handle = concurrent_io_operations.acquire().await?;
-    let write_mode = WriteMode::new(opt.append, opt.overwrite.clone());
-    match &write_mode {
-        WriteMode::Append => {
-            let mut file = tokio::fs::OpenOptions::new()
-                .write(true)
-                .create(true)
-                .append(true)
-                .open(&target_file)
-                .await?;
-
-            if !t.entries.is_empty() {
-                let content =
-                    maybe_add_buildifier_disable(t.emit_build_file(None)?, disable_format);
-                file.write(content.as_bytes())
-                    .await
-                    .with_context(|| {
-                        format!("Attempting to write file data to {:?}", target_file)
-                    })?;
-            }
-        }
-        WriteMode::Overwrite => {
-            let mut file = tokio::fs::OpenOptions::new()
-                .write(true)
-                .create(true)
-                .truncate(true)
-                .open(&target_file)
-                .await?;
-            if !t.entries.is_empty() {
-                let content =
-                    maybe_add_buildifier_disable(t.emit_build_file(None)?, disable_format);
-                file.write_all(content.as_bytes())
-                    .await
-                    .with_context(|| {
-                        format!("Attempting to write file data to {:?}", target_file)
-                    })?;
-            }
-        }
-        WriteMode::OverwriteTag(tag) => {
-            let existing = tokio::fs::read_to_string(&target_file)
-                .await
-                .unwrap_or_default();
-            let (load_block, targets_block) = t.emit_build_file_tagged(tag)?;
-            let content = replace_tag_section(
-                &replace_tag_section(
-                    &existing,
-                    &format!("LOAD_{}", tag),
-                    &load_block,
-                    true,
-                ),
-                tag,
-                &targets_block,
-                false,
-            );
-            let content = maybe_add_buildifier_disable(&content, disable_format);
-            tokio::fs::write(&target_file, content)
-                .await
-                .with_context(|| {
-                    format!("Attempting to write file data to {:?}", target_file)
-                })?;
         }
     }
-    drop(handle);
+    Ok(target_names)
+}
 
-    Ok(emitted_files)
+// The configuration, and the target name strategy of its targets, of the nodes that know
+// theirs, keyed by node label, so that dependencies on nodes of other configurations get the
+// names those are generated with.
+fn name_strategies(
+    project_conf: &'static ProjectConf,
+    build_mapping: &HashMap<String, GraphNode>,
+) -> HashMap<String, (&'static str, &'static TargetNameStrategy)> {
+    let mut name_strategies = HashMap::default();
+    for (entry, graph_node) in build_mapping.iter() {
+        let Some((configuration, module_config)) = graph_node
+            .configuration
+            .as_ref()
+            .and_then(|c| project_conf.configurations.get_key_value(c))
+        else {
+            continue;
+        };
+        let build_config = if module_config
+            .test_roots
+            .iter()
+            .any(|r| entry.starts_with(r.as_str()))
+        {
+            &module_config.build_config.test
+        } else {
+            &module_config.build_config.main
+        };
+        if let Some(build_config) = build_config {
+            name_strategies.insert(
+                entry.clone(),
+                (configuration.as_str(), &build_config.target_name_strategy),
+            );
+        }
+    }
+    name_strategies
 }
 
 async fn async_find_all_build_files(
@@ -1298,7 +2168,9 @@ async fn async_find_all_build_files(
                 {
                     if entry.file_type().map(|e| e.is_file()).unwrap_or(false)
                         && (entry.file_name() == BUILD_NO_EXT.as_os_str()
-                            || entry.file_name() == BUILD_BAZEL.as_os_str())
+                            || entry.file_name() == BUILD_BAZEL.as_os_str()
+                            || opt.build_file_name.as_deref().map(std::ffi::OsStr::new)
+                                == Some(entry.file_name()))
                     {
                         local_results.insert(entry.into_path());
                     }
@@ -1316,20 +2188,351 @@ async fn async_find_all_build_files(
     Ok(results)
 }
 
+fn keep_build_files_globset(globs: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs.iter() {
+        builder.add(
+            Glob::new(glob).with_context(|| format!("Parsing keep_build_files glob {:?}", glob))?,
+        );
+    }
+    builder
+        .build()
+        .with_context(|| "Building the keep_build_files globs")
+}
+
+fn delete_stale_build_file(opt: &Opt, path: &Path) -> Result<()> {
+    if opt.check {
+        println!("Would delete no longer used build file of: {:?}", path);
+        return Ok(());
+    }
+    println!("Deleting no longer used build file of: {:?}", path);
+    std::fs::remove_file(path).with_context(|| format!("Deleting {:?}", path))
+}
+
+// Why a BUILD file we didn't emit this run has to stay, or None when the generated content can
+// be taken out of it, see `clean_stale_build_file`. Only files the generator wrote are touched,
+// unless they match `keep_build_files` or start with a `# bzl_gen_build: keep_file` comment.
+fn keep_stale_build_file(
+    relative_path: &Path,
+    content: &str,
+    keep_build_files: &GlobSet,
+) -> Option<&'static str> {
+    if keep_build_files.is_match(relative_path) {
+        return Some("it matches keep_build_files");
+    }
+    for line in content.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        if !trimmed.starts_with('#') {
+            break;
+        }
+        if Directive::extract_directive(trimmed, "#").map(|d| d.trim() == "keep_file") == Some(true)
+        {
+            return Some("it has a keep_file header");
+        }
+    }
+    if !content.contains("# ---- BEGIN BZL_GEN_BUILD_") {
+        return Some("it wasn't generated by bzl_gen_build");
+    }
+    None
+}
+
+// Takes what the generator wrote out of a BUILD file we didn't emit this run: the untagged
+// section of overwrite and append mode, the targets an earlier merge wrote and the sections of
+// the tags of this run's configurations. Sections of other tags are left to their own runs.
+fn strip_generated_content(content: &str, tags: &BTreeSet<&String>) -> Result<String> {
+    let mut remaining = replace_appended_section(content, &TargetEntries::default())?;
+    if remaining.contains(&format!("# ---- BEGIN {} ---- ", MERGED_TARGETS_MARKER)) {
+        remaining = merge_generated_targets(&remaining, &TargetEntries::default())?;
+    }
+    for tag in tags.iter() {
+        remaining = remove_tag_section(&remaining, tag);
+    }
+    Ok(remaining)
+}
+
+// Deletes a stale BUILD file when nothing but loads is left once the generated content is out,
+// otherwise writes back what's left and returns the change.
+async fn clean_stale_build_file(
+    opt: &Opt,
+    path: &Path,
+    tags: &BTreeSet<&String>,
+) -> Result<Option<FileChange>> {
+    let content = read_existing_build_file(path, opt.force).await?;
+    let remaining = strip_generated_content(&content, tags)
+        .with_context(|| format!("Removing generated targets from {:?}", path))?;
+    let only_loads = starlark_calls(&remaining)
+        .with_context(|| format!("Parsing what's left of {:?}", path))?
+        .iter()
+        .all(|c| c.function == "load");
+    if only_loads {
+        delete_stale_build_file(opt, path)?;
+        Ok(None)
+    } else {
+        Ok(Some(write_build_file(opt, path, remaining).await?))
+    }
+}
+
+// Rewrites canonical repository names in the labels of the graph to apparent ones, warning
+// once for each canonical name the main repository can't see.
+fn apply_repo_mapping(repo_mapping: &RepoMapping, build_mapping: &mut HashMap<String, GraphNode>) {
+    let mut unmapped: BTreeSet<String> = BTreeSet::default();
+    let mut rewrite = |label: &mut String| match repo_mapping.apparent_label(label) {
+        Some(apparent) => *label = apparent,
+        None => {
+            unmapped.insert(label.split("//").next().unwrap_or_default().to_string());
+        }
+    };
+    for graph_node in build_mapping.values_mut() {
+        graph_node.dependencies.iter_mut().for_each(&mut rewrite);
+        graph_node
+            .runtime_dependencies
+            .iter_mut()
+            .for_each(&mut rewrite);
+        let metadatas = std::iter::once(&mut graph_node.node_metadata)
+            .chain(graph_node.child_nodes.values_mut());
+        for metadata in metadatas {
+            for manual_ref in metadata.manual_refs.iter_mut() {
+                rewrite(&mut manual_ref.target_value);
+            }
+        }
+    }
+    for repo in unmapped {
+        warn!(
+            "No apparent name for repository {}, its labels are written as they are",
+            repo
+        );
+    }
+}
+
+/// The `label_rewrites` and `banned_labels` of the project. They are applied to the labels a
+/// target depends on as they are written, so to graph dependencies and manual refs alike.
+#[derive(Default)]
+struct LabelRules {
+    rewriter: LabelRewriter,
+    banned: Vec<EntityPattern>,
+}
+
+// Fails if a secondary_rule_for names a secondary rule its configuration doesn't have.
+fn check_secondary_rule_for(project_conf: &ProjectConf) -> Result<()> {
+    for (configuration, module_config) in project_conf.configurations.iter() {
+        let build_config = &module_config.build_config;
+        for (dependent, secondary) in build_config.secondary_rule_for.iter() {
+            if !build_config.secondary_rules.contains_key(secondary) {
+                return Err(anyhow!(
+                    "The secondary_rule_for {} of {} is {}, which isn't one of its secondary_rules",
+                    dependent,
+                    configuration,
+                    secondary
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Banned labels, with the sources of the targets that depend on them.
+type BannedUses = BTreeMap<String, BTreeSet<String>>;
+
+impl LabelRules {
+    fn new(project_conf: &ProjectConf) -> Result<LabelRules> {
+        let rewriter = LabelRewriter::new(&project_conf.label_rewrites)
+            .with_context(|| "Parsing label_rewrites")?;
+        let banned = project_conf
+            .banned_labels
+            .iter()
+            .map(|b| EntityPattern::with_wildcards(b))
+            .collect::<Result<Vec<_>>>()
+            .with_context(|| "Parsing banned_labels")?;
+        Ok(LabelRules { rewriter, banned })
+    }
+
+    // The ways the label can be written, so that a rule for //a/b:b also applies to //a/b and
+    // one for @foo//:foo to @foo.
+    fn label_forms(label: &str) -> Vec<String> {
+        let mut forms = vec![label.to_string()];
+        let mut add = |form: String| {
+            if !forms.contains(&form) {
+                forms.push(form);
+            }
+        };
+        match MaybeLabel::from_str(label) {
+            MaybeLabel::Label {
+                workspace,
+                package_name,
+                rule_name,
+            } => {
+                let repo = workspace.as_ref().map(|w| format!("@{}", w));
+                add(format!(
+                    "{}//{}:{}",
+                    repo.unwrap_or_default(),
+                    package_name.join("/"),
+                    rule_name
+                ));
+                add(MaybeLabel::Label {
+                    workspace,
+                    package_name,
+                    rule_name,
+                }
+                .to_string());
+            }
+            MaybeLabel::JustString(s) if s.starts_with('@') && !s.contains('/') => {
+                add(format!("{}//:{}", s, &s[1..]))
+            }
+            _ => (),
+        }
+        forms
+    }
+
+    fn rewrite(&self, label: &str) -> Option<String> {
+        Self::label_forms(label)
+            .iter()
+            .find_map(|form| self.rewriter.rewrite(form))
+    }
+
+    fn is_banned(&self, label: &str) -> bool {
+        Self::label_forms(label)
+            .iter()
+            .any(|form| self.banned.iter().any(|p| p.matches(form)))
+    }
+
+    // Rewrites the deps, runtime_deps and data of a target, recording the banned labels left.
+    fn apply(
+        &self,
+        sources: &[String],
+        extra_kv_pairs: &mut HashMap<String, Vec<String>>,
+        extra_kv_selects: &mut BTreeMap<String, BTreeMap<String, Vec<String>>>,
+        banned: &mut BannedUses,
+    ) {
+        if self.rewriter.is_empty() && self.banned.is_empty() {
+            return;
+        }
+        let mut apply = |values: &mut Vec<String>| {
+            for v in values.iter_mut() {
+                if let Some(rewritten) = self.rewrite(v) {
+                    *v = rewritten;
+                }
+                if self.is_banned(v) {
+                    banned
+                        .entry(v.clone())
+                        .or_default()
+                        .extend(sources.iter().cloned());
+                }
+            }
+        };
+        for key in ["deps", "runtime_deps", "data"] {
+            if let Some(values) = extra_kv_pairs.get_mut(key) {
+                apply(values);
+            }
+            if let Some(conditions) = extra_kv_selects.get_mut(key) {
+                conditions.values_mut().for_each(&mut apply);
+            }
+        }
+    }
+}
+
+// The source files behind the paths banned labels were recorded for: graph nodes that stand for
+// a whole directory are listed as the files in it with one of the configured extensions.
+async fn banned_label_sources(
+    opt: &Opt,
+    project_conf: &ProjectConf,
+    sources: BTreeSet<String>,
+) -> Result<Vec<String>> {
+    let extensions: BTreeSet<&str> = project_conf
+        .configurations
+        .values()
+        .flat_map(|c| c.file_extensions.iter().map(|e| e.as_str()))
+        .collect();
+    let mut files = BTreeSet::default();
+    for source in sources {
+        let path = opt.working_directory.join(&source);
+        let is_dir = tokio::fs::metadata(&path)
+            .await
+            .map(|m| m.is_dir())
+            .unwrap_or(false);
+        if !is_dir {
+            files.insert(source);
+            continue;
+        }
+        let mut entries = tokio::fs::read_dir(&path)
+            .await
+            .with_context(|| format!("Listing {:?}", path))?;
+        while let Some(entry) = entries.next_entry().await? {
+            let file_path = entry.path();
+            let extension = file_path.extension().and_then(|e| e.to_str());
+            if entry.file_type().await?.is_file()
+                && extension.map(|e| extensions.contains(e)).unwrap_or(false)
+            {
+                files.insert(format!(
+                    "{}/{}",
+                    source,
+                    entry.file_name().to_string_lossy()
+                ));
+            }
+        }
+    }
+    Ok(files.into_iter().collect())
+}
+
+// Fails listing each banned label that generated targets would depend on, and the sources that
+// pulled it in.
+async fn check_banned_labels(
+    opt: &Opt,
+    project_conf: &ProjectConf,
+    banned: BannedUses,
+) -> Result<()> {
+    if banned.is_empty() {
+        return Ok(());
+    }
+    let mut lines = Vec::default();
+    for (label, sources) in banned {
+        let sources = banned_label_sources(opt, project_conf, sources).await?;
+        lines.push(format!("  {} is used by {}", label, sources.join(", ")));
+    }
+    Err(anyhow!(
+        "Generated targets depend on banned labels, the BUILD files using them weren't written:\n{}",
+        lines.join("\n")
+    ))
+}
+
 pub async fn print_build(
     opt: &'static Opt,
     print_build_args: &'static PrintBuildArgs,
     project_conf: &'static ProjectConf,
     concurrent_io_operations: &'static Semaphore,
 ) -> Result<()> {
-    let graph_data: GraphMapping = async_read_json_file(&print_build_args.graph_data)
+    let mut timings = Vec::default();
+    let st = Instant::now();
+    let mut graph_data: GraphMapping = async_read_json_file(&print_build_args.graph_data)
         .await
         .with_context(|| "Attempting to load graph data")?;
+    timings.push(("load graph", st.elapsed()));
 
+    let repo_mapping = match (
+        &print_build_args.repo_mapping,
+        &print_build_args.module_file,
+    ) {
+        (Some(path), _) => Some(RepoMapping::from_dump_file(path)?),
+        (None, Some(path)) => Some(RepoMapping::from_module_file(path)?),
+        (None, None) => None,
+    };
+    if let Some(repo_mapping) = repo_mapping {
+        apply_repo_mapping(&repo_mapping, &mut graph_data.build_mapping);
+    }
+    let label_rules = Arc::new(LabelRules::new(project_conf)?);
+    check_secondary_rule_for(project_conf)?;
+
+    let st = Instant::now();
     let mut current_files = async_find_all_build_files(opt, project_conf)
         .await
         .with_context(|| "Finding all build files")?;
+    timings.push(("find build files", st.elapsed()));
 
+    let excluded_files = graph_data.excluded_files;
+    let target_names = Arc::new(target_name_overrides(&graph_data.build_mapping)?);
+    let name_strategies = Arc::new(name_strategies(project_conf, &graph_data.build_mapping));
     let mut graph_nodes: HashMap<String, Vec<GraphNode>> = HashMap::default();
     for (entry, graph_node) in graph_data
         .build_mapping
@@ -1345,38 +2548,154 @@ pub async fn print_build(
         v.push(graph_node);
     }
 
+    // Excluded files are attached to the closest enclosing package we generate.
+    let mut excluded_by_element: HashMap<String, Vec<String>> = HashMap::default();
+    for f in excluded_files {
+        let mut directory = f.as_str();
+        while let Some(idx) = directory.rfind('/') {
+            directory = &directory[..idx];
+            if graph_nodes.contains_key(directory) {
+                excluded_by_element
+                    .entry(directory.to_string())
+                    .or_default()
+                    .push(f.clone());
+                break;
+            }
+        }
+    }
+
+    let st = Instant::now();
     let mut res = Vec::default();
     for (element, nodes) in graph_nodes {
+        let excluded_files = excluded_by_element.remove(&element).unwrap_or_default();
+        let target_names = target_names.clone();
+        let name_strategies = name_strategies.clone();
+        let label_rules = label_rules.clone();
         res.push(tokio::spawn(async move {
-            print_file(opt, project_conf, nodes, concurrent_io_operations, element).await
+            print_file(
+                opt,
+                project_conf,
+                nodes,
+                concurrent_io_operations,
+                element,
+                excluded_files,
+                target_names,
+                name_strategies,
+                label_rules,
+            )
+            .await
         }));
     }
 
+    let mut file_changes = Vec::default();
+    let mut banned = BannedUses::default();
     while let Some(nxt) = res.pop() {
-        let added_files = nxt.await??;
+        let (added_files, changes, banned_uses) = nxt.await??;
         for f in added_files.iter() {
             current_files.remove(f);
         }
+        file_changes.extend(changes);
+        for (label, sources) in banned_uses {
+            banned.entry(label).or_default().extend(sources);
+        }
+    }
+    timings.push(("write build files", st.elapsed()));
+    check_banned_labels(opt, project_conf, banned).await?;
+
+    // These files are old and not updated. Skip when using OverwriteTag (multi-language: other tags remain).
+    let st = Instant::now();
+    let mut deleted_files = Vec::default();
+    let mut kept_files = Vec::default();
+    let write_mode = WriteMode::new(opt.append, opt.merge, opt.overwrite.clone());
+    if matches!(write_mode, WriteMode::Overwrite | WriteMode::Merge) {
+        let keep_build_files = keep_build_files_globset(&project_conf.keep_build_files)?;
+        let tags: BTreeSet<&String> = project_conf
+            .configurations
+            .values()
+            .filter_map(|mc| mc.tag.as_ref())
+            .collect();
+        let mut current_files: Vec<PathBuf> = current_files.into_iter().collect();
+        current_files.sort();
+        for f in current_files {
+            let reason = if print_build_args.no_delete {
+                Some("--no-delete was passed")
+            } else if opt.output_root.is_some() {
+                Some("the output goes to --output-root")
+            } else {
+                let relative = f.strip_prefix(&opt.working_directory).unwrap_or(&f);
+                let content = std::fs::read_to_string(&f).unwrap_or_default();
+                keep_stale_build_file(relative, &content, &keep_build_files)
+            };
+            match reason {
+                Some(reason) => {
+                    println!("Keeping no longer used build file of: {:?}, {}", f, reason);
+                    kept_files.push((f, reason));
+                }
+                None => match clean_stale_build_file(opt, &f, &tags).await? {
+                    Some(change) => {
+                        println!("Keeping no longer used build file of: {:?}, it has targets this run didn't generate", f);
+                        file_changes.push(change);
+                        kept_files.push((f, "it has targets this run didn't generate"));
+                    }
+                    None => deleted_files.push(f),
+                },
+            }
+        }
     }
+    timings.push(("delete build files", st.elapsed()));
+
+    let out_of_date: Vec<&Path> = file_changes
+        .iter()
+        .filter(|c| !matches!(c, FileChange::Untouched(_)))
+        .map(|c| c.path())
+        .chain(deleted_files.iter().map(|f| f.as_path()))
+        .map(|f| f.strip_prefix(&opt.working_directory).unwrap_or(f))
+        .collect();
+    let check_result = if opt.check && !out_of_date.is_empty() {
+        Err(anyhow!(
+            "{} BUILD files are out of date:\n{}",
+            out_of_date.len(),
+            out_of_date
+                .iter()
+                .map(|f| format!("  {}", f.display()))
+                .collect::<Vec<_>>()
+                .join("\n")
+        ))
+    } else {
+        Ok(())
+    };
 
-    // These files are old and not updated. Skip when using OverwriteTag (multi-language: other tags remain).
-    let write_mode = WriteMode::new(opt.append, opt.overwrite.clone());
-    if matches!(write_mode, WriteMode::Overwrite) {
-        for f in current_files {
-            println!("Deleting no longer used build file of: {:?}", f);
-            std::fs::remove_file(&f)?;
+    if print_build_args.summary.is_some() || print_build_args.summary_text.is_some() {
+        let mut summary = RunSummary::new(
+            &opt.working_directory,
+            file_changes,
+            deleted_files,
+            kept_files,
+        );
+        for (phase, duration) in timings {
+            summary.add_timing(phase, duration);
+        }
+        if let Some(summary_out) = &print_build_args.summary {
+            write_json_file(summary_out.as_path(), &summary)?;
+        }
+        if let Some(summary_out) = &print_build_args.summary_text {
+            std::fs::write(summary_out, summary.to_text())
+                .with_context(|| format!("Writing run summary to {:?}", summary_out))?;
         }
     }
 
-    Ok(())
+    check_result
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::build_config::{BuildConfig, BuildLoad, GrpBuildConfig};
+    use crate::build_config::{BuildConfig, BuildLoad, ChildFilegroupConfig, GrpBuildConfig};
     use crate::build_graph::NodeType;
+    use crate::label_rewrite::LabelRewrite;
+    use crate::module_config::TestAttributes;
     use crate::Commands::PrintBuild;
+    use bzl_gen_build_python_utilities::starlark::target_attributes;
     use std::collections::BTreeMap;
 
     fn example_opt(no_aggregate_source: bool, write_mode: &WriteMode) -> Opt {
@@ -1387,12 +2706,22 @@ mod tests {
             cache_path: PathBuf::new(),
             no_aggregate_source: no_aggregate_source,
             append: write_mode == &WriteMode::Append,
+            merge: write_mode == &WriteMode::Merge,
+            force: false,
+            check: false,
+            build_file_name: None,
+            output_root: None,
             overwrite: match write_mode {
                 WriteMode::OverwriteTag(t) => Some(t.clone()),
                 _ => None,
             },
             command: PrintBuild(PrintBuildArgs {
                 graph_data: PathBuf::new(),
+                summary: None,
+                summary_text: None,
+                no_delete: false,
+                repo_mapping: None,
+                module_file: None,
             }),
         }
     }
@@ -1417,16 +2746,25 @@ mod tests {
                         test: None,
                         binary_application: None,
                         secondary_rules: BTreeMap::default(),
+                        secondary_rule_for: BTreeMap::default(),
+                        child_filegroup: Default::default(),
                     },
                     main_roots: vec!["src/main/protos".to_string()],
                     test_roots: vec!["src/test/protos".to_string()],
                     test_globs: vec![],
                     circular_dependency_allow_list: vec![],
+                    exclude_globs: vec![],
                     disable_format: false,
+                    per_file_tests: false,
+                    test_attributes: vec![],
+                    tag: None,
                 },
             )]),
             includes: vec![],
             path_directives: vec![],
+            keep_build_files: vec![],
+            label_rewrites: vec![],
+            banned_labels: vec![],
         }
     }
 
@@ -1481,16 +2819,25 @@ mod tests {
                                 },
                             ),
                         ]),
+                        secondary_rule_for: BTreeMap::default(),
+                        child_filegroup: Default::default(),
                     },
                     main_roots: vec!["src/main/protos".to_string()],
                     test_roots: vec!["src/test/protos".to_string()],
                     test_globs: vec![],
                     circular_dependency_allow_list: vec![],
+                    exclude_globs: vec![],
                     disable_format: false,
+                    per_file_tests: false,
+                    test_attributes: vec![],
+                    tag: None,
                 },
             )]),
             includes: vec![],
             path_directives: vec![],
+            keep_build_files: vec![],
+            label_rewrites: vec![],
+            banned_labels: vec![],
         }
     }
 
@@ -1508,7 +2855,43 @@ mod tests {
 
 filegroup(
     name='protos_files',
-    srcs=glob(include=['**/*.proto']),
+    srcs=glob(['**/*.proto']),
+    visibility=['//visibility:public']
+)
+
+proto_library(
+    name='protos',
+    srcs=[':protos_files'],
+    visibility=['//visibility:public']
+)
+        "#,
+            false,
+            WriteMode::Overwrite,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_generate_targets_with_excludes() -> Result<(), Box<dyn std::error::Error>> {
+        let build_graph = GraphNode {
+            node_type: NodeType::RealNode,
+            node_label: "src/main/protos".to_string(),
+            ..Default::default()
+        };
+        test_generate_targets_base_with_excludes(
+            example_project_conf(),
+            vec![build_graph],
+            "src/main/protos".to_string(),
+            &[
+                "src/main/protos/scratch.proto".to_string(),
+                "src/main/protos/fixtures/gen.proto".to_string(),
+            ],
+            2,
+            r#"load('@rules_proto//proto:defs.bzl', 'proto_library')
+
+filegroup(
+    name='protos_files',
+    srcs=glob(['**/*.proto'], exclude=['scratch.proto', 'fixtures/gen.proto']),
     visibility=['//visibility:public']
 )
 
@@ -1524,6 +2907,75 @@ proto_library(
         .await
     }
 
+    #[tokio::test]
+    async fn test_generate_targets_with_child_filegroup() -> Result<(), Box<dyn std::error::Error>>
+    {
+        let mut project_conf = example_project_conf();
+        let module_config = project_conf.configurations.get_mut("protos").unwrap();
+        module_config.file_extensions.push("protodevel".to_string());
+        module_config.build_config.child_filegroup = ChildFilegroupConfig {
+            name: "{folder}_srcs".to_string(),
+            function_name: "proto_srcs".to_string(),
+            headers: vec![BuildLoad {
+                load_from: "//build_tools:srcs.bzl".to_string(),
+                load_value: "proto_srcs".to_string(),
+            }],
+            extra_globs: vec!["**/*.json".to_string()],
+            write_mode: Some(WriteMode::Merge),
+        };
+        let build_graph = GraphNode {
+            node_type: NodeType::RealNode,
+            node_label: "src/main/protos".to_string(),
+            child_nodes: HashMap::from([(
+                "src/main/protos/sub".to_string(),
+                GraphNodeMetadata::default(),
+            )]),
+            ..Default::default()
+        };
+        let opt = Box::leak(Box::new(example_opt(false, &WriteMode::Overwrite)));
+        let boxed_project_conf = Box::leak(Box::new(project_conf));
+        let mut emitted_files: Vec<PathBuf> = Vec::default();
+        let (target_entries, _) = generate_targets(
+            opt,
+            boxed_project_conf,
+            None,
+            SourceConfig::Main,
+            &vec![build_graph],
+            &"src/main/protos".to_string(),
+            &[],
+            &HashMap::default(),
+            &HashMap::default(),
+            &LabelRules::default(),
+            &mut emitted_files,
+            &mut Vec::default(),
+            &mut BannedUses::default(),
+            |sub_target: PathBuf, t: TargetEntries, module_config: &'static ModuleConfig| async move {
+                assert_eq!(
+                    module_config.build_config.child_filegroup.write_mode,
+                    Some(WriteMode::Merge)
+                );
+                let generated = t.emit_build_file(None)?;
+                assert!(generated.contains("load(\"//build_tools:srcs.bzl\", \"proto_srcs\")"));
+                assert!(generated.contains("proto_srcs(\n    name = \"sub_srcs\""));
+                assert!(generated.contains(
+                    "glob([\n        \"**/*.proto\",\n        \"**/*.protodevel\",\n        \"**/*.json\",\n    ])"
+                ));
+                Ok(Some(FileChange::Untouched(sub_target)))
+            },
+        )
+        .await?;
+        assert_eq!(
+            emitted_files,
+            vec![PathBuf::from("src/main/protos/sub/BUILD.bazel")]
+        );
+        let generated = target_entries.emit_build_file(None)?;
+        assert!(generated.contains("proto_srcs(\n    name = \"protos_srcs\""));
+        assert!(generated.contains(
+            "srcs = [\n        \":protos_srcs\",\n        \"//src/main/protos/sub:sub_srcs\","
+        ));
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_generate_targets_with_secondaries() -> Result<(), Box<dyn std::error::Error>> {
         let mut node1 = GraphNode::default();
@@ -1537,50 +2989,513 @@ proto_library(
             .dependencies
             .push("src/main/protos/a.proto".to_string());
         test_generate_targets_base(
-            example_project_conf_with_secondaries(),
-            vec![node1, node2],
+            example_project_conf_with_secondaries(),
+            vec![node1, node2],
+            "src/main/protos".to_string(),
+            6,
+            r#"load('@com_google_protobuf//:protobuf.bzl', 'py_proto_library')
+load('@rules_proto//proto:defs.bzl', 'proto_library')
+
+proto_library(
+    name='a_proto',
+    srcs=['a.proto'],
+    visibility=['//visibility:public']
+)
+
+java_proto_library(
+    name='a_proto_java',
+    visibility=['//visibility:public'],
+    deps=[':a_proto'],
+)
+
+py_proto_library(
+    name='a_proto_py',
+    srcs=['a.proto'],
+    visibility=['//visibility:public'],
+    deps=[],
+)
+
+proto_library(
+    name='b_proto',
+    srcs=['b.proto'],
+    visibility=['//visibility:public'],
+    deps=[':a_proto'],
+)
+
+java_proto_library(
+    name='b_proto_java',
+    visibility=['//visibility:public'],
+    deps=[':b_proto'],
+)
+
+py_proto_library(
+    name='b_proto_py',
+    srcs=['b.proto'],
+    visibility=['//visibility:public'],
+    deps=[':a_proto_py'],
+)
+        "#,
+            true,
+            WriteMode::Overwrite,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_generate_targets_with_templates() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conf = example_project_conf_with_secondaries();
+        let build_config = &mut conf.configurations.get_mut("protos").unwrap().build_config;
+        let main = build_config.main.as_mut().unwrap();
+        main.extra_key_to_list =
+            HashMap::from([("data".to_string(), vec!["docs/${stem}.md".to_string()])]);
+        main.extra_key_to_value =
+            HashMap::from([("strip_import_prefix".to_string(), "/${package}".to_string())]);
+        build_config.secondary_rules = BTreeMap::from([(
+            "java".to_string(),
+            GrpBuildConfig {
+                headers: vec![],
+                function_name: "java_proto_library".to_string(),
+                target_name_strategy: TargetNameStrategy::Auto,
+                extra_key_to_list: HashMap::from([(
+                    "deps".to_string(),
+                    vec![
+                        ":${name}".to_string(),
+                        "${deps|replace:_proto:_proto_java}".to_string(),
+                    ],
+                )]),
+                extra_key_to_value: HashMap::default(),
+            },
+        )]);
+
+        let node1 = GraphNode {
+            node_label: "src/main/protos/a.proto".to_string(),
+            node_type: NodeType::RealNode,
+            ..Default::default()
+        };
+        let node2 = GraphNode {
+            node_label: "src/main/protos/b.proto".to_string(),
+            node_type: NodeType::RealNode,
+            dependencies: vec!["src/main/protos/a.proto".to_string()],
+            ..Default::default()
+        };
+        test_generate_targets_base(
+            conf,
+            vec![node1, node2],
+            "src/main/protos".to_string(),
+            4,
+            r#"load('@rules_proto//proto:defs.bzl', 'proto_library')
+
+proto_library(
+    name='a_proto',
+    srcs=['a.proto'],
+    data=['docs/a.md'],
+    strip_import_prefix='/src/main/protos',
+    visibility=['//visibility:public'],
+)
+
+java_proto_library(
+    name='a_proto_java',
+    visibility=['//visibility:public'],
+    deps=[':a_proto'],
+)
+
+proto_library(
+    name='b_proto',
+    srcs=['b.proto'],
+    data=['docs/b.md'],
+    strip_import_prefix='/src/main/protos',
+    visibility=['//visibility:public'],
+    deps=[':a_proto'],
+)
+
+java_proto_library(
+    name='b_proto_java',
+    visibility=['//visibility:public'],
+    deps=[':a_proto_java', ':b_proto'],
+)
+        "#,
+            true,
+            WriteMode::Overwrite,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_generate_targets_with_per_file_tests() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let element = "src/test/java/com/foo".to_string();
+        std::fs::create_dir_all(dir.path().join(&element))?;
+        for file in [
+            "FooTest.java",
+            "BarIntegrationTest.java",
+            "TestHelper.java",
+            "README.md",
+        ] {
+            std::fs::write(dir.path().join(&element).join(file), "")?;
+        }
+        let build_config = |function_name: &str| GrpBuildConfig {
+            headers: vec![],
+            function_name: function_name.to_string(),
+            target_name_strategy: TargetNameStrategy::SourceFileStem,
+            extra_key_to_list: HashMap::default(),
+            extra_key_to_value: HashMap::default(),
+        };
+        let project_conf = ProjectConf {
+            configurations: HashMap::from([(
+                "java".to_string(),
+                ModuleConfig {
+                    file_extensions: vec!["java".to_string()],
+                    build_config: BuildConfig {
+                        main: Some(build_config("java_library")),
+                        test: Some(build_config("java_test")),
+                        binary_application: None,
+                        secondary_rules: BTreeMap::default(),
+                        secondary_rule_for: BTreeMap::default(),
+                        child_filegroup: Default::default(),
+                    },
+                    main_roots: vec!["src/main/java".to_string()],
+                    test_roots: vec!["src/test/java".to_string()],
+                    test_globs: vec!["**/*Test.java".to_string()],
+                    per_file_tests: true,
+                    test_attributes: vec![TestAttributes {
+                        glob: "**/*IntegrationTest.java".to_string(),
+                        attributes: vec![
+                            "attr.string: size -> large".to_string(),
+                            "attr.int: shard_count -> 4".to_string(),
+                        ],
+                    }],
+                    ..Default::default()
+                },
+            )]),
+            includes: vec![],
+            path_directives: vec![],
+            keep_build_files: vec![],
+            label_rewrites: vec![],
+            banned_labels: vec![],
+        };
+        let node = GraphNode {
+            node_label: element.clone(),
+            node_type: NodeType::RealNode,
+            dependencies: vec!["src/main/java/com/foo".to_string()],
+            ..Default::default()
+        };
+        let mut opt = example_opt(false, &WriteMode::Overwrite);
+        opt.working_directory = dir.path().to_path_buf();
+        let (target_entries, _) = generate_targets(
+            Box::leak(Box::new(opt)),
+            Box::leak(Box::new(project_conf)),
+            None,
+            SourceConfig::Test,
+            &vec![node],
+            &element,
+            &[],
+            &HashMap::default(),
+            &HashMap::default(),
+            &LabelRules::default(),
+            &mut Vec::default(),
+            &mut Vec::default(),
+            &mut BannedUses::default(),
+            |sub_target: PathBuf, _t: TargetEntries, _module_config: &'static ModuleConfig| async move {
+                Ok(Some(FileChange::Untouched(sub_target)))
+            },
+        )
+        .await?;
+        let expected = r#"java_library(
+    name = "foo",
+    testonly = True,
+    srcs = ["TestHelper.java"],
+    visibility = ["//visibility:public"],
+    deps = ["//src/main/java/com/foo"],
+)
+
+java_test(
+    name = "BarIntegrationTest",
+    size = "large",
+    srcs = ["BarIntegrationTest.java"],
+    shard_count = 4,
+    visibility = ["//visibility:public"],
+    deps = [
+        ":foo",
+        "//src/main/java/com/foo",
+    ],
+)
+
+java_test(
+    name = "FooTest",
+    srcs = ["FooTest.java"],
+    visibility = ["//visibility:public"],
+    deps = [
+        ":foo",
+        "//src/main/java/com/foo",
+    ],
+)"#;
+        assert_eq!(
+            PythonProgram::parse(&target_entries.emit_build_file(None)?, "tmp.py")?.to_string(),
+            expected
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_generate_targets_with_attr_scalars() -> Result<(), Box<dyn std::error::Error>> {
+        let node = GraphNode {
+            node_label: "src/main/protos/a.proto".to_string(),
+            node_type: NodeType::RealNode,
+            node_metadata: GraphNodeMetadata {
+                attr_scalars: vec![
+                    AttrScalarConfig {
+                        attr_name: "testonly".to_string(),
+                        value: AttrValue::Bool(true),
+                    },
+                    AttrScalarConfig {
+                        attr_name: "shard_count".to_string(),
+                        value: AttrValue::Int(4),
+                    },
+                    AttrScalarConfig {
+                        attr_name: "size".to_string(),
+                        value: AttrValue::String("large".to_string()),
+                    },
+                ],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        test_generate_targets_base(
+            example_project_conf(),
+            vec![node],
+            "src/main/protos".to_string(),
+            1,
+            r#"load('@rules_proto//proto:defs.bzl', 'proto_library')
+
+proto_library(
+    name='a',
+    size='large',
+    testonly=True,
+    srcs=['a.proto'],
+    shard_count=4,
+    visibility=['//visibility:public'],
+)
+        "#,
+            true,
+            WriteMode::Overwrite,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_generate_targets_with_binaries() -> Result<(), Box<dyn std::error::Error>> {
+        let node = GraphNode {
+            node_label: "src/main/protos/a.proto".to_string(),
+            node_type: NodeType::RealNode,
+            node_metadata: GraphNodeMetadata {
+                binary_refs: vec![crate::directive::BinaryRefAndPath {
+                    entity_path: Some("src/main/protos/a.proto".to_string()),
+                    binary_refs: crate::directive::BinaryRefConfig {
+                        command: crate::directive::BinaryRefDirective::GenerateBinary,
+                        binary_name: "a_bin".to_string(),
+                        target_value: None,
+                    },
+                    inferred: false,
+                }],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        // Without a binary_application a binary asked for with a directive fails the run
+        assert!(test_generate_targets_base(
+            example_project_conf(),
+            vec![node.clone()],
+            "src/main/protos".to_string(),
+            2,
+            "",
+            true,
+            WriteMode::Overwrite,
+        )
+        .await
+        .is_err());
+
+        // while one the extractor added by itself is left out
+        let mut inferred = node.clone();
+        inferred.node_metadata.binary_refs[0].inferred = true;
+        test_generate_targets_base(
+            example_project_conf(),
+            vec![inferred],
+            "src/main/protos".to_string(),
+            1,
+            r#"load('@rules_proto//proto:defs.bzl', 'proto_library')
+
+proto_library(
+    name='a',
+    srcs=['a.proto'],
+    visibility=['//visibility:public'],
+)
+        "#,
+            true,
+            WriteMode::Overwrite,
+        )
+        .await?;
+
+        let mut project_conf = example_project_conf();
+        for module_config in project_conf.configurations.values_mut() {
+            module_config.build_config.binary_application = Some(GrpBuildConfig {
+                headers: vec![BuildLoad {
+                    load_from: "//build_tools:binary.bzl".to_string(),
+                    load_value: "proto_binary".to_string(),
+                }],
+                function_name: "proto_binary".to_string(),
+                target_name_strategy: TargetNameStrategy::SourceFileStem,
+                extra_key_to_list: HashMap::default(),
+                extra_key_to_value: HashMap::default(),
+            });
+        }
+        test_generate_targets_base(
+            project_conf,
+            vec![node],
+            "src/main/protos".to_string(),
+            2,
+            r#"load('@rules_proto//proto:defs.bzl', 'proto_library')
+load('//build_tools:binary.bzl', 'proto_binary')
+
+proto_binary(
+    name='a_bin',
+    entity_path='src/main/protos/a.proto',
+    owning_library=':a',
+    visibility=['//visibility:public'],
+)
+
+proto_library(
+    name='a',
+    srcs=['a.proto'],
+    visibility=['//visibility:public'],
+)
+        "#,
+            true,
+            WriteMode::Overwrite,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_generate_targets_with_target_names() -> Result<(), Box<dyn std::error::Error>> {
+        let mut project_conf = example_project_conf();
+        for module_config in project_conf.configurations.values_mut() {
+            if let Some(main) = module_config.build_config.main.as_mut() {
+                main.target_name_strategy = TargetNameStrategy::Template("{stem}_lib".to_string());
+            }
+        }
+        let node1 = GraphNode {
+            node_label: "src/main/protos/a.proto".to_string(),
+            node_type: NodeType::RealNode,
+            node_metadata: GraphNodeMetadata {
+                target_names: vec!["legacy_a".to_string()],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let node2 = GraphNode {
+            node_label: "src/main/protos/b.proto".to_string(),
+            node_type: NodeType::RealNode,
+            dependencies: vec![
+                "src/main/protos/a.proto".to_string(),
+                "src/main/protos/c.proto".to_string(),
+            ],
+            ..Default::default()
+        };
+        let node3 = GraphNode {
+            node_label: "src/main/protos/c.proto".to_string(),
+            node_type: NodeType::RealNode,
+            ..Default::default()
+        };
+        test_generate_targets_base(
+            project_conf,
+            vec![node1, node2, node3],
             "src/main/protos".to_string(),
-            6,
-            r#"load('@com_google_protobuf//:protobuf.bzl', 'py_proto_library')
-load('@rules_proto//proto:defs.bzl', 'proto_library')
+            3,
+            r#"load('@rules_proto//proto:defs.bzl', 'proto_library')
 
 proto_library(
-    name='a_proto',
+    name='legacy_a',
     srcs=['a.proto'],
     visibility=['//visibility:public']
 )
 
-java_proto_library(
-    name='a_proto_java',
-    visibility=['//visibility:public'],
-    deps=[':a_proto'],
-)
-
-py_proto_library(
-    name='a_proto_py',
-    srcs=['a.proto'],
-    visibility=['//visibility:public'],
-    deps=[],
-)
-
 proto_library(
-    name='b_proto',
+    name='b_lib',
     srcs=['b.proto'],
     visibility=['//visibility:public'],
-    deps=['//src/main/protos:a_proto'],
+    deps=[':c_lib', ':legacy_a'],
 )
 
-java_proto_library(
-    name='b_proto_java',
-    visibility=['//visibility:public'],
-    deps=[':b_proto'],
+proto_library(
+    name='c_lib',
+    srcs=['c.proto'],
+    visibility=['//visibility:public']
 )
+        "#,
+            true,
+            WriteMode::Overwrite,
+        )
+        .await
+    }
 
-py_proto_library(
-    name='b_proto_py',
-    srcs=['b.proto'],
-    visibility=['//visibility:public'],
-    deps=['//src/main/protos:a_proto_py'],
+    #[tokio::test]
+    async fn test_generate_targets_with_conditional_manual_refs(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let manual_ref = |command, target_value: &str, condition: Option<&str>| ManualRefConfig {
+            command,
+            target_value: target_value.to_string(),
+            condition: condition.map(|c| c.to_string()),
+        };
+        let node = GraphNode {
+            node_label: "src/main/protos/a.proto".to_string(),
+            node_type: NodeType::RealNode,
+            node_metadata: GraphNodeMetadata {
+                manual_refs: vec![
+                    manual_ref(
+                        directive::ManualRefDirective::Ref,
+                        "//third_party:base",
+                        None,
+                    ),
+                    manual_ref(
+                        directive::ManualRefDirective::Ref,
+                        "//third_party:cuda",
+                        Some("@platforms//os:linux"),
+                    ),
+                    manual_ref(
+                        directive::ManualRefDirective::Ref,
+                        "//third_party:metal",
+                        Some("@platforms//os:macos"),
+                    ),
+                    manual_ref(
+                        directive::ManualRefDirective::RuntimeRef,
+                        "//jdbc:postgres",
+                        Some("//config:prod"),
+                    ),
+                ],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        test_generate_targets_base(
+            example_project_conf(),
+            vec![node],
+            "src/main/protos".to_string(),
+            1,
+            r#"load('@rules_proto//proto:defs.bzl', 'proto_library')
+
+proto_library(
+    name = "a",
+    srcs = ["a.proto"],
+    visibility = ["//visibility:public"],
+    runtime_deps = select({
+        "//config:prod": ["//jdbc:postgres"],
+        "//conditions:default": [],
+    }),
+    deps = ["//third_party:base"] + select({
+        "@platforms//os:linux": ["//third_party:cuda"],
+        "@platforms//os:macos": ["//third_party:metal"],
+        "//conditions:default": [],
+    }),
 )
         "#,
             true,
@@ -1597,18 +3512,57 @@ py_proto_library(
         expected_build_file: &str,
         no_aggregate_source: bool,
         write_mode: WriteMode,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        test_generate_targets_base_with_excludes(
+            project_conf,
+            build_graph,
+            element,
+            &Vec::default(),
+            expected_target_count,
+            expected_build_file,
+            no_aggregate_source,
+            write_mode,
+        )
+        .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn test_generate_targets_base_with_excludes(
+        project_conf: ProjectConf,
+        build_graph: Vec<GraphNode>,
+        element: String,
+        excluded_files: &[String],
+        expected_target_count: usize,
+        expected_build_file: &str,
+        no_aggregate_source: bool,
+        write_mode: WriteMode,
     ) -> Result<(), Box<dyn std::error::Error>> {
         let mut emitted_files: Vec<PathBuf> = Vec::default();
         let opt = Box::leak(Box::new(example_opt(no_aggregate_source, &write_mode)));
         let boxed_project_conf = Box::leak(Box::new(project_conf));
+        let target_names = target_name_overrides(
+            &build_graph
+                .iter()
+                .map(|n| (n.node_label.clone(), n.clone()))
+                .collect(),
+        )?;
         let (target_entries, _) = generate_targets(
             opt,
             boxed_project_conf,
+            None,
             SourceConfig::Main,
             &build_graph,
             &element,
+            excluded_files,
+            &target_names,
+            &HashMap::default(),
+            &LabelRules::default(),
             &mut emitted_files,
-            |_sub_target: PathBuf, _t: TargetEntries, _disable_format: bool| async move { Ok(0) },
+            &mut Vec::default(),
+            &mut BannedUses::default(),
+            |sub_target: PathBuf, _t: TargetEntries, _module_config: &'static ModuleConfig| async move {
+                Ok(Some(FileChange::Untouched(sub_target)))
+            },
         )
         .await?;
         assert_eq!(target_entries.entries.len(), expected_target_count);
@@ -1643,6 +3597,521 @@ py_proto_library(
         assert!(out2.contains("py_library("));
         assert!(out2.contains("other stuff"));
         assert!(out2.contains("rest"));
+        // Section at the end of the file: keep a single trailing newline
+        let out3 = replace_tag_section(existing.trim_end_matches("\nrest"), tag, new_block, false);
+        assert!(out3.ends_with("no_hash\n"));
+        // Removing the section leaves everything else, and nothing when there's no section
+        let out4 = remove_tag_section(&out2, tag);
+        assert!(!out4.contains("BZL_GEN_BUILD_PY"));
+        assert!(out4.contains("other stuff"));
+        assert!(out4.contains("rest"));
+        assert_eq!(remove_tag_section("other stuff\n", tag), "other stuff\n");
+    }
+
+    #[test]
+    fn test_replace_appended_section() -> Result<()> {
+        let t = TargetEntries {
+            entries: vec![make_target_entry("foo")],
+        };
+        let hand_written = "# hand written\nexports_files([\"a.txt\"])\n";
+        let once = replace_appended_section(hand_written, &t)?;
+        let twice = replace_appended_section(&once, &t)?;
+        assert_eq!(once, twice);
+        assert!(twice.starts_with(hand_written));
+        assert_eq!(
+            twice
+                .matches("# ---- BEGIN BZL_GEN_BUILD_GENERATED_CODE")
+                .count(),
+            1
+        );
+
+        // Nothing left to generate removes the earlier block.
+        let emptied = replace_appended_section(&twice, &TargetEntries::default())?;
+        assert!(!emptied.contains("BZL_GEN_BUILD_GENERATED_CODE"));
+        assert!(emptied.starts_with(hand_written));
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_generated_targets() -> Result<()> {
+        let path = Path::new("BUILD.bazel");
+        let t = TargetEntries {
+            entries: vec![make_target_entry("foo"), make_target_entry("bar")],
+        };
+        let hand_written = "# hand written\ngenrule(\n    name = \"g\",\n    outs = [\"out.txt\"],\n    cmd = \"touch $@\",\n)\n";
+        let once = merge_generated_targets(hand_written, &t)?;
+        let twice = merge_generated_targets(&once, &t)?;
+        assert_eq!(once, twice);
+        // The load goes after the leading comments, the hand-written target stays as it is.
+        assert!(once.starts_with("# hand written\nload("));
+        assert!(once.contains(&hand_written["# hand written\n".len()..]));
+        assert!(once.contains("\n\n# ---- BEGIN BZL_GEN_BUILD_MERGED_TARGETS ---- sha256:"));
+        let section = generated_sections(&once)
+            .into_iter()
+            .find(|s| s.marker == MERGED_TARGETS_MARKER)
+            .unwrap();
+        assert_eq!(merged_target_names(section.body), vec!["foo", "bar"]);
+        verify_generated_sections(path, &once, false)?;
+
+        // Hand edits to what the generator owns are caught, `# keep` marks them as intended.
+        let edited = once.replacen("\"@jvm__io_circe__circe_core//:jar\",", "", 1);
+        assert!(verify_generated_sections(path, &edited, false).is_err());
+        let kept = once.replacen(
+            "\"@jvm__io_circe__circe_core//:jar\",",
+            "\"@jvm__io_circe__circe_core//:jar\",\n        \"//extra\",  # keep",
+            1,
+        );
+        verify_generated_sections(path, &kept, false)?;
+
+        // A target the generator doesn't produce anymore is removed, hand-written ones stay.
+        let t = TargetEntries {
+            entries: vec![make_target_entry("foo")],
+        };
+        let shrunk = merge_generated_targets(&kept, &t)?;
+        let targets = target_attributes(&shrunk)?;
+        assert_eq!(
+            targets.keys().collect::<Vec<_>>(),
+            vec!["foo", "g"],
+            "{}",
+            shrunk
+        );
+        assert!(targets["foo"]["deps"].contains(&"//extra".to_string()));
+
+        // Nothing left to generate leaves only the hand-written targets, without the section.
+        let emptied = merge_generated_targets(&shrunk, &TargetEntries::default())?;
+        assert!(!emptied.contains(MERGED_TARGETS_MARKER));
+        assert_eq!(
+            target_attributes(&emptied)?.keys().collect::<Vec<_>>(),
+            vec!["g"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_generated_sections() -> Result<()> {
+        let path = Path::new("BUILD.bazel");
+        let content = format!(
+            "# hand written\n{}",
+            generated_section(
+                "BZL_GEN_BUILD_GENERATED_CODE",
+                "py_library(name='foo', deps=['//a', '//b'])"
+            )
+        );
+        assert_eq!(generated_sections(&content).len(), 1);
+        verify_generated_sections(path, &content, false)?;
+
+        // Reformatting isn't an edit.
+        let reformatted = content.replace(
+            "py_library(name='foo', deps=['//a', '//b'])",
+            "py_library(\n    name = \"foo\",\n    deps = [\n        \"//a\",\n        \"//b\",\n    ],\n)",
+        );
+        verify_generated_sections(path, &reformatted, false)?;
+
+        let edited = content.replace("'//b'", "'//b', '//c'");
+        assert!(verify_generated_sections(path, &edited, false).is_err());
+        // Whitespace inside a string is part of the value.
+        let edited = content.replace("'foo'", "'fo o'");
+        assert!(verify_generated_sections(path, &edited, false).is_err());
+        // Comments don't start strings.
+        let commented = generated_section("BZL_GEN_BUILD_GENERATED_CODE", "# don't\nfoo(a = 'x')");
+        verify_generated_sections(path, &commented.replace("'x'", "\"x\""), false)?;
+        assert!(verify_generated_sections(path, &commented.replace("'x'", "'x '"), false).is_err());
+        verify_generated_sections(path, &edited, true)?;
+
+        // Sections written before markers carried a hash aren't checked.
+        let legacy = "# ---- BEGIN BZL_GEN_BUILD_GENERATED_CODE ---- no_hash\n\nanything\n\n# ---- END BZL_GEN_BUILD_GENERATED_CODE ---- no_hash\n";
+        verify_generated_sections(path, legacy, false)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_keep_stale_build_file() -> Result<()> {
+        assert!(format!(
+            "{:#}",
+            keep_build_files_globset(&["a/[".to_string()]).unwrap_err()
+        )
+        .contains("keep_build_files glob \"a/[\""));
+        let keep = keep_build_files_globset(&["third_party/**".to_string()])?;
+        let generated = TargetEntries {
+            entries: vec![make_target_entry("foo")],
+        }
+        .emit_build_file(None)?;
+        let path = Path::new("src/foo/BUILD.bazel");
+        assert_eq!(keep_stale_build_file(path, &generated, &keep), None);
+        assert_eq!(
+            keep_stale_build_file(path, "genrule(name = \"g\")\n", &keep),
+            Some("it wasn't generated by bzl_gen_build")
+        );
+        assert_eq!(
+            keep_stale_build_file(
+                path,
+                &format!(
+                    "# Owned by the data team\n# bzl_gen_build: keep_file\n\n{}",
+                    generated
+                ),
+                &keep
+            ),
+            Some("it has a keep_file header")
+        );
+        assert_eq!(
+            keep_stale_build_file(Path::new("third_party/x/BUILD"), &generated, &keep),
+            Some("it matches keep_build_files")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_clean_stale_build_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut opt = example_opt(false, &WriteMode::Overwrite);
+        opt.working_directory = dir.path().to_path_buf();
+        let t = TargetEntries {
+            entries: vec![make_target_entry("foo")],
+        };
+        let py = "PY".to_string();
+        let tags = BTreeSet::from([&py]);
+
+        // A hand-written target stays, the section appended after it goes.
+        let hand_written =
+            "genrule(\n    name = \"g\",\n    outs = [\"g.txt\"],\n    cmd = \"touch $@\",\n)\n";
+        let path = dir.path().join("a/BUILD.bazel");
+        std::fs::create_dir_all(dir.path().join("a"))?;
+        std::fs::write(
+            &path,
+            format!("{}\n{}", hand_written, t.emit_build_file(None)?),
+        )?;
+        assert!(matches!(
+            clean_stale_build_file(&opt, &path, &tags).await?,
+            Some(FileChange::Modified(_, _))
+        ));
+        let remaining = std::fs::read_to_string(&path)?;
+        assert!(remaining.contains("name = \"g\""));
+        assert!(!remaining.contains("BZL_GEN_BUILD"));
+        assert!(!remaining.contains("\"foo\""));
+
+        // Hand-written loads alone don't keep a file.
+        let (load_block, targets_block) = t.emit_build_file_tagged("PY")?;
+        let path = dir.path().join("b/BUILD.bazel");
+        std::fs::create_dir_all(dir.path().join("b"))?;
+        std::fs::write(
+            &path,
+            format!(
+                "load(\"//tools:defs.bzl\", \"x\")\n\n{}\n{}",
+                load_block, targets_block
+            ),
+        )?;
+        assert_eq!(clean_stale_build_file(&opt, &path, &tags).await?, None);
+        assert!(!path.exists());
+
+        // The sections of a tag outside of this run are left to it.
+        let (load_block, targets_block) = t.emit_build_file_tagged("PROTO")?;
+        let path = dir.path().join("c/BUILD.bazel");
+        std::fs::create_dir_all(dir.path().join("c"))?;
+        let proto_only = format!("{}\n{}", load_block, targets_block);
+        std::fs::write(&path, &proto_only)?;
+        clean_stale_build_file(&opt, &path, &tags).await?;
+        assert_eq!(std::fs::read_to_string(&path)?, proto_only);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_build_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut opt = example_opt(false, &WriteMode::Overwrite);
+        opt.working_directory = dir.path().to_path_buf();
+        let path = dir.path().join("BUILD.bazel");
+        let content = "py_library(\n    name = \"a\",\n    deps = [\"//x\"],\n)\n".to_string();
+        assert_eq!(
+            write_build_file(&opt, &path, content.clone()).await?,
+            FileChange::Created(path.clone())
+        );
+        assert_eq!(
+            write_build_file(&opt, &path, content.clone()).await?,
+            FileChange::Untouched(path.clone())
+        );
+        match write_build_file(&opt, &path, content.replace("//x", "//y")).await? {
+            FileChange::Modified(p, targets) => {
+                assert_eq!(p, path);
+                assert_eq!(targets.len(), 1);
+                assert_eq!(targets[0].name, "a");
+            }
+            other => panic!("Expected a modified file, got {:?}", other),
+        }
+
+        // With an output root the repo isn't touched, and changes are relative to the repo.
+        let output_root = tempfile::tempdir()?;
+        opt.output_root = Some(output_root.path().to_path_buf());
+        let nested = dir.path().join("src/b/BUILD.bazel");
+        assert_eq!(
+            write_build_file(&opt, &nested, content.clone()).await?,
+            FileChange::Created(nested.clone())
+        );
+        assert!(!nested.exists());
+        assert_eq!(
+            std::fs::read_to_string(output_root.path().join("src/b/BUILD.bazel"))?,
+            content
+        );
+
+        // In check mode nothing is written, the change is only reported.
+        opt.check = true;
+        let checked = dir.path().join("src/c/BUILD.bazel");
+        assert_eq!(
+            write_build_file(&opt, &checked, content.clone()).await?,
+            FileChange::Created(checked.clone())
+        );
+        assert!(!checked.exists());
+        assert!(!output_root.path().join("src/c/BUILD.bazel").exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_targets_with_nothing_to_write() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut opt = example_opt(false, &WriteMode::Overwrite);
+        opt.working_directory = dir.path().to_path_buf();
+        let path = dir.path().join("BUILD.bazel");
+        let empty = TargetEntries::default();
+
+        // No file without targets is created.
+        let change = write_targets(&opt, &path, &[(WriteMode::Overwrite, &empty)], false).await?;
+        assert_eq!(change, None);
+        assert!(!path.exists());
+
+        // An existing file is left for the stale BUILD file handling, rather than emptied.
+        let t = TargetEntries {
+            entries: vec![make_target_entry("foo")],
+        };
+        write_targets(&opt, &path, &[(WriteMode::Overwrite, &t)], false).await?;
+        let generated = std::fs::read_to_string(&path)?;
+        let change = write_targets(&opt, &path, &[(WriteMode::Overwrite, &empty)], false).await?;
+        assert_eq!(change, None);
+        assert_eq!(std::fs::read_to_string(&path)?, generated);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_print_file_with_tagged_configurations() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut opt = example_opt(true, &WriteMode::Overwrite);
+        opt.working_directory = dir.path().to_path_buf();
+        let opt = Box::leak(Box::new(opt));
+        let mut project_conf = example_project_conf();
+        let protos = project_conf.configurations.get_mut("protos").unwrap();
+        protos.main_roots = vec!["src".to_string()];
+        protos.tag = Some("PROTO".to_string());
+        protos.build_config.secondary_rules = BTreeMap::from([(
+            "py".to_string(),
+            GrpBuildConfig {
+                headers: vec![],
+                function_name: "py_proto_library".to_string(),
+                target_name_strategy: TargetNameStrategy::Auto,
+                extra_key_to_list: HashMap::from([(
+                    "deps".to_string(),
+                    vec![":${name}".to_string()],
+                )]),
+                extra_key_to_value: HashMap::default(),
+            },
+        )]);
+        protos.build_config.secondary_rule_for =
+            BTreeMap::from([("python".to_string(), "py".to_string())]);
+        project_conf.configurations.insert(
+            "python".to_string(),
+            ModuleConfig {
+                file_extensions: vec!["py".to_string()],
+                build_config: BuildConfig {
+                    main: Some(GrpBuildConfig {
+                        headers: vec![],
+                        function_name: "py_library".to_string(),
+                        target_name_strategy: TargetNameStrategy::Auto,
+                        extra_key_to_list: HashMap::default(),
+                        extra_key_to_value: HashMap::default(),
+                    }),
+                    ..Default::default()
+                },
+                main_roots: vec!["src".to_string()],
+                tag: Some("PY".to_string()),
+                ..Default::default()
+            },
+        );
+        let project_conf = Box::leak(Box::new(project_conf));
+        check_secondary_rule_for(project_conf)?;
+        let mut build_mapping = HashMap::from([
+            (
+                "src/a.py".to_string(),
+                GraphNode {
+                    node_type: NodeType::RealNode,
+                    node_label: "src/a.py".to_string(),
+                    configuration: Some("python".to_string()),
+                    dependencies: vec!["src/b.proto".to_string()],
+                    ..Default::default()
+                },
+            ),
+            (
+                "src/b.proto".to_string(),
+                GraphNode {
+                    node_type: NodeType::RealNode,
+                    node_label: "src/b.proto".to_string(),
+                    configuration: Some("protos".to_string()),
+                    ..Default::default()
+                },
+            ),
+        ]);
+        let name_strategies = Arc::new(name_strategies(project_conf, &build_mapping));
+        let concurrent_io_operations = Box::leak(Box::new(Semaphore::new(1)));
+        let path = dir.path().join("src/BUILD.bazel");
+        std::fs::create_dir_all(dir.path().join("src"))?;
+        let hand_written = "# hand written\nexports_files([\"c.txt\"])\n";
+        std::fs::write(&path, hand_written)?;
+
+        let print = |build_mapping: &HashMap<String, GraphNode>| {
+            print_file(
+                opt,
+                project_conf,
+                build_mapping.values().cloned().collect(),
+                concurrent_io_operations,
+                "src".to_string(),
+                vec![],
+                Arc::new(HashMap::default()),
+                name_strategies.clone(),
+                Arc::new(LabelRules::new(project_conf).unwrap()),
+            )
+        };
+        for _ in 0..2 {
+            print(&build_mapping).await?;
+        }
+        let content = std::fs::read_to_string(&path)?;
+        assert!(content.contains(hand_written));
+        assert_eq!(
+            content
+                .matches("# ---- BEGIN BZL_GEN_BUILD_PY_GENERATED_CODE")
+                .count(),
+            1
+        );
+        assert!(content.contains("# ---- BEGIN BZL_GEN_BUILD_LOAD_PROTO_GENERATED_CODE"));
+        assert!(content.contains("proto_library(\n    name = \"b\","));
+        // The python target depends on the secondary target the protos generate for python.
+        assert!(content.contains("py_library(\n    name = \"a_py\","));
+        assert!(content.contains("    deps = [\":b_py\"],"));
+
+        // The sections of a configuration without sources any more go away.
+        build_mapping.remove("src/b.proto");
+        build_mapping.get_mut("src/a.py").unwrap().dependencies = vec![];
+        print(&build_mapping).await?;
+        let content = std::fs::read_to_string(&path)?;
+        assert!(content.contains(hand_written));
+        assert!(content.contains("# ---- BEGIN BZL_GEN_BUILD_PY_GENERATED_CODE"));
+        assert!(!content.contains("PROTO_GENERATED_CODE"));
+        assert!(!content.contains("proto_library"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_label_rewrites_and_banned_labels() -> Result<()> {
+        let rewrite = |from: &str, to: &str| LabelRewrite {
+            from: from.to_string(),
+            to: to.to_string(),
+        };
+        let label_rules = LabelRules {
+            rewriter: LabelRewriter::new(&[
+                rewrite("@maven//:log4j", "//platform/logging"),
+                rewrite("//src/old:old", "//src/new"),
+                rewrite("@pip//*", "//third_party/python:*"),
+            ])?,
+            banned: vec![
+                EntityPattern::with_wildcards("@maven//:commons*")?,
+                EntityPattern::with_wildcards("//platform/*")?,
+                EntityPattern::with_wildcards("@legacy//:legacy")?,
+            ],
+        };
+
+        // Rules match the label however it's written
+        assert_eq!(
+            label_rules.rewrite("//src/old"),
+            Some("//src/new".to_string())
+        );
+        assert_eq!(
+            label_rules.rewrite("@maven//:log4j"),
+            Some("//platform/logging".to_string())
+        );
+        assert!(label_rules.is_banned("@legacy"));
+        assert!(!label_rules.is_banned("//src/new"));
+
+        let mut banned = BannedUses::default();
+        let mut extra_kv_pairs = HashMap::from([
+            (
+                "deps".to_string(),
+                vec![
+                    "@pip//numpy".to_string(),
+                    "//src/old:old".to_string(),
+                    "@legacy".to_string(),
+                ],
+            ),
+            ("data".to_string(), vec!["@maven//:log4j".to_string()]),
+            ("srcs".to_string(), vec!["@maven//:log4j".to_string()]),
+        ]);
+        let mut extra_kv_selects = BTreeMap::from([(
+            "runtime_deps".to_string(),
+            BTreeMap::from([(
+                "//conditions:default".to_string(),
+                vec!["@maven//:commons_lang".to_string()],
+            )]),
+        )]);
+        label_rules.apply(
+            &["src/a".to_string()],
+            &mut extra_kv_pairs,
+            &mut extra_kv_selects,
+            &mut banned,
+        );
+        assert_eq!(
+            extra_kv_pairs["deps"],
+            vec!["//third_party/python:numpy", "//src/new", "@legacy"]
+        );
+        assert_eq!(extra_kv_pairs["data"], vec!["//platform/logging"]);
+        // Only dependency attributes are rewritten
+        assert_eq!(extra_kv_pairs["srcs"], vec!["@maven//:log4j"]);
+
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir_all(dir.path().join("src/a"))?;
+        std::fs::write(dir.path().join("src/a/x.proto"), "")?;
+        std::fs::write(dir.path().join("src/a/y.proto"), "")?;
+        std::fs::write(dir.path().join("src/a/README.md"), "")?;
+        banned
+            .entry("@legacy".to_string())
+            .or_default()
+            .insert("src/b/z.proto".to_string());
+        let mut opt = example_opt(false, &WriteMode::Overwrite);
+        opt.working_directory = dir.path().to_path_buf();
+        let err = check_banned_labels(&opt, &example_project_conf(), banned)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Generated targets depend on banned labels, the BUILD files using them weren't written:\n  //platform/logging is used by src/a/x.proto, src/a/y.proto\n  @legacy is used by src/a/x.proto, src/a/y.proto, src/b/z.proto\n  @maven//:commons_lang is used by src/a/x.proto, src/a/y.proto"
+        );
+        assert!(
+            check_banned_labels(&opt, &example_project_conf(), BannedUses::default())
+                .await
+                .is_ok()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_file_path() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut opt = example_opt(false, &WriteMode::Overwrite);
+        assert_eq!(
+            build_file_path(&opt, dir.path()),
+            dir.path().join("BUILD.bazel")
+        );
+        std::fs::write(dir.path().join("BUILD"), "")?;
+        assert_eq!(build_file_path(&opt, dir.path()), dir.path().join("BUILD"));
+        opt.build_file_name = Some("BUILD.gen.bazel".to_string());
+        assert_eq!(
+            build_file_path(&opt, dir.path()),
+            dir.path().join("BUILD.gen.bazel")
+        );
+        Ok(())
     }
 
     #[test]
@@ -1650,7 +4119,7 @@ py_proto_library(
         let python_source = r#"load("//build_tools/lang_support/scala/test:scalatest.bzl", "scala_tests")
 scala_tests(
     name = "scala_extractor",
-    srcs = glob(include =  ["*.scala"]),
+    srcs = glob(["*.scala"]),
     visibility = ["//visibility:public"],
     deps = [
         "//src/main/scala/com/example/scala_extractor",
@@ -1683,6 +4152,71 @@ scala_tests(
         );
     }
 
+    #[test]
+    fn test_emit_build_file_like_buildifier() {
+        // Expected as buildifier --type=build formats it, so --mode=check passes without
+        // `# buildifier: disable=format`.
+        let mut entry = make_target_entry("scala_extractor");
+        entry.srcs = Some(SrcType::Glob {
+            include: vec!["*.scala".to_string(), "*.java".to_string()],
+            exclude: vec!["Scratch.scala".to_string()],
+        });
+        entry.visibility = Some(Arc::new("//visibility:public".to_string()));
+        entry
+            .extra_kv_pairs
+            .push(("data".to_string(), vec!["a \"b\".txt".to_string()]));
+        let second = TargetEntry {
+            srcs: Some(SrcType::Glob {
+                include: vec!["*.scala".to_string()],
+                exclude: Vec::default(),
+            }),
+            ..make_target_entry("other")
+        };
+        let target_entries = TargetEntries {
+            entries: vec![entry, second],
+        };
+
+        let generated = target_entries.emit_build_file(None).unwrap();
+        let code = generated
+            .lines()
+            .filter(|l| !l.starts_with("# ---- "))
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!(
+            code.trim(),
+            r#"load("//build_tools/lang_support/scala/test:scalatest.bzl", "scala_tests")
+
+scala_tests(
+    name = "scala_extractor",
+    srcs = glob(
+        [
+            "*.scala",
+            "*.java",
+        ],
+        exclude = ["Scratch.scala"],
+    ),
+    data = ["a \"b\".txt"],
+    visibility = ["//visibility:public"],
+    deps = [
+        "//src/main/scala/com/example/scala_extractor",
+        "@jvm__io_circe__circe_core//:jar",
+        "@jvm__org_scalacheck__scalacheck//:jar",
+    ],
+)
+
+scala_tests(
+    name = "other",
+    srcs = glob(["*.scala"]),
+    visibility = ["//visibility:public"],
+    deps = [
+        "//src/main/scala/com/example/scala_extractor",
+        "@jvm__io_circe__circe_core//:jar",
+        "@jvm__org_scalacheck__scalacheck//:jar",
+    ],
+)"#
+        );
+    }
+
     fn make_target_entry(name: &str) -> TargetEntry {
         let mut required_load = HashMap::new();
         required_load.insert(
@@ -1707,6 +4241,8 @@ scala_tests(
             }),
             target_type: Arc::new("scala_tests".to_string()),
             extra_k_strs: Vec::default(),
+            extra_k_scalars: Vec::default(),
+            extra_kv_selects: Vec::default(),
         }
     }
 
@@ -1834,6 +4370,89 @@ scala_tests(
             );
         }
     }
+    #[test]
+    fn test_maybe_label_buildifier_order() {
+        let mut labels: Vec<MaybeLabel> = vec![
+            "@foo//:foo",
+            "//a/b",
+            "@@//com/example:a",
+            "//a-b",
+            ":x",
+            "@foo",
+            "//a/b:c",
+            "plain",
+            "//a/b",
+        ]
+        .into_iter()
+        .map(MaybeLabel::from_str)
+        .collect();
+        labels.sort();
+        labels.dedup_by(|a, b| Ord::cmp(&*a, &*b).is_eq());
+        let printed: Vec<String> = labels.into_iter().map(|l| l.to_string()).collect();
+        assert_eq!(
+            printed,
+            vec![
+                "plain",
+                ":x",
+                "//a-b",
+                "//a/b",
+                "//a/b:c",
+                "@@//com/example:a",
+                "@foo"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_maybe_label_relative_to() {
+        let relative = |label: &str| {
+            MaybeLabel::from_str(label)
+                .relative_to("com/example")
+                .to_string()
+        };
+        assert_eq!(relative("//com/example:foo"), ":foo");
+        assert_eq!(relative("//com/example"), ":example");
+        assert_eq!(relative("//com/example/sub:foo"), "//com/example/sub:foo");
+        assert_eq!(relative("//com:example"), "//com:example");
+        assert_eq!(
+            relative("@other//com/example:foo"),
+            "@other//com/example:foo"
+        );
+        assert_eq!(relative(":foo"), ":foo");
+        assert_eq!(relative("@//com/example:foo"), ":foo");
+        assert_eq!(relative("@@//com/example:foo"), ":foo");
+        assert_eq!(
+            relative("@@//com/example/sub:foo"),
+            "@@//com/example/sub:foo"
+        );
+        assert_eq!(
+            relative("@@other//com/example:foo"),
+            "@@other//com/example:foo"
+        );
+    }
+
+    #[test]
+    fn test_compare_load_labels() {
+        let mut loads = vec![
+            "//build_tools/lang_support/scala:scala.bzl",
+            ":defs.bzl",
+            "@rules_python//python:defs.bzl",
+            "//build_tools:rules.bzl",
+            "@io_bazel_rules_scala//scala:scala.bzl",
+        ];
+        loads.sort_by(|a, b| compare_load_labels(a, b));
+        assert_eq!(
+            loads,
+            vec![
+                "@io_bazel_rules_scala//scala:scala.bzl",
+                "@rules_python//python:defs.bzl",
+                ":defs.bzl",
+                "//build_tools:rules.bzl",
+                "//build_tools/lang_support/scala:scala.bzl",
+            ]
+        );
+    }
+
     #[test]
     fn test_maybe_label_to_string() {
         let test_cases = vec![
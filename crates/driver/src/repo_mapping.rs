@@ -0,0 +1,225 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use bzl_gen_build_python_utilities::starlark::starlark_calls;
+
+/// Maps canonical repository names, as found in Bzlmod labels like
+/// `@@rules_jvm_external~~maven~maven//:guava`, to the apparent names the main repository uses
+/// for them, e.g. `@maven//:guava`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RepoMapping {
+    // canonical name to apparent name, from `bazel mod dump_repo_mapping`
+    canonical: HashMap<String, String>,
+    // module name to the apparent name of its bazel_dep
+    modules: HashMap<String, String>,
+    // (module defining the extension, extension name, repo name) to the apparent name of its
+    // use_repo, the module is empty for extensions of the main repository
+    extension_repos: HashMap<(String, String, String), String>,
+}
+
+impl RepoMapping {
+    /// Reads the JSON written by `bazel mod dump_repo_mapping ''`, which maps every apparent
+    /// name visible from the main repository to its canonical name.
+    pub fn from_dump_file(path: &Path) -> Result<RepoMapping> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Reading repo mapping {:?}", path))?;
+        let apparent_to_canonical: BTreeMap<String, String> = serde_json::from_str(&content)
+            .with_context(|| format!("Parsing repo mapping {:?}", path))?;
+        let mut mapping = RepoMapping::default();
+        // BTreeMap order makes the first (shortest) apparent name win for aliased repos
+        for (apparent, canonical) in apparent_to_canonical.into_iter() {
+            mapping.canonical.entry(canonical).or_insert(apparent);
+        }
+        Ok(mapping)
+    }
+
+    /// Works out the mapping from the `bazel_dep`, `use_extension` and `use_repo` calls of
+    /// MODULE.bazel. Canonical names are matched the way Bazel builds them, so both the
+    /// `module~version~extension~repo` and `module+extension+repo` forms are understood.
+    pub fn from_module_file(path: &Path) -> Result<RepoMapping> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Reading module file {:?}", path))?;
+        RepoMapping::from_module_content(&content)
+            .with_context(|| format!("Parsing module file {:?}", path))
+    }
+
+    fn from_module_content(content: &str) -> Result<RepoMapping> {
+        let calls = starlark_calls(content)?;
+
+        let mut mapping = RepoMapping::default();
+        let mut apparent_to_module: HashMap<String, String> = HashMap::default();
+        for call in calls.iter().filter(|c| c.function == "bazel_dep") {
+            if let Some(name) = call.keyword("name") {
+                let apparent = call.keyword("repo_name").unwrap_or(name);
+                apparent_to_module.insert(apparent.to_string(), name.to_string());
+                mapping
+                    .modules
+                    .insert(name.to_string(), apparent.to_string());
+            }
+        }
+
+        // variable name to the module defining the extension and the extension name
+        let mut extensions: HashMap<String, (String, String)> = HashMap::default();
+        for call in calls.iter().filter(|c| c.function == "use_extension") {
+            if let (Some(variable), [bzl_file, extension, ..]) =
+                (&call.assigned_to, call.positional().as_slice())
+            {
+                let module = match bzl_file.strip_prefix('@') {
+                    Some(rest) => {
+                        let apparent = rest.split("//").next().unwrap_or_default();
+                        apparent_to_module
+                            .get(apparent)
+                            .cloned()
+                            .unwrap_or_else(|| apparent.to_string())
+                    }
+                    None => String::default(),
+                };
+                extensions.insert(variable.clone(), (module, extension.to_string()));
+            }
+        }
+
+        for call in calls.iter().filter(|c| c.function == "use_repo") {
+            let Some(variable) = call.args.first() else {
+                continue;
+            };
+            let Some((module, extension)) = extensions.get(&variable.value) else {
+                continue;
+            };
+            for arg in call.args.iter().skip(1) {
+                let apparent = arg.key.clone().unwrap_or_else(|| arg.value.clone());
+                mapping.extension_repos.insert(
+                    (module.clone(), extension.clone(), arg.value.clone()),
+                    apparent,
+                );
+            }
+        }
+        Ok(mapping)
+    }
+
+    fn apparent_name(&self, canonical: &str) -> Option<String> {
+        if let Some(apparent) = self.canonical.get(canonical) {
+            return Some(apparent.clone());
+        }
+        let separator = if canonical.contains('+') { '+' } else { '~' };
+        let parts: Vec<&str> = canonical.split(separator).collect();
+        match parts.as_slice() {
+            [module] | [module, _] => self.modules.get(*module).cloned(),
+            [module, .., extension, repo] => {
+                // Bazel 7 names the main repository _main
+                let module = if *module == "_main" { "" } else { *module };
+                self.extension_repos
+                    .get(&(module.to_string(), extension.to_string(), repo.to_string()))
+                    .cloned()
+            }
+            [] => None,
+        }
+    }
+
+    /// The label with its canonical repository name replaced by the apparent one, labels in the
+    /// main repository lose the repository part altogether. Labels that don't use a canonical
+    /// name are returned as they are, None if the canonical name isn't visible from the main
+    /// repository.
+    pub fn apparent_label(&self, label: &str) -> Option<String> {
+        let Some(rest) = label.strip_prefix("@@") else {
+            return Some(label.to_string());
+        };
+        let (canonical, target) = match rest.find("//") {
+            Some(idx) => (&rest[..idx], &rest[idx..]),
+            None => (rest, ""),
+        };
+        if canonical.is_empty() {
+            return Some(target.to_string());
+        }
+        let apparent = self.apparent_name(canonical)?;
+        if target.is_empty() {
+            Some(format!("@{}", apparent))
+        } else {
+            Some(format!("@{}{}", apparent, target))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_module_file_repos() {
+        let mapping = RepoMapping::from_module_content(
+            r#"
+module(name = "example", version = "0.0.0")
+
+bazel_dep(name = "rules_python", version = "1.8.1")
+bazel_dep(name = "rules_jvm_external", version = "6.8", repo_name = "jvm_external")
+
+pip = use_extension("@rules_python//python/extensions:pip.bzl", "pip")
+use_repo(pip, "pip")
+
+maven = use_extension("@jvm_external//:extensions.bzl", "maven")
+use_repo(maven, mvn = "maven")
+
+local = use_extension("//build_tools:local.bzl", "local")
+use_repo(local, "tools")
+"#,
+        )
+        .unwrap();
+        let cases = vec![
+            ("@@rules_jvm_external~~maven~maven//:guava", "@mvn//:guava"),
+            ("@@rules_jvm_external++maven+maven//:guava", "@mvn//:guava"),
+            (
+                "@@rules_jvm_external~6.8~maven~maven//:guava",
+                "@mvn//:guava",
+            ),
+            ("@@rules_python++pip+pip//pandas", "@pip//pandas"),
+            (
+                "@@rules_python~//python:defs.bzl",
+                "@rules_python//python:defs.bzl",
+            ),
+            (
+                "@@rules_jvm_external+//:defs.bzl",
+                "@jvm_external//:defs.bzl",
+            ),
+            ("@@+local+tools//:bin", "@tools//:bin"),
+            ("@@_main~local~tools//:bin", "@tools//:bin"),
+            ("@@//com/example:foo", "//com/example:foo"),
+            ("@maven//:guava", "@maven//:guava"),
+            ("//com/example:foo", "//com/example:foo"),
+        ];
+        for (label, expected) in cases {
+            assert_eq!(
+                mapping.apparent_label(label),
+                Some(expected.to_string()),
+                "{}",
+                label
+            );
+        }
+        assert_eq!(
+            mapping.apparent_label("@@rules_python++pip+pip_310_pandas//:pkg"),
+            None
+        );
+    }
+
+    #[test]
+    fn maps_dumped_repos() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("repo_mapping.json");
+        std::fs::write(
+            &path,
+            r#"{"": "", "example": "", "maven": "rules_jvm_external++maven+maven", "bazel_tools": "bazel_tools"}"#,
+        )?;
+        let mapping = RepoMapping::from_dump_file(&path)?;
+        assert_eq!(
+            mapping.apparent_label("@@rules_jvm_external++maven+maven//:guava"),
+            Some("@maven//:guava".to_string())
+        );
+        assert_eq!(
+            mapping.apparent_label("@@bazel_tools//tools/jdk"),
+            Some("@bazel_tools//tools/jdk".to_string())
+        );
+        assert_eq!(mapping.apparent_label("@@other+//:x"), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,371 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use bzl_gen_build_python_utilities::starlark::target_attributes;
+use serde::{Deserialize, Serialize};
+
+/// What a `print-build` run did to the BUILD files it owns, see `--summary` and
+/// `--summary-text`.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct RunSummary {
+    pub created: Vec<String>,
+    pub modified: Vec<ModifiedFile>,
+    pub deleted: Vec<String>,
+    // Stale BUILD files that were left in place, and why.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub kept: Vec<KeptFile>,
+    pub untouched: Vec<String>,
+    pub timings: Vec<PhaseTiming>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct KeptFile {
+    pub path: String,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct ModifiedFile {
+    pub path: String,
+    pub targets: Vec<TargetDiff>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetChange {
+    Added,
+    Removed,
+    Modified,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct TargetDiff {
+    pub name: String,
+    pub change: TargetChange,
+    pub attributes: Vec<AttributeDiff>,
+}
+
+/// Entries added to and removed from one attribute. A changed scalar value shows up as its old
+/// value removed and its new value added.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct AttributeDiff {
+    pub attribute: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub added: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct PhaseTiming {
+    pub phase: String,
+    pub millis: u128,
+}
+
+/// The outcome of writing a single BUILD file.
+#[derive(Debug, PartialEq, Eq)]
+pub enum FileChange {
+    Created(PathBuf),
+    Modified(PathBuf, Vec<TargetDiff>),
+    Untouched(PathBuf),
+}
+
+impl FileChange {
+    pub fn path(&self) -> &Path {
+        match self {
+            FileChange::Created(p) | FileChange::Modified(p, _) | FileChange::Untouched(p) => p,
+        }
+    }
+
+    // Merges two writes of the same file in one run, the first one decides whether it was created.
+    fn combine(self, later: FileChange) -> FileChange {
+        match (self, later) {
+            (FileChange::Untouched(_), later) => later,
+            (FileChange::Created(p), _) => FileChange::Created(p),
+            (FileChange::Modified(p, mut diffs), FileChange::Modified(_, more)) => {
+                diffs.extend(more);
+                FileChange::Modified(p, diffs)
+            }
+            (earlier, _) => earlier,
+        }
+    }
+}
+
+/// Compares the targets of two versions of a BUILD file.
+pub fn diff_targets(before: &str, after: &str) -> Vec<TargetDiff> {
+    // a file we can't parse has no targets we could compare
+    let before = target_attributes(before).unwrap_or_default();
+    let mut after = target_attributes(after).unwrap_or_default();
+    let mut diffs = Vec::default();
+    for (name, before_attrs) in before.into_iter() {
+        match after.remove(&name) {
+            Some(after_attrs) => {
+                let attributes = diff_attributes(before_attrs, after_attrs);
+                if !attributes.is_empty() {
+                    diffs.push(TargetDiff {
+                        name,
+                        change: TargetChange::Modified,
+                        attributes,
+                    });
+                }
+            }
+            None => diffs.push(TargetDiff {
+                name,
+                change: TargetChange::Removed,
+                attributes: Vec::default(),
+            }),
+        }
+    }
+    for (name, after_attrs) in after.into_iter() {
+        diffs.push(TargetDiff {
+            name,
+            change: TargetChange::Added,
+            attributes: diff_attributes(BTreeMap::default(), after_attrs),
+        });
+    }
+    diffs.sort_by(|a, b| a.name.cmp(&b.name));
+    diffs
+}
+
+fn diff_attributes(
+    mut before: BTreeMap<String, Vec<String>>,
+    after: BTreeMap<String, Vec<String>>,
+) -> Vec<AttributeDiff> {
+    let mut diffs = Vec::default();
+    for (attribute, after_values) in after.into_iter() {
+        let before_values: BTreeSet<String> = before
+            .remove(&attribute)
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        let after_values: BTreeSet<String> = after_values.into_iter().collect();
+        let added: Vec<String> = after_values.difference(&before_values).cloned().collect();
+        let removed: Vec<String> = before_values.difference(&after_values).cloned().collect();
+        if !added.is_empty() || !removed.is_empty() {
+            diffs.push(AttributeDiff {
+                attribute,
+                added,
+                removed,
+            });
+        }
+    }
+    for (attribute, before_values) in before.into_iter() {
+        diffs.push(AttributeDiff {
+            attribute,
+            added: Vec::default(),
+            removed: before_values,
+        });
+    }
+    diffs.sort_by(|a, b| a.attribute.cmp(&b.attribute));
+    diffs
+}
+
+impl RunSummary {
+    /// Builds the summary from every file written in the run, with paths relative to
+    /// `working_directory`.
+    pub fn new(
+        working_directory: &Path,
+        changes: Vec<FileChange>,
+        deleted: Vec<PathBuf>,
+        kept: Vec<(PathBuf, &str)>,
+    ) -> RunSummary {
+        let relative = |p: &Path| {
+            p.strip_prefix(working_directory)
+                .unwrap_or(p)
+                .display()
+                .to_string()
+        };
+        let mut by_path: BTreeMap<PathBuf, FileChange> = BTreeMap::default();
+        for change in changes.into_iter() {
+            let path = change.path().to_path_buf();
+            let combined = match by_path.remove(&path) {
+                Some(earlier) => earlier.combine(change),
+                None => change,
+            };
+            by_path.insert(path, combined);
+        }
+
+        let mut summary = RunSummary::default();
+        for (path, change) in by_path.into_iter() {
+            match change {
+                FileChange::Created(_) => summary.created.push(relative(&path)),
+                FileChange::Modified(_, targets) => summary.modified.push(ModifiedFile {
+                    path: relative(&path),
+                    targets,
+                }),
+                FileChange::Untouched(_) => summary.untouched.push(relative(&path)),
+            }
+        }
+        summary.deleted = deleted.iter().map(|p| relative(p)).collect();
+        summary.deleted.sort();
+        summary.kept = kept
+            .into_iter()
+            .map(|(p, reason)| KeptFile {
+                path: relative(&p),
+                reason: reason.to_string(),
+            })
+            .collect();
+        summary.kept.sort_by(|a, b| a.path.cmp(&b.path));
+        summary
+    }
+
+    pub fn add_timing(&mut self, phase: &str, duration: Duration) {
+        self.timings.push(PhaseTiming {
+            phase: phase.to_string(),
+            millis: duration.as_millis(),
+        });
+    }
+
+    /// A human readable version of the summary, e.g. for a pull request description.
+    pub fn to_text(&self) -> String {
+        let mut out = String::default();
+        let _ = writeln!(
+            out,
+            "BUILD files: {} created, {} modified, {} deleted, {} untouched",
+            self.created.len(),
+            self.modified.len(),
+            self.deleted.len(),
+            self.untouched.len()
+        );
+        for path in self.created.iter() {
+            let _ = writeln!(out, "\ncreated {}", path);
+        }
+        for file in self.modified.iter() {
+            let _ = writeln!(out, "\nmodified {}", file.path);
+            for target in file.targets.iter() {
+                let change = match target.change {
+                    TargetChange::Added => "added",
+                    TargetChange::Removed => "removed",
+                    TargetChange::Modified => "modified",
+                };
+                let _ = writeln!(out, "  {} :{}", change, target.name);
+                if target.change == TargetChange::Added {
+                    continue;
+                }
+                for attribute in target.attributes.iter() {
+                    let _ = writeln!(out, "    {}", attribute.attribute);
+                    for value in attribute.removed.iter() {
+                        let _ = writeln!(out, "      - {}", value);
+                    }
+                    for value in attribute.added.iter() {
+                        let _ = writeln!(out, "      + {}", value);
+                    }
+                }
+            }
+        }
+        for path in self.deleted.iter() {
+            let _ = writeln!(out, "\ndeleted {}", path);
+        }
+        for file in self.kept.iter() {
+            let _ = writeln!(out, "\nkept {}, {}", file.path, file.reason);
+        }
+        if !self.timings.is_empty() {
+            let _ = writeln!(out, "\ntimings");
+            for timing in self.timings.iter() {
+                let _ = writeln!(out, "  {}: {}ms", timing.phase, timing.millis);
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diffs_targets() {
+        let before = r#"
+py_library(
+    name = "a",
+    srcs = ["a.py"],
+    deps = ["//x", "//y"],
+)
+
+py_library(
+    name = "gone",
+    srcs = ["gone.py"],
+)
+"#;
+        let after = r#"
+py_library(
+    name = "a",
+    srcs = ["a.py"],
+    deps = ["//y", "//z"],
+    testonly = True,
+)
+
+py_library(
+    name = "new",
+    srcs = ["new.py"],
+)
+"#;
+        let diffs = diff_targets(before, after);
+        assert_eq!(
+            diffs
+                .iter()
+                .map(|d| (d.name.as_str(), d.change))
+                .collect::<Vec<_>>(),
+            vec![
+                ("a", TargetChange::Modified),
+                ("gone", TargetChange::Removed),
+                ("new", TargetChange::Added),
+            ]
+        );
+        assert_eq!(
+            diffs[0].attributes,
+            vec![
+                AttributeDiff {
+                    attribute: "deps".to_string(),
+                    added: vec!["//z".to_string()],
+                    removed: vec!["//x".to_string()],
+                },
+                AttributeDiff {
+                    attribute: "testonly".to_string(),
+                    added: vec!["True".to_string()],
+                    removed: Vec::default(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn summarizes_changes() {
+        let root = Path::new("/repo");
+        let summary = RunSummary::new(
+            root,
+            vec![
+                FileChange::Untouched(root.join("b/BUILD.bazel")),
+                FileChange::Created(root.join("a/BUILD.bazel")),
+                FileChange::Untouched(root.join("c/BUILD.bazel")),
+                FileChange::Modified(root.join("b/BUILD.bazel"), Vec::default()),
+            ],
+            vec![root.join("d/BUILD.bazel")],
+            vec![(root.join("e/BUILD"), "it wasn't generated by bzl_gen_build")],
+        );
+        assert_eq!(summary.created, vec!["a/BUILD.bazel"]);
+        assert_eq!(
+            summary.modified,
+            vec![ModifiedFile {
+                path: "b/BUILD.bazel".to_string(),
+                targets: Vec::default(),
+            }]
+        );
+        assert_eq!(summary.untouched, vec!["c/BUILD.bazel"]);
+        assert_eq!(summary.deleted, vec!["d/BUILD.bazel"]);
+        assert_eq!(
+            summary.kept,
+            vec![KeptFile {
+                path: "e/BUILD".to_string(),
+                reason: "it wasn't generated by bzl_gen_build".to_string(),
+            }]
+        );
+        assert!(summary
+            .to_text()
+            .starts_with("BUILD files: 1 created, 1 modified, 1 deleted, 1 untouched"));
+    }
+}
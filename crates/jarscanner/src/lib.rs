@@ -112,7 +112,11 @@ pub fn process_input(
             entity_path: relative_path.to_string(),
             defs: classes,
             refs: HashSet::new(),
+            ignored_refs: BTreeSet::new(),
+            runtime_refs: BTreeSet::new(),
             bzl_gen_build_commands: HashSet::new(),
+            bzl_gen_build_command_lines: HashMap::new(),
+            inferred_bzl_gen_build_commands: BTreeSet::new(),
         }],
     })
 }
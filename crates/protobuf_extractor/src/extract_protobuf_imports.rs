@@ -6,7 +6,8 @@ use tree_sitter::Parser;
 pub struct ProtobufSource {
     pub imports: Vec<String>,
     pub well_known_refs: Vec<String>,
-    pub bzl_gen_build_commands: Vec<String>,
+    /// commands along with the (1-based) line of the comment they were found in
+    pub bzl_gen_build_commands: Vec<(String, usize)>,
 }
 
 impl ProtobufSource {
@@ -40,7 +41,10 @@ impl ProtobufSource {
                 "comment" => {
                     let raw_comment = child_node.utf8_text(bytes)?;
                     if let Some(bzl_command) = Directive::extract_directive(&raw_comment, "//") {
-                        bzl_gen_build_commands.push(bzl_command.trim().to_string());
+                        bzl_gen_build_commands.push((
+                            bzl_command.trim().to_string(),
+                            child_node.start_position().row + 1,
+                        ));
                     }
                 }
                 _ => (),
@@ -85,7 +89,7 @@ message Address {
   string city = 1;
 }"#;
         let parsed = ProtobufSource::parse(protobuf_source, "tmp.proto")?;
-        let expected = vec!["manual_ref:aaa"];
+        let expected = vec![("manual_ref:aaa".to_string(), 5)];
         assert_eq!(parsed.bzl_gen_build_commands, expected);
         Ok(())
     }
@@ -3,7 +3,7 @@ use bzl_gen_build_shared_types::api::extracted_data::{DataBlock, ExtractedData};
 use clap::Parser;
 use log::debug;
 use std::{
-    collections::{BTreeSet, HashSet},
+    collections::{BTreeSet, HashMap, HashSet},
     path::PathBuf,
     time::Instant,
 };
@@ -70,6 +70,7 @@ async fn main() -> Result<()> {
         let mut refs: HashSet<String> = Default::default();
         let mut defs: BTreeSet<String> = Default::default();
         let mut bzl_gen_build_commands: HashSet<String> = Default::default();
+        let mut bzl_gen_build_command_lines: HashMap<String, usize> = Default::default();
 
         let input_str = std::fs::read_to_string(&input_file).with_context(|| {
             format!(
@@ -87,8 +88,11 @@ async fn main() -> Result<()> {
             )
         })?;
 
-        if !program.bzl_gen_build_commands.is_empty() {
-            bzl_gen_build_commands.extend(program.bzl_gen_build_commands);
+        for (command, line) in program.bzl_gen_build_commands {
+            bzl_gen_build_command_lines
+                .entry(command.clone())
+                .or_insert(line);
+            bzl_gen_build_commands.insert(command);
         }
         if !opt.disable_ref_generation {
             refs.extend(program.imports);
@@ -107,12 +111,19 @@ async fn main() -> Result<()> {
         //     import "myproject/other_protos.proto";
         // So here we export the relative path as a "definition".
         defs.extend(Some(relative_path.clone()));
+        // protoc generates the Python module `com.example.aa_pb2` for `com/example/aa.proto`,
+        // which lets Python sources extracted in the same run depend on the schema.
+        defs.extend(python_module(&relative_path));
 
         data_blocks.push(DataBlock {
             entity_path: relative_path,
             defs,
             refs,
+            ignored_refs: BTreeSet::new(),
+            runtime_refs: BTreeSet::new(),
             bzl_gen_build_commands,
+            bzl_gen_build_command_lines,
+            inferred_bzl_gen_build_commands: BTreeSet::new(),
         })
     }
 
@@ -127,3 +138,23 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+fn python_module(relative_path: &str) -> Option<String> {
+    relative_path
+        .strip_suffix(".proto")
+        .map(|stem| format!("{}_pb2", stem.replace('/', ".")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_python_module() {
+        assert_eq!(
+            python_module("com/example/aa.proto"),
+            Some("com.example.aa_pb2".to_string())
+        );
+        assert_eq!(python_module("com/example/BUILD"), None);
+    }
+}
@@ -1,14 +1,40 @@
+use std::collections::{BTreeMap, BTreeSet};
+
 use bzl_gen_build_shared_types::Directive;
+use rustpython_parser::{lexer::lex, Mode, Tok};
 
-pub fn extract(python_src: &str) -> Vec<String> {
-    let mut buf = Vec::default();
-    for ln in python_src.lines() {
+// Returns each command along with the first (1-based) line it appears on.
+pub fn extract(python_src: &str) -> BTreeMap<String, usize> {
+    let mut buf = BTreeMap::default();
+    for (idx, ln) in python_src.lines().enumerate() {
         if let Some(bzl_command) = Directive::extract_directive(&ln, "#") {
-            buf.push(bzl_command.trim().to_string());
+            buf.entry(bzl_command.trim().to_string()).or_insert(idx + 1);
+        }
+    }
+
+    buf
+}
+
+// Returns the (1-based) lines carrying a trailing `# bzl_gen_build: ignore` after some code,
+// such as `import foo  # bzl_gen_build: ignore`. Only comment tokens count, so a `#` inside a
+// string literal is left alone.
+pub fn ignored_lines(python_src: &str) -> BTreeSet<usize> {
+    let mut buf = BTreeSet::default();
+    for (tok, range) in lex(python_src, Mode::Module).map_while(Result::ok) {
+        if let Tok::Comment(comment) = tok {
+            // The directive may follow another one, as in `# noqa # bzl_gen_build: ignore`
+            let is_ignore = comment.match_indices('#').any(|(pos, _)| {
+                Directive::extract_directive(&comment[pos..], "#")
+                    .map(|d| d.trim() == "ignore")
+                    .unwrap_or(false)
+            });
+            let before = &python_src[..usize::from(range.start())];
+            let line_start = before.rfind('\n').map(|idx| idx + 1).unwrap_or(0);
+            if is_ignore && !before[line_start..].trim().is_empty() {
+                buf.insert(before.matches('\n').count() + 1);
+            }
         }
     }
-    buf.sort();
-    buf.dedup();
 
     buf
 }
@@ -33,14 +59,34 @@ def my_fn():
   #bzl_gen_build : manual_runtime_ref: //:build_properties2.jar
         "#;
 
-        let mut expected = vec![
-            "runtime_ref: tensorflow.keras".to_string(),
-            "runtime_ref: src.main.python.foo.bar.baz.NumericalEmbedding".to_string(),
-            "manual_runtime_ref: //:build_properties.jar".to_string(),
-            "manual_runtime_ref: //:build_properties2.jar".to_string(),
-        ];
-        expected.sort();
-        expected.dedup();
+        let expected = BTreeMap::from([
+            ("runtime_ref: tensorflow.keras".to_string(), 6),
+            (
+                "runtime_ref: src.main.python.foo.bar.baz.NumericalEmbedding".to_string(),
+                7,
+            ),
+            ("manual_runtime_ref: //:build_properties.jar".to_string(), 8),
+            (
+                "manual_runtime_ref: //:build_properties2.jar".to_string(),
+                12,
+            ),
+        ]);
         assert_eq!(extract(python_source), expected)
     }
+
+    #[test]
+    fn test_ignored_lines() {
+        let python_source = r##"# bzl_gen_build: ignore
+import os  # bzl_gen_build: ignore
+import sys # noqa # bzl_gen_build:ignore
+import json  # bzl_gen_build: unref: json
+from foo import (
+    bar,  # bzl_gen_build: ignore
+)
+x = """# bzl_gen_build: ignore
+"""
+"##;
+
+        assert_eq!(ignored_lines(python_source), BTreeSet::from([2, 3, 6]))
+    }
 }
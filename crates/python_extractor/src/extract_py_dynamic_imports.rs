@@ -0,0 +1,488 @@
+use std::collections::BTreeSet;
+
+use ast::{Constant, Expr, Stmt};
+use bzl_gen_build_python_utilities::PythonProgram;
+use rustpython_parser::ast;
+
+use crate::extract_py_imports::{expand_prefixes, packages, resolve_relative, IgnoredLines};
+
+/// The modules a program loads by name, with `importlib.import_module(...)` or `__import__(...)`.
+/// Only calls naming the module with a string literal can be followed, the (1-based) lines of
+/// the other ones are kept in `unresolved_lines` so they can be reported.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DynamicImports {
+    pub refs: Vec<String>,
+    pub unresolved_lines: Vec<usize>,
+}
+
+/// Calls on a line marked with `# bzl_gen_build: ignore` are skipped, like imports are.
+pub fn extract(
+    program: &PythonProgram,
+    python_src: &str,
+    module_names: &[String],
+) -> DynamicImports {
+    let mut collector = Collector {
+        ignored_lines: IgnoredLines::new(
+            python_src,
+            crate::extract_py_bzl_gen_build_commands::ignored_lines(python_src),
+        ),
+        packages: packages(module_names),
+        buf: Vec::default(),
+        unresolved_lines: BTreeSet::default(),
+    };
+    collector.visit_body(&program.body);
+
+    DynamicImports {
+        refs: expand_prefixes(collector.buf),
+        unresolved_lines: collector.unresolved_lines.into_iter().collect(),
+    }
+}
+
+enum Loader {
+    ImportModule,
+    DunderImport,
+}
+
+impl Loader {
+    fn of(func: &Expr) -> Option<Loader> {
+        match func {
+            Expr::Attribute(ast::ExprAttribute { value, attr, .. })
+                if attr.as_str() == "import_module"
+                    && matches!(
+                        value.as_ref(),
+                        Expr::Name(ast::ExprName { id, .. }) if id.as_str() == "importlib"
+                    ) =>
+            {
+                Some(Loader::ImportModule)
+            }
+            // from importlib import import_module
+            Expr::Name(ast::ExprName { id, .. }) if id.as_str() == "import_module" => {
+                Some(Loader::ImportModule)
+            }
+            Expr::Name(ast::ExprName { id, .. }) if id.as_str() == "__import__" => {
+                Some(Loader::DunderImport)
+            }
+            _ => None,
+        }
+    }
+}
+
+// An argument given either by position or by keyword.
+fn argument<'a>(call: &'a ast::ExprCall, position: usize, keyword: &str) -> Option<&'a Expr> {
+    call.args.get(position).or_else(|| {
+        call.keywords
+            .iter()
+            .find(|k| k.arg.as_ref().map(|a| a.as_str()) == Some(keyword))
+            .map(|k| &k.value)
+    })
+}
+
+fn string_literal(e: &Expr) -> Option<&str> {
+    match e {
+        Expr::Constant(ast::ExprConstant {
+            value: Constant::Str(s),
+            ..
+        }) => Some(s.as_str()),
+        _ => None,
+    }
+}
+
+struct Collector {
+    ignored_lines: IgnoredLines,
+    packages: Vec<String>,
+    buf: Vec<String>,
+    unresolved_lines: BTreeSet<usize>,
+}
+
+impl Collector {
+    // The modules the call loads, empty when they can't be known from the source.
+    fn loaded_modules(&self, loader: Loader, call: &ast::ExprCall) -> Vec<String> {
+        let name = match argument(call, 0, "name").and_then(string_literal) {
+            Some(name) => name,
+            None => return Vec::default(),
+        };
+        let module = name.trim_start_matches('.');
+        let level = name.len() - module.len();
+        if level == 0 {
+            return vec![name.to_string()];
+        }
+        // `__import__` takes relative imports through its `level` argument instead.
+        if let Loader::DunderImport = loader {
+            return Vec::default();
+        }
+        // import_module(".plugins", package=__package__)
+        let packages = match argument(call, 1, "package") {
+            Some(Expr::Name(ast::ExprName { id, .. })) if id.as_str() == "__package__" => {
+                self.packages.clone()
+            }
+            Some(package) => string_literal(package)
+                .map(|p| vec![p.to_string()])
+                .unwrap_or_default(),
+            None => Vec::default(),
+        };
+        let module = Some(module).filter(|m| !m.is_empty());
+        packages
+            .iter()
+            .filter_map(|p| resolve_relative(p, level, module))
+            .collect()
+    }
+
+    fn visit_call(&mut self, call: &ast::ExprCall) {
+        let start: usize = call.range.start().into();
+        let end: usize = call.range.end().into();
+        if let Some(loader) = Loader::of(&call.func) {
+            if !self.ignored_lines.covers(start, end) {
+                let modules = self.loaded_modules(loader, call);
+                if modules.is_empty() {
+                    self.unresolved_lines
+                        .insert(self.ignored_lines.line_of(start));
+                }
+                self.buf.extend(modules);
+            }
+        }
+        self.visit_expr(&call.func);
+        self.visit_exprs(&call.args);
+        for keyword in call.keywords.iter() {
+            self.visit_expr(&keyword.value);
+        }
+    }
+
+    fn visit_exprs(&mut self, exprs: &[Expr]) {
+        for e in exprs.iter() {
+            self.visit_expr(e);
+        }
+    }
+
+    fn visit_optional_expr(&mut self, expr: &Option<Box<Expr>>) {
+        if let Some(expr) = expr {
+            self.visit_expr(expr);
+        }
+    }
+
+    fn visit_comprehensions(&mut self, generators: &[ast::Comprehension]) {
+        for generator in generators.iter() {
+            self.visit_expr(&generator.iter);
+            self.visit_exprs(&generator.ifs);
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Call(call) => self.visit_call(call),
+            Expr::Attribute(ast::ExprAttribute { value, .. })
+            | Expr::Starred(ast::ExprStarred { value, .. })
+            | Expr::Await(ast::ExprAwait { value, .. })
+            | Expr::YieldFrom(ast::ExprYieldFrom { value, .. })
+            | Expr::NamedExpr(ast::ExprNamedExpr { value, .. }) => self.visit_expr(value),
+            Expr::Yield(ast::ExprYield { value, .. }) => self.visit_optional_expr(value),
+            Expr::Subscript(ast::ExprSubscript { value, slice, .. }) => {
+                self.visit_expr(value);
+                self.visit_expr(slice);
+            }
+            Expr::UnaryOp(ast::ExprUnaryOp { operand, .. }) => self.visit_expr(operand),
+            Expr::BinOp(ast::ExprBinOp { left, right, .. }) => {
+                self.visit_expr(left);
+                self.visit_expr(right);
+            }
+            Expr::BoolOp(ast::ExprBoolOp { values, .. }) => self.visit_exprs(values),
+            Expr::Compare(ast::ExprCompare {
+                left, comparators, ..
+            }) => {
+                self.visit_expr(left);
+                self.visit_exprs(comparators);
+            }
+            Expr::IfExp(ast::ExprIfExp {
+                test, body, orelse, ..
+            }) => {
+                self.visit_expr(test);
+                self.visit_expr(body);
+                self.visit_expr(orelse);
+            }
+            Expr::Lambda(ast::ExprLambda { args, body, .. }) => {
+                for arg in args
+                    .posonlyargs
+                    .iter()
+                    .chain(args.args.iter())
+                    .chain(args.kwonlyargs.iter())
+                {
+                    self.visit_optional_expr(&arg.default);
+                }
+                self.visit_expr(body);
+            }
+            Expr::List(ast::ExprList { elts, .. })
+            | Expr::Tuple(ast::ExprTuple { elts, .. })
+            | Expr::Set(ast::ExprSet { elts, .. }) => self.visit_exprs(elts),
+            Expr::Dict(ast::ExprDict { keys, values, .. }) => {
+                for key in keys.iter().flatten() {
+                    self.visit_expr(key);
+                }
+                self.visit_exprs(values);
+            }
+            Expr::ListComp(ast::ExprListComp {
+                elt, generators, ..
+            })
+            | Expr::SetComp(ast::ExprSetComp {
+                elt, generators, ..
+            })
+            | Expr::GeneratorExp(ast::ExprGeneratorExp {
+                elt, generators, ..
+            }) => {
+                self.visit_expr(elt);
+                self.visit_comprehensions(generators);
+            }
+            Expr::DictComp(ast::ExprDictComp {
+                key,
+                value,
+                generators,
+                ..
+            }) => {
+                self.visit_expr(key);
+                self.visit_expr(value);
+                self.visit_comprehensions(generators);
+            }
+            // f-strings
+            Expr::JoinedStr(ast::ExprJoinedStr { values, .. }) => self.visit_exprs(values),
+            Expr::FormattedValue(ast::ExprFormattedValue {
+                value, format_spec, ..
+            }) => {
+                self.visit_expr(value);
+                self.visit_optional_expr(format_spec);
+            }
+            Expr::Slice(ast::ExprSlice {
+                lower, upper, step, ..
+            }) => {
+                self.visit_optional_expr(lower);
+                self.visit_optional_expr(upper);
+                self.visit_optional_expr(step);
+            }
+            Expr::Constant(_) | Expr::Name(_) => (),
+        }
+    }
+
+    fn visit_body(&mut self, body: &[Stmt]) {
+        for stmt in body.iter() {
+            self.visit_stmt(stmt);
+        }
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::FunctionDef(ast::StmtFunctionDef {
+                body,
+                decorator_list,
+                ..
+            })
+            | Stmt::AsyncFunctionDef(ast::StmtAsyncFunctionDef {
+                body,
+                decorator_list,
+                ..
+            }) => {
+                self.visit_exprs(decorator_list);
+                self.visit_body(body);
+            }
+            Stmt::ClassDef(ast::StmtClassDef {
+                bases,
+                keywords,
+                body,
+                decorator_list,
+                ..
+            }) => {
+                self.visit_exprs(decorator_list);
+                self.visit_exprs(bases);
+                for keyword in keywords.iter() {
+                    self.visit_expr(&keyword.value);
+                }
+                self.visit_body(body);
+            }
+            Stmt::Expr(ast::StmtExpr { value, .. })
+            | Stmt::Assign(ast::StmtAssign { value, .. })
+            | Stmt::AugAssign(ast::StmtAugAssign { value, .. }) => self.visit_expr(value),
+            Stmt::Return(ast::StmtReturn { value, .. })
+            | Stmt::AnnAssign(ast::StmtAnnAssign { value, .. }) => self.visit_optional_expr(value),
+            Stmt::Raise(ast::StmtRaise { exc, cause, .. }) => {
+                self.visit_optional_expr(exc);
+                self.visit_optional_expr(cause);
+            }
+            Stmt::Assert(ast::StmtAssert { test, msg, .. }) => {
+                self.visit_expr(test);
+                self.visit_optional_expr(msg);
+            }
+            Stmt::Delete(ast::StmtDelete { targets, .. }) => self.visit_exprs(targets),
+            Stmt::For(ast::StmtFor {
+                iter, body, orelse, ..
+            })
+            | Stmt::AsyncFor(ast::StmtAsyncFor {
+                iter, body, orelse, ..
+            }) => {
+                self.visit_expr(iter);
+                self.visit_body(body);
+                self.visit_body(orelse);
+            }
+            Stmt::While(ast::StmtWhile {
+                test, body, orelse, ..
+            })
+            | Stmt::If(ast::StmtIf {
+                test, body, orelse, ..
+            }) => {
+                self.visit_expr(test);
+                self.visit_body(body);
+                self.visit_body(orelse);
+            }
+            Stmt::With(ast::StmtWith { items, body, .. })
+            | Stmt::AsyncWith(ast::StmtAsyncWith { items, body, .. }) => {
+                for item in items.iter() {
+                    self.visit_expr(&item.context_expr);
+                }
+                self.visit_body(body);
+            }
+            Stmt::Match(ast::StmtMatch { subject, cases, .. }) => {
+                self.visit_expr(subject);
+                for case in cases.iter() {
+                    self.visit_optional_expr(&case.guard);
+                    self.visit_body(&case.body);
+                }
+            }
+            Stmt::Try(ast::StmtTry {
+                body,
+                handlers,
+                orelse,
+                finalbody,
+                ..
+            })
+            | Stmt::TryStar(ast::StmtTryStar {
+                body,
+                handlers,
+                orelse,
+                finalbody,
+                ..
+            }) => {
+                self.visit_body(body);
+                for handler in handlers.iter() {
+                    if let Some(handler) = handler.as_except_handler() {
+                        self.visit_optional_expr(&handler.type_);
+                        self.visit_body(&handler.body);
+                    }
+                }
+                self.visit_body(orelse);
+                self.visit_body(finalbody);
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dynamic_imports(python_source: &str, module_names: &[&str]) -> DynamicImports {
+        let parsed = PythonProgram::parse(python_source, "tmp.py").unwrap();
+        let module_names: Vec<String> = module_names.iter().map(|m| m.to_string()).collect();
+        extract(&parsed, python_source, &module_names)
+    }
+
+    #[test]
+    fn test_dynamic_imports() {
+        let python_source = r#"import importlib
+from importlib import import_module
+
+PLUGINS = [importlib.import_module("app.plugins.csv"), __import__("yaml")]
+
+def load(name):
+    return import_module(name)
+
+class Registry:
+    json = import_module(".plugins.json", package=__package__)
+"#;
+
+        assert_eq!(
+            dynamic_imports(python_source, &["app.registry"]),
+            DynamicImports {
+                refs: vec![
+                    "app".to_string(),
+                    "app.plugins".to_string(),
+                    "app.plugins.csv".to_string(),
+                    "app.plugins.json".to_string(),
+                    "yaml".to_string(),
+                ],
+                unresolved_lines: vec![7],
+            }
+        );
+    }
+
+    #[test]
+    fn test_dynamic_imports_in_comprehensions() {
+        let python_source = r#"import importlib
+
+PLUGINS = {n: importlib.import_module(f"pkg.plugins.{n}") for n in NAMES}
+LOADED = [m for m in (importlib.import_module(x) for x in NAMES) if m]
+"#;
+
+        assert_eq!(
+            dynamic_imports(python_source, &["pkg.registry"]),
+            DynamicImports {
+                refs: vec![],
+                unresolved_lines: vec![3, 4],
+            }
+        );
+    }
+
+    #[test]
+    fn test_dynamic_imports_in_other_expressions() {
+        let python_source = r#"import importlib
+
+GREETING = f"{importlib.import_module('pkg.greeting').HELLO:>{__import__('pkg.width').WIDTH}}"
+
+def plugins():
+    yield importlib.import_module("pkg.yielded")
+    yield from __import__("pkg.delegated").ALL
+
+FIRST = NAMES[__import__("pkg.offsets").START:]
+assert __import__("pkg.checks").ok(), importlib.import_module("pkg.messages").FAILED
+del CACHE[importlib.import_module("pkg.cache").KEY]
+raise importlib.import_module("pkg.errors").Failure() from __import__("pkg.causes").CAUSE
+"#;
+
+        assert_eq!(
+            dynamic_imports(python_source, &["pkg.registry"]),
+            DynamicImports {
+                refs: vec![
+                    "pkg".to_string(),
+                    "pkg.cache".to_string(),
+                    "pkg.causes".to_string(),
+                    "pkg.checks".to_string(),
+                    "pkg.delegated".to_string(),
+                    "pkg.errors".to_string(),
+                    "pkg.greeting".to_string(),
+                    "pkg.messages".to_string(),
+                    "pkg.offsets".to_string(),
+                    "pkg.width".to_string(),
+                    "pkg.yielded".to_string(),
+                ],
+                unresolved_lines: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn test_unresolved_dynamic_imports() {
+        assert_eq!(
+            dynamic_imports("import_module('.plugins')\n", &["app.registry"]),
+            DynamicImports {
+                refs: vec![],
+                unresolved_lines: vec![1],
+            }
+        );
+        assert_eq!(
+            dynamic_imports(
+                "importlib.import_module(name)  # bzl_gen_build: ignore\n",
+                &["app.registry"]
+            ),
+            DynamicImports::default()
+        );
+        assert_eq!(
+            dynamic_imports("other.import_module('x')\n", &["app.registry"]),
+            DynamicImports::default()
+        );
+    }
+}
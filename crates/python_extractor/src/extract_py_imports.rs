@@ -1,11 +1,136 @@
+use std::collections::BTreeSet;
+
 use ast::Stmt;
 use bzl_gen_build_python_utilities::PythonProgram;
 use rustpython_parser::ast;
 
-pub fn extract(program: &PythonProgram) -> Vec<String> {
+/// The references of a program's imports. Imports on a line marked with a trailing
+/// `# bzl_gen_build: ignore` are kept apart in `ignored_refs` rather than dropped silently.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Imports {
+    pub refs: Vec<String>,
+    pub ignored_refs: Vec<String>,
+}
+
+// Maps byte offsets in the source to 1-based lines, to find imports on ignored lines.
+pub(crate) struct IgnoredLines {
+    line_starts: Vec<usize>,
+    lines: BTreeSet<usize>,
+}
+
+impl IgnoredLines {
+    pub(crate) fn new(python_src: &str, lines: BTreeSet<usize>) -> IgnoredLines {
+        let line_starts = std::iter::once(0)
+            .chain(python_src.match_indices('\n').map(|(idx, _)| idx + 1))
+            .collect();
+        IgnoredLines { line_starts, lines }
+    }
+
+    pub(crate) fn line_of(&self, offset: usize) -> usize {
+        self.line_starts.partition_point(|start| *start <= offset)
+    }
+
+    // An import spanning several lines is ignored when any of them is marked.
+    pub(crate) fn covers(&self, start: usize, end: usize) -> bool {
+        !self.lines.is_empty()
+            && self
+                .lines
+                .range(self.line_of(start)..=self.line_of(end))
+                .next()
+                .is_some()
+    }
+
+    // Which of an import's aliases are ignored. A marked line ignores the aliases on it, and one
+    // holding none of them, such as `from a import (  # bzl_gen_build: ignore`, all of them.
+    pub(crate) fn ignored_aliases(
+        &self,
+        start: usize,
+        end: usize,
+        aliases: &[ast::Alias],
+    ) -> Vec<bool> {
+        let alias_lines: Vec<(usize, usize)> = aliases
+            .iter()
+            .map(|a| {
+                (
+                    self.line_of(a.range.start().into()),
+                    self.line_of(a.range.end().into()),
+                )
+            })
+            .collect();
+        let whole_statement = self
+            .lines
+            .range(self.line_of(start)..=self.line_of(end))
+            .any(|line| {
+                !alias_lines
+                    .iter()
+                    .any(|(first, last)| first <= line && line <= last)
+            });
+        alias_lines
+            .iter()
+            .map(|(first, last)| whole_statement || self.lines.range(first..=last).next().is_some())
+            .collect()
+    }
+}
+
+/// `module_names` are the names the file can be imported with, see `expand_path_to_defs`, which
+/// relative imports are resolved against.
+pub fn extract(program: &PythonProgram, python_src: &str, module_names: &[String]) -> Imports {
+    let ignored_lines = IgnoredLines::new(
+        python_src,
+        crate::extract_py_bzl_gen_build_commands::ignored_lines(python_src),
+    );
+    let packages = packages(module_names);
     let mut buf = Vec::default();
-    extract_from_body(&program.body, &mut buf);
+    let mut ignored_buf = Vec::default();
+    extract_from_body(
+        &program.body,
+        &ignored_lines,
+        &packages,
+        &mut buf,
+        &mut ignored_buf,
+    );
+
+    let refs = expand_prefixes(buf);
+    // A prefix shared with an import that is kept is still a reference.
+    let ignored_refs = expand_prefixes(ignored_buf)
+        .into_iter()
+        .filter(|r| refs.binary_search(r).is_err())
+        .collect();
 
+    Imports { refs, ignored_refs }
+}
+
+// The packages of the modules, e.g. `a.b` for `a.b.c` as well as for `a.b.__init__`. A module
+// at the top level has no package to import from relatively.
+pub(crate) fn packages(module_names: &[String]) -> Vec<String> {
+    let mut packages: Vec<String> = module_names
+        .iter()
+        .filter(|m| !module_names.contains(&format!("{}.__init__", m)))
+        .filter_map(|m| m.rsplit_once('.').map(|(package, _)| package.to_string()))
+        .collect();
+    packages.sort();
+    packages.dedup();
+    packages
+}
+
+// The module a relative import of the given level refers to from within the package:
+// `from . import x` imports from the package itself, `from .. import x` from its parent.
+pub(crate) fn resolve_relative(
+    package: &str,
+    level: usize,
+    module: Option<&str>,
+) -> Option<String> {
+    let mut base = package;
+    for _ in 1..level {
+        base = base.rsplit_once('.')?.0;
+    }
+    Some(match module {
+        Some(module) => format!("{}.{}", base, module),
+        None => base.to_string(),
+    })
+}
+
+pub(crate) fn expand_prefixes(buf: Vec<String>) -> Vec<String> {
     let mut buf: Vec<String> = buf
         .into_iter()
         .flat_map(|b| {
@@ -30,35 +155,49 @@ pub fn extract(program: &PythonProgram) -> Vec<String> {
     buf
 }
 
-fn extract_from_body(body: &Vec<Stmt>, buf: &mut Vec<String>) {
+fn extract_from_body(
+    body: &Vec<Stmt>,
+    ignored_lines: &IgnoredLines,
+    packages: &[String],
+    buf: &mut Vec<String>,
+    ignored_buf: &mut Vec<String>,
+) {
     for element in body.iter() {
         match element {
-            Stmt::FunctionDef(ast::StmtFunctionDef { body, .. }) => extract_from_body(&body, buf),
+            Stmt::FunctionDef(ast::StmtFunctionDef { body, .. }) => {
+                extract_from_body(&body, ignored_lines, packages, buf, ignored_buf)
+            }
             Stmt::AsyncFunctionDef(ast::StmtAsyncFunctionDef { body, .. }) => {
-                extract_from_body(&body, buf)
+                extract_from_body(&body, ignored_lines, packages, buf, ignored_buf)
+            }
+            Stmt::ClassDef(ast::StmtClassDef { body, .. }) => {
+                extract_from_body(&body, ignored_lines, packages, buf, ignored_buf)
             }
-            Stmt::ClassDef(ast::StmtClassDef { body, .. }) => extract_from_body(&body, buf),
             Stmt::For(ast::StmtFor { body, orelse, .. }) => {
-                extract_from_body(&body, buf);
-                extract_from_body(&orelse, buf);
+                extract_from_body(&body, ignored_lines, packages, buf, ignored_buf);
+                extract_from_body(&orelse, ignored_lines, packages, buf, ignored_buf);
             }
             Stmt::AsyncFor(ast::StmtAsyncFor { body, orelse, .. }) => {
-                extract_from_body(&body, buf);
-                extract_from_body(&orelse, buf);
+                extract_from_body(&body, ignored_lines, packages, buf, ignored_buf);
+                extract_from_body(&orelse, ignored_lines, packages, buf, ignored_buf);
             }
             Stmt::While(ast::StmtWhile { body, orelse, .. }) => {
-                extract_from_body(&body, buf);
-                extract_from_body(&orelse, buf);
+                extract_from_body(&body, ignored_lines, packages, buf, ignored_buf);
+                extract_from_body(&orelse, ignored_lines, packages, buf, ignored_buf);
             }
             Stmt::If(ast::StmtIf { body, orelse, .. }) => {
-                extract_from_body(&body, buf);
-                extract_from_body(&orelse, buf);
+                extract_from_body(&body, ignored_lines, packages, buf, ignored_buf);
+                extract_from_body(&orelse, ignored_lines, packages, buf, ignored_buf);
+            }
+            Stmt::With(ast::StmtWith { body, .. }) => {
+                extract_from_body(&body, ignored_lines, packages, buf, ignored_buf)
+            }
+            Stmt::AsyncWith(ast::StmtAsyncWith { body, .. }) => {
+                extract_from_body(&body, ignored_lines, packages, buf, ignored_buf)
             }
-            Stmt::With(ast::StmtWith { body, .. }) => extract_from_body(&body, buf),
-            Stmt::AsyncWith(ast::StmtAsyncWith { body, .. }) => extract_from_body(&body, buf),
             Stmt::Match(ast::StmtMatch { cases, .. }) => {
                 for case in cases.iter() {
-                    extract_from_body(&case.body, buf);
+                    extract_from_body(&case.body, ignored_lines, packages, buf, ignored_buf);
                 }
             }
             Stmt::Try(ast::StmtTry {
@@ -71,31 +210,60 @@ fn extract_from_body(body: &Vec<Stmt>, buf: &mut Vec<String>) {
                 for handler in handlers.iter() {
                     match &handler.as_except_handler() {
                         Some(ast::ExceptHandlerExceptHandler { body, .. }) => {
-                            extract_from_body(&body, buf);
+                            extract_from_body(&body, ignored_lines, packages, buf, ignored_buf);
                         }
                         None => {}
                     }
                 }
-                extract_from_body(&body, buf);
-                extract_from_body(&orelse, buf);
-                extract_from_body(&finalbody, buf);
+                extract_from_body(&body, ignored_lines, packages, buf, ignored_buf);
+                extract_from_body(&orelse, ignored_lines, packages, buf, ignored_buf);
+                extract_from_body(&finalbody, ignored_lines, packages, buf, ignored_buf);
             }
-            Stmt::Import(ast::StmtImport { names, .. }) => {
-                for nme in names.iter() {
+            Stmt::Import(ast::StmtImport { range, names, .. }) => {
+                let ignored =
+                    ignored_lines.ignored_aliases(range.start().into(), range.end().into(), names);
+                for (nme, ignored) in names.iter().zip(ignored) {
+                    let buf = if ignored {
+                        &mut *ignored_buf
+                    } else {
+                        &mut *buf
+                    };
                     buf.push(nme.name.to_string().clone());
                 }
             }
             Stmt::ImportFrom(ast::StmtImportFrom {
+                range,
                 module,
                 names,
-                level: _,
+                level,
                 ..
             }) => {
-                for nme in names.iter() {
-                    if let Some(module) = module.as_ref() {
-                        buf.push(format!("{}.{}", module, nme.name));
-                    } else {
-                        buf.push(nme.name.to_string().clone());
+                let ignored =
+                    ignored_lines.ignored_aliases(range.start().into(), range.end().into(), names);
+                let level = level.map(|l| l.to_usize()).unwrap_or_default();
+                let modules: Vec<Option<String>> = if level == 0 {
+                    vec![module.as_ref().map(|m| m.to_string())]
+                } else {
+                    packages
+                        .iter()
+                        .filter_map(|p| {
+                            resolve_relative(p, level, module.as_ref().map(|m| m.as_str()))
+                        })
+                        .map(Some)
+                        .collect()
+                };
+                for module in modules.iter() {
+                    for (nme, ignored) in names.iter().zip(ignored.iter()) {
+                        let buf = if *ignored {
+                            &mut *ignored_buf
+                        } else {
+                            &mut *buf
+                        };
+                        if let Some(module) = module.as_ref() {
+                            buf.push(format!("{}.{}", module, nme.name));
+                        } else {
+                            buf.push(nme.name.to_string().clone());
+                        }
                     }
                 }
             }
@@ -141,6 +309,91 @@ def my_fn():
         ];
         expected.sort();
         expected.dedup();
-        assert_eq!(extract(&parsed), expected)
+        assert_eq!(extract(&parsed, python_source, &[]).refs, expected)
+    }
+
+    #[test]
+    fn test_ignored_import() {
+        let python_source = r#"import os.path
+import os.environ  # bzl_gen_build: ignore
+from tensorflow.keras import (  # bzl_gen_build: ignore
+    layers,
+)
+def my_fn():
+  from x.y import z  # bzl_gen_build: ignore
+        "#;
+
+        let parsed = PythonProgram::parse(python_source, "tmp.py").unwrap();
+        let imports = extract(&parsed, python_source, &[]);
+        assert_eq!(imports.refs, vec!["os".to_string(), "os.path".to_string()]);
+        assert_eq!(
+            imports.ignored_refs,
+            vec![
+                "os.environ".to_string(),
+                "tensorflow".to_string(),
+                "tensorflow.keras".to_string(),
+                "tensorflow.keras.layers".to_string(),
+                "x".to_string(),
+                "x.y".to_string(),
+                "x.y.z".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ignored_alias() {
+        let python_source = r##"from a import (b,  # bzl_gen_build: ignore
+    c)
+import d, e  # bzl_gen_build: ignore
+import g; f = """# bzl_gen_build: ignore
+"""
+"##;
+
+        let parsed = PythonProgram::parse(python_source, "tmp.py").unwrap();
+        let imports = extract(&parsed, python_source, &[]);
+        assert_eq!(
+            imports.refs,
+            vec!["a".to_string(), "a.c".to_string(), "g".to_string()]
+        );
+        assert_eq!(
+            imports.ignored_refs,
+            vec!["a.b".to_string(), "d".to_string(), "e".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_relative_import() {
+        let python_source = r#"from . import utils
+from .models import User
+from ..api import client
+from ... import too_far
+"#;
+
+        let parsed = PythonProgram::parse(python_source, "tmp.py").unwrap();
+        let module_names = vec!["src.app.views".to_string()];
+        assert_eq!(
+            extract(&parsed, python_source, &module_names).refs,
+            vec![
+                "src".to_string(),
+                "src.api".to_string(),
+                "src.api.client".to_string(),
+                "src.app".to_string(),
+                "src.app.models".to_string(),
+                "src.app.models.User".to_string(),
+                "src.app.utils".to_string(),
+            ]
+        );
+
+        // The package of an __init__.py file is the module itself.
+        let module_names = vec!["src.app".to_string(), "src.app.__init__".to_string()];
+        assert_eq!(
+            extract(&parsed, python_source, &module_names).refs,
+            extract(&parsed, python_source, &["src.app.views".to_string()]).refs
+        );
+
+        // A module at the top level has no package to import from.
+        assert!(extract(&parsed, python_source, &["views".to_string()])
+            .refs
+            .is_empty());
     }
 }
@@ -0,0 +1,106 @@
+use ast::{CmpOp, Constant, Expr, Stmt};
+use bzl_gen_build_python_utilities::PythonProgram;
+use rustpython_parser::ast;
+
+// Returns the (1-based) line of a top-level `if __name__ == "__main__":` block, if the program
+// has one. Blocks that just run the tests of the module with `unittest.main()` or
+// `pytest.main()` don't make it a script.
+pub fn main_block_line(program: &PythonProgram, python_src: &str) -> Option<usize> {
+    program.body.iter().find_map(|stmt| match stmt {
+        Stmt::If(ast::StmtIf {
+            range, test, body, ..
+        }) if is_main_check(test) && !body.iter().any(runs_tests) => {
+            let offset: usize = range.start().into();
+            Some(python_src[..offset].matches('\n').count() + 1)
+        }
+        _ => None,
+    })
+}
+
+fn is_main_check(test: &Expr) -> bool {
+    fn is_name(e: &Expr) -> bool {
+        matches!(e, Expr::Name(ast::ExprName { id, .. }) if id.as_str() == "__name__")
+    }
+    fn is_main(e: &Expr) -> bool {
+        matches!(
+            e,
+            Expr::Constant(ast::ExprConstant { value: Constant::Str(s), .. }) if s == "__main__"
+        )
+    }
+    match test {
+        Expr::Compare(ast::ExprCompare {
+            left,
+            ops,
+            comparators,
+            ..
+        }) if ops.as_slice() == [CmpOp::Eq] && comparators.len() == 1 => {
+            (is_name(left) && is_main(&comparators[0]))
+                || (is_main(left) && is_name(&comparators[0]))
+        }
+        _ => false,
+    }
+}
+
+fn runs_tests(stmt: &Stmt) -> bool {
+    fn is_test_runner(e: &Expr) -> bool {
+        match e {
+            Expr::Call(ast::ExprCall { func, args, .. }) => {
+                let is_runner = matches!(
+                    func.as_ref(),
+                    Expr::Attribute(ast::ExprAttribute { value, attr, .. })
+                        if attr.as_str() == "main"
+                            && matches!(
+                                value.as_ref(),
+                                Expr::Name(ast::ExprName { id, .. })
+                                    if id.as_str() == "unittest" || id.as_str() == "pytest"
+                            )
+                );
+                // e.g. sys.exit(pytest.main())
+                is_runner || args.iter().any(is_test_runner)
+            }
+            _ => false,
+        }
+    }
+    matches!(stmt, Stmt::Expr(ast::StmtExpr { value, .. }) if is_test_runner(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn main_line(python_source: &str) -> Option<usize> {
+        let parsed = PythonProgram::parse(python_source, "tmp.py").unwrap();
+        main_block_line(&parsed, python_source)
+    }
+
+    #[test]
+    fn test_main_block_line() {
+        assert_eq!(
+            main_line(
+                r#"import sys
+
+def main():
+    print(sys.argv)
+
+if __name__ == "__main__":
+    main()
+"#
+            ),
+            Some(6)
+        );
+        assert_eq!(main_line("if '__main__' == __name__:\n    pass\n"), Some(1));
+        assert_eq!(main_line("if __name__ != '__main__':\n    pass\n"), None);
+        assert_eq!(main_line("while __name__ == '__main__':\n    pass\n"), None);
+        assert_eq!(main_line("import os\n"), None);
+        assert_eq!(
+            main_line("import unittest\n\nif __name__ == '__main__':\n    unittest.main()\n"),
+            None
+        );
+        assert_eq!(
+            main_line(
+                "import pytest, sys\n\nif __name__ == '__main__':\n    sys.exit(pytest.main())\n"
+            ),
+            None
+        );
+    }
+}
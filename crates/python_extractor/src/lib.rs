@@ -4,6 +4,7 @@ use bzl_gen_build_shared_types::api::extracted_data::{DataBlock, ExtractedData};
 use encoding_rs::*;
 use futures::future::join_all;
 use lazy_static::lazy_static;
+use log::warn;
 use regex::Regex;
 use std::{
     collections::{BTreeSet, HashMap, HashSet},
@@ -12,7 +13,9 @@ use std::{
 };
 
 mod extract_py_bzl_gen_build_commands;
+mod extract_py_dynamic_imports;
 mod extract_py_imports;
+mod extract_py_main;
 
 lazy_static! {
     static ref CODECS: HashMap<&'static str, &'static Encoding> = {
@@ -130,13 +133,25 @@ async fn extract_file(
     let input_file = working_directory.join(&relative_path);
     let mut refs: HashSet<String> = Default::default();
     let mut defs: BTreeSet<String> = Default::default();
+    let mut ignored_refs: BTreeSet<String> = Default::default();
+    let mut runtime_refs: BTreeSet<String> = Default::default();
     let mut bzl_gen_build_commands: HashSet<String> = Default::default();
+    let mut bzl_gen_build_command_lines: HashMap<String, usize> = Default::default();
+    let mut inferred_bzl_gen_build_commands: BTreeSet<String> = Default::default();
 
     let input_str = read_file_to_str(&input_file)?;
 
-    bzl_gen_build_commands.extend(extract_py_bzl_gen_build_commands::extract(&input_str));
+    for (command, line) in extract_py_bzl_gen_build_commands::extract(&input_str) {
+        bzl_gen_build_commands.insert(command.clone());
+        bzl_gen_build_command_lines.insert(command, line);
+    }
 
     let file_p = input_file.to_string_lossy();
+    let expanded = if let Some(rel) = import_path_relative_from {
+        expand_path_to_defs_from_offset(rel, &file_p)
+    } else {
+        expand_path_to_defs(&relative_path, &file_p)
+    };
     if !disable_ref_generation {
         let program = PythonProgram::parse(&input_str, &file_p).with_context(|| {
             format!(
@@ -145,21 +160,49 @@ async fn extract_file(
                 input_file
             )
         })?;
-        refs.extend(extract_py_imports::extract(&program));
+        let imports = extract_py_imports::extract(&program, &input_str, &expanded);
+        refs.extend(imports.refs);
+        ignored_refs.extend(imports.ignored_refs);
+
+        let dynamic_imports = extract_py_dynamic_imports::extract(&program, &input_str, &expanded);
+        runtime_refs.extend(dynamic_imports.refs);
+        for line in dynamic_imports.unresolved_lines {
+            warn!(
+                "{}:{}: the module loaded here isn't a string literal, add a `bzl_gen_build:runtime_ref` directive for it",
+                relative_path, line
+            );
+        }
+
+        // scripts get a binary unless they ask for a specific one, or for none at all. The
+        // directives are only parsed by the driver, which reports the ones it can't parse.
+        let wants_binary = !bzl_gen_build_commands
+            .iter()
+            .any(|c| c == "no_binary" || c.starts_with("binary_generate"));
+        if let Some(line) = extract_py_main::main_block_line(&program, &input_str) {
+            if wants_binary {
+                let stem = Path::new(&relative_path)
+                    .file_stem()
+                    .map(|s| s.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                let command = format!("binary_generate: {}_bin", stem);
+                bzl_gen_build_commands.insert(command.clone());
+                bzl_gen_build_command_lines.insert(command.clone(), line);
+                inferred_bzl_gen_build_commands.insert(command);
+            }
+        }
     }
 
-    let expanded = if let Some(rel) = import_path_relative_from {
-        expand_path_to_defs_from_offset(rel, &file_p)
-    } else {
-        expand_path_to_defs(&relative_path, &file_p)
-    };
     defs.extend(expanded);
 
     Ok(DataBlock {
         entity_path: relative_path,
         defs,
         refs,
+        ignored_refs,
+        runtime_refs,
         bzl_gen_build_commands,
+        bzl_gen_build_command_lines,
+        inferred_bzl_gen_build_commands,
     })
 }
 
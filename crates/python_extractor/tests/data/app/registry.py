@@ -0,0 +1,6 @@
+import importlib
+
+PLUGINS = {
+    "csv": importlib.import_module("app.plugins.csv"),
+    "json": importlib.import_module(".plugins.json", package=__package__),
+}
@@ -0,0 +1,6 @@
+from . import models
+from .models import User
+
+
+def current_user():
+    return User(models.DEFAULT_NAME)
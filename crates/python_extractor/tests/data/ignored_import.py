@@ -0,0 +1,7 @@
+import os
+import json  # bzl_gen_build: ignore
+from typing import Any
+
+
+def load(path: str) -> Any:
+    return json.loads(os.path.basename(path))
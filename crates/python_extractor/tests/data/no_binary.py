@@ -0,0 +1,5 @@
+# bzl_gen_build: no_binary
+import sys
+
+if __name__ == "__main__":
+    print(sys.argv)
@@ -6,6 +6,26 @@ use tempfile::tempdir;
 use bzl_gen_build_shared_types::api::extracted_data::ExtractedData;
 use bzl_gen_python_extractor as pe;
 
+// Runs the extractor on a file of tests/data and reads back what it wrote.
+async fn extract_fixture(entity_path: &str) -> ExtractedData {
+    let tmpdir = tempdir().expect("Failed to create temp directory");
+    let tmp_json_path = tmpdir.path().join("python_extractor_output.json");
+
+    pe::extract_python(
+        entity_path.to_string(),
+        PathBuf::from("tests/data/"),
+        tmp_json_path.clone(),
+        "@pip".to_string(),
+        false,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let file = File::open(tmp_json_path).unwrap();
+    serde_json::from_reader(BufReader::new(file)).unwrap()
+}
+
 #[tokio::test]
 async fn process_well_formatted_python_module() {
     let tmpdir = tempdir().expect("Failed to create temp directory");
@@ -61,7 +81,9 @@ async fn process_well_formatted_python_module() {
                         "copy",
                         "binascii.hexlify"
                     ],
-                    "bzl_gen_build_commands": []
+                    "bzl_gen_build_commands": ["binary_generate: test_module_bin"],
+                    "bzl_gen_build_command_lines": {"binary_generate: test_module_bin": 15},
+                    "inferred_bzl_gen_build_commands": ["binary_generate: test_module_bin"]
                 }
             ],
             "label_or_repo_path": "@pip"
@@ -113,3 +135,92 @@ async fn process_non_utf8_python_module() {
     let expected: ExtractedData = serde_json::from_str(expected_data).unwrap();
     assert!(expected == data)
 }
+
+#[tokio::test]
+async fn process_python_module_with_ignored_import() {
+    let data = extract_fixture("ignored_import.py").await;
+
+    let expected_data = r#"
+        {
+            "data_blocks": [
+                {
+                    "entity_path": "ignored_import.py",
+                    "defs": ["ignored_import"],
+                    "refs": ["os", "typing", "typing.Any"],
+                    "ignored_refs": ["json"],
+                    "bzl_gen_build_commands": []
+                }
+            ],
+            "label_or_repo_path": "@pip"
+        }
+        "#;
+
+    let expected: ExtractedData = serde_json::from_str(expected_data).unwrap();
+    assert_eq!(expected, data)
+}
+
+#[tokio::test]
+async fn process_python_script_without_binary() {
+    let data = extract_fixture("no_binary.py").await;
+
+    let expected_data = r#"
+        {
+            "data_blocks": [
+                {
+                    "entity_path": "no_binary.py",
+                    "defs": ["no_binary"],
+                    "refs": ["sys"],
+                    "bzl_gen_build_commands": ["no_binary"],
+                    "bzl_gen_build_command_lines": {"no_binary": 1}
+                }
+            ],
+            "label_or_repo_path": "@pip"
+        }
+        "#;
+
+    let expected: ExtractedData = serde_json::from_str(expected_data).unwrap();
+    assert_eq!(expected, data)
+}
+
+#[tokio::test]
+async fn process_python_module_with_relative_imports() {
+    let data = extract_fixture("app/views.py").await;
+
+    let expected_data = r#"
+        {
+            "data_blocks": [
+                {
+                    "entity_path": "app/views.py",
+                    "defs": ["app.views"],
+                    "refs": ["app", "app.models", "app.models.User"]
+                }
+            ],
+            "label_or_repo_path": "@pip"
+        }
+        "#;
+
+    let expected: ExtractedData = serde_json::from_str(expected_data).unwrap();
+    assert_eq!(expected, data)
+}
+
+#[tokio::test]
+async fn process_python_module_with_dynamic_imports() {
+    let data = extract_fixture("app/registry.py").await;
+
+    let expected_data = r#"
+        {
+            "data_blocks": [
+                {
+                    "entity_path": "app/registry.py",
+                    "defs": ["app.registry"],
+                    "refs": ["importlib"],
+                    "runtime_refs": ["app", "app.plugins", "app.plugins.csv", "app.plugins.json"]
+                }
+            ],
+            "label_or_repo_path": "@pip"
+        }
+        "#;
+
+    let expected: ExtractedData = serde_json::from_str(expected_data).unwrap();
+    assert_eq!(expected, data)
+}
@@ -14,6 +14,22 @@ pub fn with_constant_str(s: String) -> Expr {
     })
 }
 
+pub fn with_constant_bool(b: bool) -> Expr {
+    Expr::Constant(ast::ExprConstant {
+        range: empty_range(),
+        value: ast::Constant::Bool(b),
+        kind: None,
+    })
+}
+
+pub fn with_constant_int(i: i64) -> Expr {
+    Expr::Constant(ast::ExprConstant {
+        range: empty_range(),
+        value: ast::Constant::Int(i.into()),
+        kind: None,
+    })
+}
+
 pub fn as_py_list(elements: Vec<Expr>) -> Expr {
     Expr::List(ast::ExprList {
         range: empty_range(),
@@ -22,6 +38,24 @@ pub fn as_py_list(elements: Vec<Expr>) -> Expr {
     })
 }
 
+pub fn as_py_dict(entries: Vec<(Expr, Expr)>) -> Expr {
+    let (keys, values) = entries.into_iter().map(|(k, v)| (Some(k), v)).unzip();
+    Expr::Dict(ast::ExprDict {
+        range: empty_range(),
+        keys,
+        values,
+    })
+}
+
+pub fn with_binop_add(left: Expr, right: Expr) -> Expr {
+    Expr::BinOp(ast::ExprBinOp {
+        range: empty_range(),
+        left: Box::new(left),
+        op: ast::Operator::Add,
+        right: Box::new(right),
+    })
+}
+
 pub fn as_stmt_expr(u: Expr) -> Stmt {
     Stmt::Expr(ast::StmtExpr {
         range: empty_range(),
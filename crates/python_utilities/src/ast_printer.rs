@@ -8,7 +8,8 @@ pub(crate) struct WritingBuffer<'a> {
     indent: usize,
     offset: usize,
     in_line: bool,
-    in_keyword: bool,
+    // how many calls the expression being written is nested in
+    call_depth: usize,
 }
 
 impl<'a> WritingBuffer<'a> {
@@ -18,7 +19,7 @@ impl<'a> WritingBuffer<'a> {
             indent: 0,
             offset: 0,
             in_line: true,
-            in_keyword: false,
+            call_depth: 0,
         }
     }
 
@@ -65,13 +66,13 @@ impl<'a> WritingBuffer<'a> {
         self.buf.join("")
     }
 
-    pub fn begin_keyword(&mut self) -> &mut Self {
-        self.in_keyword = true;
+    pub fn begin_call(&mut self) -> &mut Self {
+        self.call_depth += 1;
         self
     }
 
-    pub fn finish_keyword(&mut self) -> &mut Self {
-        self.in_keyword = false;
+    pub fn finish_call(&mut self) -> &mut Self {
+        self.call_depth -= 1;
         self
     }
 }
@@ -181,6 +182,25 @@ pub(crate) fn emit_body<'a>(body: &'a [Stmt], str_buffer: &mut WritingBuffer<'a>
     }
 }
 
+// A string literal the way buildifier writes it: double-quoted, with quotes, backslashes and
+// control characters escaped.
+fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
 /**
  * CustomDisplay represents a mutable pretty printing.
  * WritingBuffer keeps track of the indentation state, so we can't just return string.
@@ -218,7 +238,6 @@ impl CustomDisplay for ast::Expr {
                 }
             }
             for (idx, kw) in keywords.iter().enumerate() {
-                str_buffer.begin_keyword();
                 match &kw.arg {
                     Some(arg) => {
                         str_buffer.push_cow(Cow::Owned(format!("{} = ", arg)));
@@ -229,7 +248,6 @@ impl CustomDisplay for ast::Expr {
                 if idx < keywords.len() - 1 {
                     str_buffer.push(", ");
                 }
-                str_buffer.finish_keyword();
             }
             str_buffer.push_cow(Cow::Owned(end_marker.to_string()));
         }
@@ -250,7 +268,6 @@ impl CustomDisplay for ast::Expr {
                 str_buffer.push(",").finish_line();
             }
             for kw in keywords.iter() {
-                str_buffer.begin_keyword();
                 match &kw.arg {
                     Some(arg) => {
                         str_buffer.push_cow(Cow::Owned(format!("{} = ", arg)));
@@ -258,7 +275,7 @@ impl CustomDisplay for ast::Expr {
                     None => (),
                 };
                 kw.value.custom_fmt(str_buffer, defer);
-                str_buffer.push(",").finish_keyword().finish_line();
+                str_buffer.push(",").finish_line();
             }
             str_buffer
                 .deindent()
@@ -281,7 +298,7 @@ impl CustomDisplay for ast::Expr {
         match self {
             // This uses double-quotation for String literals
             ast::Expr::Constant(ast::ExprConstant { value, .. }) => match value {
-                ast::Constant::Str(str) => push(str_buffer, defer, format!("\"{}\"", str)),
+                ast::Constant::Str(str) => push(str_buffer, defer, quote(str)),
                 _ => push(str_buffer, defer, format!("{}", self)),
             },
             ast::Expr::Call(ast::ExprCall {
@@ -297,11 +314,14 @@ impl CustomDisplay for ast::Expr {
                     str_buffer.push_cow(Cow::Owned(name));
                     push_inline_list(str_buffer, defer, "(", args, keywords, ")");
                 } else {
-                    if !(str_buffer.in_keyword || str_buffer.at_start()) {
+                    // buildifier separates top-level statements with a blank line
+                    if str_buffer.call_depth == 0 && !str_buffer.at_start() {
                         str_buffer.push("").finish_line();
                     }
                     str_buffer.push_cow(Cow::Owned(name));
-                    push_list(str_buffer, defer, "(", args, keywords, ")")
+                    str_buffer.begin_call();
+                    push_list(str_buffer, defer, "(", args, keywords, ")");
+                    str_buffer.finish_call();
                 }
                 "".to_string()
             }
@@ -309,6 +329,36 @@ impl CustomDisplay for ast::Expr {
                 push_list(str_buffer, defer, "[", elts, &vec![], "]");
                 "".to_string()
             }
+            ast::Expr::BinOp(ast::ExprBinOp {
+                left,
+                op: ast::Operator::Add,
+                right,
+                ..
+            }) => {
+                left.custom_fmt(str_buffer, defer);
+                str_buffer.push(" + ");
+                right.custom_fmt(str_buffer, defer);
+                "".to_string()
+            }
+            // Like buildifier, dicts (e.g. inside select()) always get one entry per line
+            ast::Expr::Dict(ast::ExprDict { keys, values, .. }) if !keys.is_empty() => {
+                str_buffer.push("{").finish_line().indent();
+                for (key, value) in keys.iter().zip(values.iter()) {
+                    match key {
+                        Some(key) => {
+                            key.custom_fmt(str_buffer, defer);
+                            str_buffer.push(": ");
+                        }
+                        None => {
+                            str_buffer.push("**");
+                        }
+                    }
+                    value.custom_fmt(str_buffer, defer);
+                    str_buffer.push(",").finish_line();
+                }
+                str_buffer.deindent().push("}");
+                "".to_string()
+            }
             _ => push(str_buffer, defer, format!("{}", self)),
         }
     }
@@ -364,6 +414,51 @@ filegroup(
         )
     }
 
+    #[test]
+    fn round_trip_build_file_select() {
+        assert_round_trip(
+            r#"py_library(
+    name = "foo",
+    srcs = ["foo.py"],
+    visibility = ["//visibility:public"],
+    deps = ["//a"] + select({
+        "@platforms//os:linux": [
+            "//third_party:cuda",
+            "//third_party:nccl",
+        ],
+        "//conditions:default": [],
+    }),
+)"#,
+        )
+    }
+
+    #[test]
+    fn round_trip_buildifier_formatted() {
+        // As buildifier --type=build formats it
+        assert_round_trip(
+            r#"load("@rules_java//java:defs.bzl", "java_library")
+
+exports_files(glob(["*.txt"]))
+
+java_library(
+    name = "lib",
+    srcs = glob(
+        [
+            "**/*.java",
+            "**/*.kt",
+        ],
+        exclude = ["scratch/**"],
+    ),
+    javacopts = ["-Dgreeting=\"hi\" -Dsep=\\"],
+    visibility = ["//visibility:public"],
+    deps = [
+        ":a",
+        "//b",
+    ],
+)"#,
+        )
+    }
+
     #[test]
     fn round_trip_python_source() {
         assert_round_trip(
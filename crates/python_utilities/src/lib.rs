@@ -3,6 +3,7 @@ use rustpython_parser::{ast, Parse};
 
 pub mod ast_builder;
 pub mod ast_printer;
+pub mod starlark;
 use ast_printer::{emit_body, WritingBuffer};
 
 #[derive(PartialEq)]
@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+
+use ast::{Constant, Expr, Ranged, Stmt};
+use rustpython_parser::ast;
+
+use crate::PythonProgram;
+
+/// An argument of a call. String literals are unquoted, anything else is kept as written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StarlarkArg {
+    pub key: Option<String>,
+    pub value: String,
+    // the entries of the value when it is a list literal, given the same way
+    pub entries: Option<Vec<String>>,
+}
+
+/// A top-level call in a Starlark file such as BUILD or MODULE.bazel, with the variable it's
+/// assigned to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StarlarkCall {
+    pub assigned_to: Option<String>,
+    pub function: String,
+    // in source order
+    pub args: Vec<StarlarkArg>,
+}
+
+impl StarlarkCall {
+    pub fn positional(&self) -> Vec<&str> {
+        self.args
+            .iter()
+            .filter(|a| a.key.is_none())
+            .map(|a| a.value.as_str())
+            .collect()
+    }
+
+    pub fn keyword(&self, key: &str) -> Option<&str> {
+        self.args
+            .iter()
+            .find(|a| a.key.as_deref() == Some(key))
+            .map(|a| a.value.as_str())
+    }
+}
+
+/// The top-level calls of a Starlark file, e.g. the targets of a BUILD file or the `bazel_dep`
+/// and `use_repo` calls of MODULE.bazel.
+pub fn starlark_calls(src: &str) -> anyhow::Result<Vec<StarlarkCall>> {
+    let program = PythonProgram::parse(src, "<starlark>")?;
+    let text = |value: &Expr| match value {
+        Expr::Constant(ast::ExprConstant {
+            value: Constant::Str(s),
+            ..
+        }) => s.to_string(),
+        _ => src[usize::from(value.start())..usize::from(value.end())].to_string(),
+    };
+
+    let mut calls = Vec::default();
+    for stmt in program.body.iter() {
+        let (assigned_to, value) = match stmt {
+            Stmt::Expr(ast::StmtExpr { value, .. }) => (None, value),
+            Stmt::Assign(ast::StmtAssign { targets, value, .. }) => {
+                let assigned_to = match targets.as_slice() {
+                    [Expr::Name(ast::ExprName { id, .. })] => Some(id.to_string()),
+                    _ => None,
+                };
+                (assigned_to, value)
+            }
+            _ => continue,
+        };
+        let Expr::Call(call) = value.as_ref() else {
+            continue;
+        };
+        let function = match call.func.as_ref() {
+            Expr::Name(ast::ExprName { id, .. }) => id.to_string(),
+            Expr::Attribute(ast::ExprAttribute { attr, .. }) => attr.to_string(),
+            _ => continue,
+        };
+        let mut values: Vec<(Option<String>, &Expr)> =
+            call.args.iter().map(|a| (None, a)).collect();
+        values.extend(
+            call.keywords
+                .iter()
+                .map(|k| (k.arg.as_ref().map(|a| a.to_string()), &k.value)),
+        );
+        values.sort_by_key(|(_, v)| v.start());
+        let args = values
+            .into_iter()
+            .map(|(key, value)| StarlarkArg {
+                key,
+                value: text(value),
+                entries: match value {
+                    Expr::List(ast::ExprList { elts, .. }) => Some(elts.iter().map(text).collect()),
+                    _ => None,
+                },
+            })
+            .collect();
+        calls.push(StarlarkCall {
+            assigned_to,
+            function,
+            args,
+        });
+    }
+    Ok(calls)
+}
+
+/// The attributes of each named target in a BUILD file, keyed by target name then attribute.
+/// List values are split into their entries, other values are a single entry.
+pub fn target_attributes(
+    src: &str,
+) -> anyhow::Result<BTreeMap<String, BTreeMap<String, Vec<String>>>> {
+    let mut targets = BTreeMap::default();
+    for call in starlark_calls(src)?
+        .into_iter()
+        .filter(|c| c.function != "load")
+    {
+        let Some(name) = call.keyword("name").map(|n| n.to_string()) else {
+            continue;
+        };
+        let attributes = call
+            .args
+            .into_iter()
+            .filter_map(|a| Some((a.key?, a.entries.unwrap_or_else(|| vec![a.value]))))
+            .collect();
+        targets.insert(name, attributes);
+    }
+    Ok(targets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_calls() {
+        let src = r#"bazel_dep(name = "rules_jvm_external", version = "6.0")
+maven = use_extension("@rules_jvm_external//:extensions.bzl", "maven")
+use_repo(maven, "maven", guava = "maven_guava")
+"#;
+        let calls = starlark_calls(src).unwrap();
+        assert_eq!(calls.len(), 3);
+        assert_eq!(calls[0].keyword("name"), Some("rules_jvm_external"));
+        assert_eq!(calls[1].assigned_to.as_deref(), Some("maven"));
+        assert_eq!(
+            calls[1].positional(),
+            vec!["@rules_jvm_external//:extensions.bzl", "maven"]
+        );
+        assert_eq!(calls[2].positional(), vec!["maven", "maven"]);
+        assert_eq!(calls[2].keyword("guava"), Some("maven_guava"));
+    }
+
+    #[test]
+    fn reads_target_attributes() {
+        let src = r#"load("@rules_python//python:defs.bzl", "py_library")
+
+py_library(
+    name = "foo",
+    srcs = ["foo.py"],
+    deps = [
+        "//a",  # keep
+        ":b",
+    ],
+    visibility = ["//visibility:public"],
+    testonly = True,
+)
+
+exports_files(["x.txt"])
+"#;
+        let targets = target_attributes(src).unwrap();
+        assert_eq!(targets.len(), 1);
+        let foo = &targets["foo"];
+        assert_eq!(foo["name"], vec!["foo"]);
+        assert_eq!(foo["deps"], vec!["//a", ":b"]);
+        assert_eq!(foo["testonly"], vec!["True"]);
+    }
+}
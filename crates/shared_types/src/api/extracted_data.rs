@@ -1,4 +1,4 @@
-use std::collections::{BTreeSet, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 
 use serde::{Deserialize, Serialize};
 
@@ -18,6 +18,23 @@ pub struct DataBlock {
     pub defs: BTreeSet<String>,
     #[serde(serialize_with = "crate::serde_helpers::ordered_set")]
     pub refs: HashSet<String>,
+    /// References an extractor dropped because of a line-level ignore, kept for explaining output.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub ignored_refs: BTreeSet<String>,
+    /// References only needed at runtime, e.g. modules loaded dynamically by name.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub runtime_refs: BTreeSet<String>,
     #[serde(default, serialize_with = "crate::serde_helpers::ordered_set")]
     pub bzl_gen_build_commands: HashSet<String>,
+    /// 1-based line each of the `bzl_gen_build_commands` was found on, extractors may omit this.
+    #[serde(
+        default,
+        skip_serializing_if = "HashMap::is_empty",
+        serialize_with = "crate::serde_helpers::ordered_map"
+    )]
+    pub bzl_gen_build_command_lines: HashMap<String, usize>,
+    /// The `bzl_gen_build_commands` the extractor added by itself rather than read from the
+    /// source, such as the `binary_generate` of a Python `__main__` block.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub inferred_bzl_gen_build_commands: BTreeSet<String>,
 }
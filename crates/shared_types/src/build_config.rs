@@ -15,6 +15,14 @@ pub struct BuildConfig {
 
     #[serde(default)]
     pub secondary_rules: BTreeMap<String, GrpBuildConfig>,
+
+    /// The secondary rule targets of another configuration depend on instead of the main
+    /// target, by the name of that configuration, e.g. `{"python": "py"}`
+    #[serde(default)]
+    pub secondary_rule_for: BTreeMap<String, String>,
+
+    #[serde(default)]
+    pub child_filegroup: ChildFilegroupConfig,
 }
 
 impl BuildConfig {
@@ -30,7 +38,77 @@ impl BuildConfig {
             (Some(_), Some(_)) => panic!("Unable to merge two specified build configs for test"),
             _ => (),
         };
+
+        for (consumer, secondary) in other.secondary_rule_for {
+            match self.secondary_rule_for.get(&consumer) {
+                Some(s) if *s != secondary => panic!(
+                    "Unable to merge secondary rules {} and {} for {}",
+                    s, secondary, consumer
+                ),
+                _ => {
+                    self.secondary_rule_for.insert(consumer, secondary);
+                }
+            }
+        }
+
+        if self.child_filegroup == ChildFilegroupConfig::default() {
+            self.child_filegroup = other.child_filegroup;
+        }
+    }
+}
+
+/// The target written into the BUILD file of each child directory collapsed into its parent,
+/// which the parent target takes its sources from.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ChildFilegroupConfig {
+    /// `{folder}` is replaced with the name of the child directory
+    #[serde(default = "default_child_filegroup_name")]
+    pub name: String,
+    #[serde(default = "default_child_filegroup_function_name")]
+    pub function_name: String,
+    #[serde(default)]
+    pub headers: Vec<BuildLoad>,
+    /// globs included next to the ones for each of the `file_extensions`, e.g. for resources
+    #[serde(default)]
+    pub extra_globs: Vec<String>,
+    /// how child BUILD files are written, the write mode of the run when not set
+    #[serde(default)]
+    pub write_mode: Option<WriteMode>,
+}
+
+impl Default for ChildFilegroupConfig {
+    fn default() -> Self {
+        ChildFilegroupConfig {
+            name: default_child_filegroup_name(),
+            function_name: default_child_filegroup_function_name(),
+            headers: Vec::default(),
+            extra_globs: Vec::default(),
+            write_mode: None,
+        }
+    }
+}
+
+impl ChildFilegroupConfig {
+    pub fn target_name(&self, folder_name: &str) -> String {
+        self.name.replace("{folder}", folder_name)
     }
+
+    /// A glob for each of the file extensions, followed by the extra globs.
+    pub fn globs(&self, file_extensions: &[String]) -> Vec<String> {
+        file_extensions
+            .iter()
+            .map(|e| format!("**/*.{}", e))
+            .chain(self.extra_globs.iter().cloned())
+            .collect()
+    }
+}
+
+fn default_child_filegroup_name() -> String {
+    "{folder}_files".to_string()
+}
+
+fn default_child_filegroup_function_name() -> String {
+    "filegroup".to_string()
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
@@ -39,7 +117,7 @@ pub struct BuildLoad {
     pub load_value: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq, Copy)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum TargetNameStrategy {
     /// automatic default
@@ -47,6 +125,8 @@ pub enum TargetNameStrategy {
     Auto,
     /// use the file stem (file name without the extension) of the source code
     SourceFileStem,
+    /// expand a template such as `{stem}_lib`, where `{stem}` is the file stem of the source code
+    Template(String),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
@@ -74,6 +154,8 @@ pub enum WriteMode {
     Append,
     /// replace only the section between BEGIN/END BZL_GEN_BUILD_<tag>_GENERATED_CODE (multi-language safe)
     OverwriteTag(String),
+    /// update the generated attributes of existing targets, keeping hand-written content
+    Merge,
 }
 
 impl Default for WriteMode {
@@ -83,9 +165,11 @@ impl Default for WriteMode {
 }
 
 impl WriteMode {
-    pub fn new(append: bool, overwrite_tag: Option<String>) -> WriteMode {
+    pub fn new(append: bool, merge: bool, overwrite_tag: Option<String>) -> WriteMode {
         if let Some(tag) = overwrite_tag {
             WriteMode::OverwriteTag(tag)
+        } else if merge {
+            WriteMode::Merge
         } else if append {
             WriteMode::Append
         } else {
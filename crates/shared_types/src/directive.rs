@@ -11,6 +11,8 @@ use nom::{
 };
 use serde::{Deserialize, Serialize};
 
+use crate::entity_pattern::EntityPattern;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum SrcDirective {
     Ref,
@@ -33,6 +35,14 @@ impl SrcDirective {
             value(SrcDirective::RuntimeUnref, tag("runtime_unref")),
         ))(input)
     }
+
+    /// Removal directives may act on wildcard or regex patterns.
+    pub fn is_removal(&self) -> bool {
+        matches!(
+            self,
+            SrcDirective::Unref | SrcDirective::Undef | SrcDirective::RuntimeUnref
+        )
+    }
 }
 impl std::fmt::Display for SrcDirective {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -78,6 +88,9 @@ impl std::fmt::Display for ManualRefDirective {
 pub enum AttrDirective {
     StringList,
     LabelList,
+    String,
+    Bool,
+    Int,
 }
 impl AttrDirective {
     pub fn parse<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
@@ -86,6 +99,9 @@ impl AttrDirective {
         alt((
             value(AttrDirective::LabelList, tag("attr.label_list")),
             value(AttrDirective::StringList, tag("attr.string_list")),
+            value(AttrDirective::String, tag("attr.string")),
+            value(AttrDirective::Bool, tag("attr.bool")),
+            value(AttrDirective::Int, tag("attr.int")),
         ))(input)
     }
 }
@@ -94,6 +110,9 @@ impl std::fmt::Display for AttrDirective {
         match self {
             AttrDirective::LabelList => write!(f, "attr.label_list"),
             AttrDirective::StringList => write!(f, "attr.string_list"),
+            AttrDirective::String => write!(f, "attr.string"),
+            AttrDirective::Bool => write!(f, "attr.bool"),
+            AttrDirective::Int => write!(f, "attr.int"),
         }
     }
 }
@@ -158,6 +177,9 @@ pub struct EntityDirectiveConfig {
 pub struct ManualRefConfig {
     pub command: ManualRefDirective,
     pub target_value: String,
+    /// config_setting label the reference is conditional on, rendered through select()
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub condition: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
@@ -166,12 +188,51 @@ pub struct AttrStringListConfig {
     pub values: Vec<String>,
 }
 
+/// A typed scalar attribute value, emitted as a string, bool or int literal.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttrValue {
+    String(String),
+    Bool(bool),
+    Int(i64),
+}
+impl AttrValue {
+    pub fn attr_directive(&self) -> AttrDirective {
+        match self {
+            AttrValue::String(_) => AttrDirective::String,
+            AttrValue::Bool(_) => AttrDirective::Bool,
+            AttrValue::Int(_) => AttrDirective::Int,
+        }
+    }
+}
+impl std::fmt::Display for AttrValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AttrValue::String(s) => write!(f, "\"{}\"", s),
+            AttrValue::Bool(true) => write!(f, "True"),
+            AttrValue::Bool(false) => write!(f, "False"),
+            AttrValue::Int(i) => write!(f, "{}", i),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub struct AttrScalarConfig {
+    pub attr_name: String,
+    pub value: AttrValue,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct BinaryRefAndPath {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub entity_path: Option<String>,
 
     pub binary_refs: BinaryRefConfig,
+
+    /// Added by the extractor rather than asked for with a directive, e.g. for a Python
+    /// `__main__` block. Configurations without a `binary_application` leave these out.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub inferred: bool,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
@@ -181,6 +242,38 @@ pub struct BinaryRefConfig {
     pub target_value: Option<String>,
 }
 
+/// Where a directive was written, so problems can be reported against it.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DirectiveOrigin {
+    /// an inline comment in a source file, the line is 1-based when the extractor reports it
+    File { path: String, line: Option<usize> },
+    /// an entry of the `path_directives` in the project configuration
+    PathDirective { prefix: String },
+}
+impl std::fmt::Display for DirectiveOrigin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DirectiveOrigin::File {
+                path,
+                line: Some(line),
+            } => write!(f, "{}:{}", path, line),
+            DirectiveOrigin::File { path, line: None } => write!(f, "{}", path),
+            DirectiveOrigin::PathDirective { prefix } => {
+                write!(f, "path_directives with prefix '{}'", prefix)
+            }
+        }
+    }
+}
+
+/// A directive that changed nothing where it was applied, reported by directive linting.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+pub struct IneffectiveDirective {
+    pub directive: String,
+    pub origin: DirectiveOrigin,
+    pub reason: String,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
 pub enum Directive {
     SrcDirective(SrcDirectiveConfig),
@@ -188,6 +281,10 @@ pub enum Directive {
     ManualRef(ManualRefConfig),
     BinaryRef(BinaryRefConfig),
     AttrStringList(AttrStringListConfig),
+    AttrScalar(AttrScalarConfig),
+    TargetName(String),
+    Ignore,
+    NoBinary,
 }
 
 impl Directive {
@@ -200,11 +297,10 @@ impl Directive {
         for str_directive in e.into_iter() {
             match Directive::parse::<(&str, nom::error::ErrorKind)>(str_directive.as_str()) {
                 Ok((_, d)) => directives.push(d),
-                Err(err) => {
+                Err(_) => {
                     return Err(anyhow::anyhow!(
-                        "Error parsing directive {}, error: {:?}",
-                        str_directive,
-                        err
+                        "Error parsing directive:\n{}",
+                        Directive::describe_parse_error(str_directive)
                     ))
                 }
             }
@@ -212,6 +308,79 @@ impl Directive {
         Ok(directives)
     }
 
+    pub fn from_strings_with_origin<'a, T>(
+        e: T,
+        origin: &DirectiveOrigin,
+    ) -> anyhow::Result<Vec<Directive>>
+    where
+        T: IntoIterator<Item = &'a String> + Copy + std::fmt::Debug,
+    {
+        e.into_iter()
+            .map(|str_directive| Directive::parse_with_origin(str_directive, origin))
+            .collect()
+    }
+
+    pub fn parse_with_origin(
+        str_directive: &str,
+        origin: &DirectiveOrigin,
+    ) -> anyhow::Result<Directive> {
+        match Directive::parse::<(&str, nom::error::ErrorKind)>(str_directive) {
+            Ok((_, d)) => Ok(d),
+            Err(_) => Err(anyhow::anyhow!(
+                "Error parsing directive in {}:\n{}",
+                origin,
+                Directive::describe_parse_error(str_directive)
+            )),
+        }
+    }
+
+    // Renders the directive with a caret under the point where parsing failed. Every
+    // directive parser is run again and the one that got furthest into the input wins.
+    fn describe_parse_error(str_directive: &str) -> String {
+        let (input, _) =
+            space0::<&str, nom::error::VerboseError<&str>>(str_directive).unwrap_or_default();
+        let mut furthest: Option<nom::error::VerboseError<&str>> = None;
+        for parser in Directive::parsers::<nom::error::VerboseError<&str>>() {
+            if let Err(nom::Err::Error(e) | nom::Err::Failure(e)) = parser(input) {
+                let remaining = |e: &nom::error::VerboseError<&str>| {
+                    e.errors.first().map(|(i, _)| i.len()).unwrap_or(usize::MAX)
+                };
+                let further = match &furthest {
+                    Some(f) => remaining(&e) < remaining(f),
+                    None => true,
+                };
+                if further {
+                    furthest = Some(e);
+                }
+            }
+        }
+
+        let (offset, message) = match furthest {
+            Some(e) if !e.errors.is_empty() && e.errors[0].0.len() < input.len() => {
+                let offset = str_directive.len() - e.errors[0].0.len();
+                let context = e.errors.iter().find_map(|(_, kind)| match kind {
+                    nom::error::VerboseErrorKind::Context(c) => Some(c.to_string()),
+                    _ => None,
+                });
+                let message = context.unwrap_or_else(|| match &e.errors[0].1 {
+                    nom::error::VerboseErrorKind::Char(c) => format!("expected '{}'", c),
+                    nom::error::VerboseErrorKind::Nom(nom::error::ErrorKind::Eof) => {
+                        "unexpected trailing input".to_string()
+                    }
+                    nom::error::VerboseErrorKind::Nom(kind) => kind.description().to_lowercase(),
+                    nom::error::VerboseErrorKind::Context(c) => c.to_string(),
+                });
+                (offset, message)
+            }
+            _ => (
+                str_directive.len() - input.len(),
+                "unknown directive".to_string(),
+            ),
+        };
+        let column = str_directive[..offset].chars().count();
+        format!("  {}\n  {}^ {}", str_directive, " ".repeat(column), message)
+    }
+
     fn entity_block<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
         input: &'a str,
     ) -> IResult<&'a str, String, E> {
@@ -224,6 +393,18 @@ impl Directive {
         Ok((input, d.to_string()))
     }
 
+    // An entity block that may hold a wildcard or `re:` pattern, which must compile.
+    fn entity_pattern_block<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
+        input: &'a str,
+    ) -> IResult<&'a str, String, E> {
+        nom::error::context(
+            "invalid entity pattern",
+            nom::combinator::verify(Directive::entity_block, |e: &String| {
+                EntityPattern::new(e).is_ok()
+            }),
+        )(input)
+    }
+
     fn parse_binary_ref_directive<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
         input: &'a str,
     ) -> IResult<&'a str, Directive, E> {
@@ -282,7 +463,16 @@ impl Directive {
     fn parse_manual_ref_directive<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
         input: &'a str,
     ) -> IResult<&'a str, Directive, E> {
+        // manual_ref[@platforms//os:linux]: //third_party:cuda
         let (input, src_d) = ManualRefDirective::parse(input)?;
+        let (input, condition) = nom::combinator::opt(nom::error::context(
+            "condition label in brackets",
+            nom::sequence::delimited(
+                nom::character::complete::char('['),
+                nom::bytes::complete::take_while1(|e: char| !(e == ']' || e.is_whitespace())),
+                nom::character::complete::char(']'),
+            ),
+        ))(input)?;
         let (input, _) = nom::error::context(
             "colon after entity",
             tuple((space0, nom::bytes::complete::tag(":"), space0)),
@@ -303,6 +493,7 @@ impl Directive {
             Directive::ManualRef(ManualRefConfig {
                 command: src_d,
                 target_value: d.to_string(),
+                condition: condition.map(|c| c.to_string()),
             }),
         ))
     }
@@ -315,7 +506,12 @@ impl Directive {
             "colon after entity",
             tuple((space0, nom::bytes::complete::tag(":"), space0)),
         )(input)?;
-        let (input, src_entity) = Directive::entity_block(input)?;
+        let (input, src_entity) = nom::error::context(
+            "patterns are only supported when removing entities",
+            nom::combinator::verify(Directive::entity_pattern_block, |e: &String| {
+                src_d.is_removal() || !EntityPattern::is_pattern(e)
+            }),
+        )(input)?;
         let (input, _) = space0(input)?;
         let (input, _) = nom::combinator::eof(input)?;
 
@@ -366,7 +562,15 @@ impl Directive {
         // link:com.foo.bar.baz -> {org.example.Z, org.ppp.QQQ,org.eee.lll.QQQ}
         let (input, src_d) = EntityDirective::parse(input)?;
         let (input, _) = tuple((space0, nom::bytes::complete::tag(":"), space0))(input)?;
+        let act_on_input = input;
         let (input, (src_entity, dest_entities)) = Self::parse_key_string_list(input)?;
+        if EntityPattern::new(&src_entity).is_err() {
+            return Err(nom::Err::Error(E::add_context(
+                act_on_input,
+                "invalid entity pattern",
+                E::from_error_kind(act_on_input, nom::error::ErrorKind::Verify),
+            )));
+        }
 
         Ok((
             input,
@@ -381,7 +585,9 @@ impl Directive {
     fn parse_attr_string_list_directive<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
         input: &'a str,
     ) -> IResult<&'a str, Directive, E> {
-        let (input, _) = AttrDirective::parse(input)?;
+        let (input, _) = nom::combinator::verify(AttrDirective::parse, |d| {
+            matches!(d, AttrDirective::LabelList | AttrDirective::StringList)
+        })(input)?;
         let (input, _) = tuple((space0, nom::bytes::complete::tag(":"), space0))(input)?;
         let (input, (key, values)) = Self::parse_key_string_list(input)?;
 
@@ -394,17 +600,134 @@ impl Directive {
         ))
     }
 
+    fn parse_attr_scalar_directive<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
+        input: &'a str,
+    ) -> IResult<&'a str, Directive, E> {
+        // attr.bool: testonly -> True
+        let (input, attr_d) = AttrDirective::parse(input)?;
+        let (input, _) = tuple((space0, nom::bytes::complete::tag(":"), space0))(input)?;
+        let (input, attr_name) = Directive::entity_block(input)?;
+        let (input, _) = tuple((space0, nom::bytes::complete::tag("->"), space0))(input)?;
+
+        fn parse_string<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
+            input: &'a str,
+        ) -> IResult<&'a str, AttrValue, E> {
+            let (input, s) = alt((
+                nom::sequence::delimited(
+                    nom::character::complete::char('"'),
+                    nom::bytes::complete::take_while(|e| e != '"'),
+                    nom::character::complete::char('"'),
+                ),
+                nom::bytes::complete::take_while1(|e: char| !e.is_whitespace()),
+            ))(input)?;
+            Ok((input, AttrValue::String(s.to_string())))
+        }
+
+        let (input, value) = match attr_d {
+            AttrDirective::String => {
+                nom::error::context("parsing attr.string value", parse_string)(input)?
+            }
+            AttrDirective::Bool => nom::error::context(
+                "parsing attr.bool value",
+                alt((
+                    value(AttrValue::Bool(true), alt((tag("True"), tag("true")))),
+                    value(AttrValue::Bool(false), alt((tag("False"), tag("false")))),
+                )),
+            )(input)?,
+            AttrDirective::Int => nom::error::context(
+                "parsing attr.int value",
+                nom::character::complete::i64.map(AttrValue::Int),
+            )(input)?,
+            AttrDirective::StringList | AttrDirective::LabelList => {
+                return Err(nom::Err::Error(E::from_error_kind(
+                    input,
+                    nom::error::ErrorKind::Verify,
+                )))
+            }
+        };
+        let (input, _) = space0(input)?;
+        let (input, _) = nom::combinator::eof(input)?;
+
+        Ok((
+            input,
+            Directive::AttrScalar(AttrScalarConfig { attr_name, value }),
+        ))
+    }
+
+    fn parse_target_name_directive<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
+        input: &'a str,
+    ) -> IResult<&'a str, Directive, E> {
+        // target_name: legacy_lib
+        let (input, _) = tag("target_name")(input)?;
+        let (input, _) = nom::error::context(
+            "colon after target_name",
+            tuple((space0, nom::bytes::complete::tag(":"), space0)),
+        )(input)?;
+        let (input, name) = nom::error::context(
+            "bazel target name parsing",
+            nom::bytes::complete::take_while1(|e: char| !(e.is_whitespace() || e == ':')),
+        )(input)?;
+        let (input, _) = space0(input)?;
+        let (input, _) = nom::combinator::eof(input)?;
+        Ok((input, Directive::TargetName(name.to_string())))
+    }
+
+    fn parse_ignore_directive<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
+        input: &'a str,
+    ) -> IResult<&'a str, Directive, E> {
+        let (input, _) = tag("ignore")(input)?;
+        let (input, _) = space0(input)?;
+        let (input, _) = nom::combinator::eof(input)?;
+        Ok((input, Directive::Ignore))
+    }
+
+    fn parse_no_binary_directive<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
+        input: &'a str,
+    ) -> IResult<&'a str, Directive, E> {
+        let (input, _) = tag("no_binary")(input)?;
+        let (input, _) = space0(input)?;
+        let (input, _) = nom::combinator::eof(input)?;
+        Ok((input, Directive::NoBinary))
+    }
+
     pub fn parse<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
         input: &'a str,
     ) -> IResult<&'a str, Directive, E> {
         let (input, _) = space0(input)?;
-        alt((
+        let mut error: Option<E> = None;
+        for parser in Directive::parsers() {
+            match parser(input) {
+                Err(nom::Err::Error(e)) => {
+                    error = Some(match error {
+                        Some(prev) => prev.or(e),
+                        None => e,
+                    })
+                }
+                res => return res,
+            }
+        }
+        let error = error.unwrap_or_else(|| E::from_error_kind(input, nom::error::ErrorKind::Alt));
+        Err(nom::Err::Error(E::append(
+            input,
+            nom::error::ErrorKind::Alt,
+            error,
+        )))
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn parsers<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
+    ) -> [fn(&'a str) -> IResult<&'a str, Directive, E>; 9] {
+        [
             Directive::parse_src_directive,
             Directive::parse_entity_directive,
             Directive::parse_manual_ref_directive,
             Directive::parse_binary_ref_directive,
             Directive::parse_attr_string_list_directive,
-        ))(input)
+            Directive::parse_attr_scalar_directive,
+            Directive::parse_target_name_directive,
+            Directive::parse_ignore_directive,
+            Directive::parse_no_binary_directive,
+        ]
     }
 
     pub fn extract_directive(raw_comment: &str, comment_prefix: &str) -> Option<String> {
@@ -444,9 +767,13 @@ impl std::fmt::Display for Directive {
             Directive::ManualRef(ManualRefConfig {
                 command,
                 target_value,
+                condition,
             }) => {
-                write!(f, "{}:", command)?;
-                write!(f, "{}", target_value)?;
+                write!(f, "{}", command)?;
+                if let Some(c) = condition {
+                    write!(f, "[{}]", c)?;
+                }
+                write!(f, ":{}", target_value)?;
             }
             Directive::BinaryRef(BinaryRefConfig {
                 command,
@@ -472,6 +799,12 @@ impl std::fmt::Display for Directive {
             Directive::AttrStringList(AttrStringListConfig { attr_name, values }) => {
                 write_string_list(f, &"attr.string_list".to_string(), attr_name, values)?;
             }
+            Directive::AttrScalar(AttrScalarConfig { attr_name, value }) => {
+                write!(f, "{}:{} -> {}", value.attr_directive(), attr_name, value)?;
+            }
+            Directive::TargetName(name) => write!(f, "target_name:{}", name)?,
+            Directive::Ignore => write!(f, "ignore")?,
+            Directive::NoBinary => write!(f, "no_binary")?,
         }
         Ok(())
     }
@@ -530,7 +863,8 @@ mod tests {
             parse_to_directive("manual_runtime_ref://:build_gradle_properties_jar"),
             Directive::ManualRef(ManualRefConfig {
                 command: ManualRefDirective::RuntimeRef,
-                target_value: "//:build_gradle_properties_jar".to_string()
+                target_value: "//:build_gradle_properties_jar".to_string(),
+                condition: None,
             })
         );
 
@@ -538,10 +872,25 @@ mod tests {
             parse_to_directive("data_ref://x/y/z:artifact"),
             Directive::ManualRef(ManualRefConfig {
                 command: ManualRefDirective::DataRef,
-                target_value: "//x/y/z:artifact".to_string()
+                target_value: "//x/y/z:artifact".to_string(),
+                condition: None,
             })
         );
 
+        assert_eq!(
+            parse_to_directive("manual_ref[@platforms//os:linux]: //third_party:cuda"),
+            Directive::ManualRef(ManualRefConfig {
+                command: ManualRefDirective::Ref,
+                target_value: "//third_party:cuda".to_string(),
+                condition: Some("@platforms//os:linux".to_string()),
+            })
+        );
+        assert_eq!(
+            parse_to_directive("manual_runtime_ref[//config:prod]: //jdbc:postgres").to_string(),
+            "manual_runtime_ref[//config:prod]://jdbc:postgres"
+        );
+        assert!(Directive::parse::<(&str, ErrorKind)>("manual_ref[]: //a").is_err());
+
         assert_eq!(
             parse_to_directive("attr.label_list: plugins -> { //x/y/z:artifact }"),
             Directive::AttrStringList(AttrStringListConfig {
@@ -558,6 +907,43 @@ mod tests {
             })
         );
 
+        assert_eq!(
+            parse_to_directive("attr.bool: testonly -> True"),
+            Directive::AttrScalar(AttrScalarConfig {
+                attr_name: "testonly".to_string(),
+                value: AttrValue::Bool(true)
+            })
+        );
+
+        assert_eq!(
+            parse_to_directive("attr.int: shard_count -> 4"),
+            Directive::AttrScalar(AttrScalarConfig {
+                attr_name: "shard_count".to_string(),
+                value: AttrValue::Int(4)
+            })
+        );
+
+        assert_eq!(
+            parse_to_directive("attr.string: size -> large"),
+            Directive::AttrScalar(AttrScalarConfig {
+                attr_name: "size".to_string(),
+                value: AttrValue::String("large".to_string())
+            })
+        );
+
+        assert_eq!(
+            parse_to_directive("attr.string: main -> \"app main.py\""),
+            Directive::AttrScalar(AttrScalarConfig {
+                attr_name: "main".to_string(),
+                value: AttrValue::String("app main.py".to_string())
+            })
+        );
+        let quoted = parse_to_directive("attr.string: main -> \"app main.py\"");
+        assert_eq!(parse_to_directive(&quoted.to_string()), quoted);
+
+        assert!(Directive::parse::<(&str, ErrorKind)>("attr.int: shard_count -> four").is_err());
+        assert!(Directive::parse::<(&str, ErrorKind)>("attr.bool: testonly -> yes").is_err());
+
         assert_eq!(
             parse_to_directive("binary_generate: my_binary"),
             Directive::BinaryRef(BinaryRefConfig {
@@ -585,8 +971,94 @@ mod tests {
             })
         );
     }
+    #[test]
+    fn parse_error_messages() {
+        let origin = DirectiveOrigin::File {
+            path: "src/main/python/a.py".to_string(),
+            line: Some(3),
+        };
+        let err = |input: &str| {
+            Directive::parse_with_origin(input, &origin)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            err("unref: re:("),
+            "Error parsing directive in src/main/python/a.py:3:\n  unref: re:(\n         ^ invalid entity pattern"
+        );
+        assert_eq!(
+            err("attr.int: shard_count -> four"),
+            "Error parsing directive in src/main/python/a.py:3:\n  attr.int: shard_count -> four\n                           ^ parsing attr.int value"
+        );
+        assert_eq!(
+            err("refs: com.foo"),
+            "Error parsing directive in src/main/python/a.py:3:\n  refs: com.foo\n     ^ colon after entity"
+        );
+        assert_eq!(
+            err("frobnicate: com.foo"),
+            "Error parsing directive in src/main/python/a.py:3:\n  frobnicate: com.foo\n  ^ unknown directive"
+        );
+        assert_eq!(
+            Directive::from_strings_with_origin(
+                &vec!["manual_ref: //a b".to_string()],
+                &DirectiveOrigin::PathDirective {
+                    prefix: "src/main".to_string()
+                }
+            )
+            .unwrap_err()
+            .to_string(),
+            "Error parsing directive in path_directives with prefix 'src/main':\n  manual_ref: //a b\n                  ^ unexpected trailing input"
+        );
+    }
+
     #[test]
     fn other_parsing() {
+        assert_eq!(parse_to_directive(" ignore "), Directive::Ignore);
+        assert!(Directive::parse::<(&str, ErrorKind)>("ignore: foo").is_err());
+        assert_eq!(parse_to_directive("no_binary"), Directive::NoBinary);
+
+        assert_eq!(
+            parse_to_directive("target_name: legacy_lib "),
+            Directive::TargetName("legacy_lib".to_string())
+        );
+        assert!(Directive::parse::<(&str, ErrorKind)>("target_name: a b").is_err());
+        assert!(Directive::parse::<(&str, ErrorKind)>("target_name: //foo:bar").is_err());
+
+        assert_eq!(
+            parse_to_directive("unref: glob:com.foo.internal.*"),
+            Directive::SrcDirective(SrcDirectiveConfig {
+                command: SrcDirective::Unref,
+                act_on: "glob:com.foo.internal.*".to_string()
+            })
+        );
+        assert_eq!(
+            parse_to_directive(r"undef: re:.*\$Companion"),
+            Directive::SrcDirective(SrcDirectiveConfig {
+                command: SrcDirective::Undef,
+                act_on: r"re:.*\$Companion".to_string()
+            })
+        );
+        assert_eq!(
+            parse_to_directive("link: glob:com.foo.*.Impl -> com.foo.Api"),
+            Directive::EntityDirective(EntityDirectiveConfig {
+                command: EntityDirective::Link,
+                act_on: "glob:com.foo.*.Impl".to_string(),
+                pointing_at: vec!["com.foo.Api".to_string()]
+            })
+        );
+        // patterns can only remove entities, and must compile
+        assert!(Directive::parse::<(&str, ErrorKind)>("ref: glob:com.foo.*").is_err());
+        // without a prefix a `*` is literal, naming a wildcard import
+        assert_eq!(
+            parse_to_directive("ref: com.foo.*"),
+            Directive::SrcDirective(SrcDirectiveConfig {
+                command: SrcDirective::Ref,
+                act_on: "com.foo.*".to_string()
+            })
+        );
+        assert!(Directive::parse::<(&str, ErrorKind)>("unref: re:(").is_err());
+        assert!(Directive::parse::<(&str, ErrorKind)>("link: re:( -> com.foo.Api").is_err());
+
         assert_eq!(
             parse_to_directive("runtime_ref:com.example.foo.bar.baz.Elephant"),
             Directive::SrcDirective(SrcDirectiveConfig {
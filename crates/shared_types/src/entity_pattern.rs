@@ -0,0 +1,93 @@
+use regex::Regex;
+
+/// Matches entities named in directives. Plain values match exactly, a `*` included, values
+/// prefixed with `glob:` are wildcards (`glob:com.foo.internal.*`) and values prefixed with
+/// `re:` are regular expressions (`re:.*\$Companion`). Wildcards and regular expressions must
+/// match the whole entity.
+#[derive(Debug, Clone)]
+pub enum EntityPattern {
+    Exact(String),
+    Pattern(Regex),
+}
+
+impl EntityPattern {
+    pub fn new(value: &str) -> anyhow::Result<EntityPattern> {
+        if let Some(re) = value.strip_prefix("re:") {
+            Ok(EntityPattern::Pattern(Regex::new(&format!(
+                "^(?:{})$",
+                re
+            ))?))
+        } else if let Some(glob) = value.strip_prefix("glob:") {
+            EntityPattern::from_glob(glob)
+        } else {
+            Ok(EntityPattern::Exact(value.to_string()))
+        }
+    }
+
+    /// Like `new`, but a bare `*` is a wildcard too. For configuration values, which have
+    /// always been patterns, unlike directives where `*` used to name wildcard imports.
+    pub fn with_wildcards(value: &str) -> anyhow::Result<EntityPattern> {
+        if value.contains('*') && !EntityPattern::is_pattern(value) {
+            EntityPattern::from_glob(value)
+        } else {
+            EntityPattern::new(value)
+        }
+    }
+
+    fn from_glob(glob: &str) -> anyhow::Result<EntityPattern> {
+        let escaped: Vec<String> = glob.split('*').map(regex::escape).collect();
+        Ok(EntityPattern::Pattern(Regex::new(&format!(
+            "^{}$",
+            escaped.join(".*")
+        ))?))
+    }
+
+    /// true for wildcard and regular expression values.
+    pub fn is_pattern(value: &str) -> bool {
+        value.starts_with("re:") || value.starts_with("glob:")
+    }
+
+    pub fn matches(&self, entity: &str) -> bool {
+        match self {
+            EntityPattern::Exact(e) => e == entity,
+            EntityPattern::Pattern(re) => re.is_match(entity),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matching() {
+        let exact = EntityPattern::new("com.foo.Bar").unwrap();
+        assert!(exact.matches("com.foo.Bar"));
+        assert!(!exact.matches("com.foo.Barr"));
+
+        let wildcard = EntityPattern::new("glob:com.foo.internal.*").unwrap();
+        assert!(wildcard.matches("com.foo.internal.Baz"));
+        assert!(wildcard.matches("com.foo.internal.a.b"));
+        assert!(!wildcard.matches("com.foo.internalBaz"));
+        assert!(!wildcard.matches("org.com.foo.internal.Baz"));
+
+        let re = EntityPattern::new(r"re:.*\$Companion").unwrap();
+        assert!(re.matches("com.foo.Bar$Companion"));
+        assert!(!re.matches("com.foo.Bar$Companion.x"));
+
+        // without a prefix a `*` is matched literally, as wildcard imports are named
+        let literal = EntityPattern::new("com.foo.*").unwrap();
+        assert!(literal.matches("com.foo.*"));
+        assert!(!literal.matches("com.foo.Bar"));
+
+        let configured = EntityPattern::with_wildcards("@maven//:commons*").unwrap();
+        assert!(configured.matches("@maven//:commons_io"));
+        let configured = EntityPattern::with_wildcards("@maven//:log4j").unwrap();
+        assert!(!configured.matches("@maven//:log4j2"));
+
+        assert!(EntityPattern::new("re:(").is_err());
+        assert!(EntityPattern::is_pattern("glob:a.*"));
+        assert!(!EntityPattern::is_pattern("a.*"));
+        assert!(!EntityPattern::is_pattern("a.b"));
+    }
+}
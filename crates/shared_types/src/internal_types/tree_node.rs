@@ -1,12 +1,13 @@
-use std::collections::{BTreeSet, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 
 use serde::{Deserialize, Serialize};
 
 use crate::{
     directive::{
-        AttrStringListConfig, BinaryRefAndPath, EntityDirectiveConfig, ManualRefConfig,
-        SrcDirectiveConfig,
+        AttrScalarConfig, AttrStringListConfig, BinaryRefAndPath, DirectiveOrigin,
+        EntityDirectiveConfig, IneffectiveDirective, ManualRefConfig, SrcDirectiveConfig,
     },
+    entity_pattern::EntityPattern,
     Directive,
 };
 
@@ -33,21 +34,61 @@ pub struct TreeNode {
 
     #[serde(default, serialize_with = "crate::serde_helpers::ordered_list")]
     pub attr_string_list_directives: Vec<AttrStringListConfig>,
+
+    #[serde(default, serialize_with = "crate::serde_helpers::ordered_list")]
+    pub attr_scalar_directives: Vec<AttrScalarConfig>,
+
+    #[serde(default, serialize_with = "crate::serde_helpers::ordered_list")]
+    pub target_name_directives: Vec<String>,
+
+    /// Where each applied directive came from, keyed by the directive as written back out.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub directive_origins: BTreeMap<String, BTreeSet<DirectiveOrigin>>,
+
+    /// Directives that changed nothing when applied to this node.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ineffective_directives: Vec<IneffectiveDirective>,
 }
 
 impl TryFrom<crate::api::extracted_data::DataBlock> for TreeNode {
     type Error = anyhow::Error;
 
     fn try_from(value: crate::api::extracted_data::DataBlock) -> Result<Self, Self::Error> {
-        let directives = Directive::from_strings(&value.bzl_gen_build_commands)?;
+        // Apply the directives in the order they appear in the file.
+        let mut commands: Vec<(Option<usize>, &String)> = value
+            .bzl_gen_build_commands
+            .iter()
+            .map(|c| (value.bzl_gen_build_command_lines.get(c).copied(), c))
+            .collect();
+        commands.sort();
+        let mut directives: Vec<(Directive, DirectiveOrigin)> = Vec::default();
+        for (line, command) in commands {
+            let origin = DirectiveOrigin::File {
+                path: value.entity_path.clone(),
+                line,
+            };
+            directives.push((Directive::parse_with_origin(command, &origin)?, origin));
+        }
+        if directives.iter().any(|(d, _)| d == &Directive::Ignore) {
+            return Err(anyhow::anyhow!(
+                "The ignore directive in {} must be in the comment block at the top of the file",
+                value.entity_path
+            ));
+        }
         let mut t = Self {
             label_or_repo_path: String::default(),
             defs: value.defs,
             refs: value.refs,
+            runtime_refs: value.runtime_refs.into_iter().collect(),
             ..Default::default()
         };
 
-        t.apply_directives_with_path(&directives, Some(value.entity_path.as_str()));
+        t.apply_directives_with_origins(&directives);
+        let inferred = Directive::from_strings(&value.inferred_bzl_gen_build_commands)?;
+        for binary_ref in t.binary_ref_directives.iter_mut() {
+            binary_ref.inferred =
+                inferred.contains(&Directive::BinaryRef(binary_ref.binary_refs.clone()));
+        }
 
         Ok(t)
     }
@@ -61,33 +102,84 @@ impl TreeNode {
         }
     }
 
-    fn apply_directives_with_path<'a, T>(&mut self, directives: T, entity_path: Option<&str>)
-    where
-        T: IntoIterator<Item = &'a Directive> + Copy + std::fmt::Debug,
-    {
-        for directive in directives.into_iter() {
-            match directive {
-                Directive::SrcDirective(SrcDirectiveConfig { command, act_on }) => {
-                    match command {
-                        crate::SrcDirective::Ref => self.refs.insert(act_on.clone()),
-                        crate::SrcDirective::Unref => self.refs.remove(act_on),
-                        crate::SrcDirective::Def => self.defs.insert(act_on.clone()),
-                        crate::SrcDirective::Undef => self.defs.remove(act_on),
-                        crate::SrcDirective::RuntimeRef => self.runtime_refs.insert(act_on.clone()),
-                        crate::SrcDirective::RuntimeUnref => self.runtime_refs.remove(act_on),
+    /// Applies one directive, returning why it had no effect when it changed nothing.
+    fn apply_directive(
+        &mut self,
+        directive: &Directive,
+        entity_path: Option<&str>,
+    ) -> Option<&'static str> {
+        match directive {
+            Directive::SrcDirective(SrcDirectiveConfig { command, act_on })
+                if EntityPattern::is_pattern(act_on) =>
+            {
+                // Patterns are validated when parsing, and only allowed for removals.
+                if let Ok(pattern) = EntityPattern::new(act_on) {
+                    let (before, after) = match command {
+                        crate::SrcDirective::Unref => {
+                            let before = self.refs.len();
+                            self.refs.retain(|e| !pattern.matches(e));
+                            (before, self.refs.len())
+                        }
+                        crate::SrcDirective::Undef => {
+                            let before = self.defs.len();
+                            self.defs.retain(|e| !pattern.matches(e));
+                            (before, self.defs.len())
+                        }
+                        crate::SrcDirective::RuntimeUnref => {
+                            let before = self.runtime_refs.len();
+                            self.runtime_refs.retain(|e| !pattern.matches(e));
+                            (before, self.runtime_refs.len())
+                        }
+                        _ => return None,
                     };
+                    if before == after {
+                        return Some("the pattern matched nothing");
+                    }
                 }
-                Directive::EntityDirective(ed) => self.entity_directives.push(ed.clone()),
-                Directive::ManualRef(mr) => self.manual_ref_directives.push(mr.clone()),
-                Directive::BinaryRef(mr) => self.binary_ref_directives.push(BinaryRefAndPath {
-                    entity_path: entity_path.map(|e| e.to_string()),
-                    binary_refs: mr.clone(),
-                }),
-                Directive::AttrStringList(attr) => {
-                    self.attr_string_list_directives.push(attr.clone())
+            }
+            Directive::SrcDirective(SrcDirectiveConfig { command, act_on }) => {
+                let changed = match command {
+                    crate::SrcDirective::Ref => self.refs.insert(act_on.clone()),
+                    crate::SrcDirective::Unref => self.refs.remove(act_on),
+                    crate::SrcDirective::Def => self.defs.insert(act_on.clone()),
+                    crate::SrcDirective::Undef => self.defs.remove(act_on),
+                    crate::SrcDirective::RuntimeRef => self.runtime_refs.insert(act_on.clone()),
+                    crate::SrcDirective::RuntimeUnref => self.runtime_refs.remove(act_on),
+                };
+                if !changed {
+                    return Some(match command {
+                        crate::SrcDirective::Ref => "already referenced",
+                        crate::SrcDirective::Unref => "not referenced",
+                        crate::SrcDirective::Def => "already defined",
+                        crate::SrcDirective::Undef => "not defined",
+                        crate::SrcDirective::RuntimeRef => "already a runtime reference",
+                        crate::SrcDirective::RuntimeUnref => "not a runtime reference",
+                    });
+                }
+            }
+            Directive::EntityDirective(ed) => self.entity_directives.push(ed.clone()),
+            Directive::ManualRef(mr) => self.manual_ref_directives.push(mr.clone()),
+            Directive::BinaryRef(mr) => self.binary_ref_directives.push(BinaryRefAndPath {
+                entity_path: entity_path.map(|e| e.to_string()),
+                binary_refs: mr.clone(),
+                inferred: false,
+            }),
+            Directive::AttrStringList(attr) => self.attr_string_list_directives.push(attr.clone()),
+            Directive::AttrScalar(attr) => self.attr_scalar_directives.push(attr.clone()),
+            Directive::TargetName(name) => self.target_name_directives.push(name.clone()),
+            // Ignored files are skipped before they ever reach a tree node.
+            Directive::Ignore => (),
+            // The Python extractor acts on it, by not generating a binary for the file.
+            Directive::NoBinary => {
+                if !entity_path.map(|p| p.ends_with(".py")).unwrap_or(false) {
+                    return Some("only Python sources generate binaries by themselves");
                 }
             }
         }
+        None
+    }
+
+    fn normalize_directives(&mut self) {
         self.entity_directives.sort();
         self.entity_directives.dedup();
 
@@ -99,13 +191,51 @@ impl TreeNode {
 
         self.attr_string_list_directives.sort();
         self.attr_string_list_directives.dedup();
+
+        self.attr_scalar_directives.sort();
+        self.attr_scalar_directives.dedup();
+
+        self.target_name_directives.sort();
+        self.target_name_directives.dedup();
+    }
+
+    /// Applies the directives in order, recording where each came from and any that changed
+    /// nothing so they can be linted later.
+    pub fn apply_directives_with_origins(&mut self, directives: &[(Directive, DirectiveOrigin)]) {
+        for (directive, origin) in directives.iter() {
+            let entity_path = match origin {
+                DirectiveOrigin::File { path, .. } => Some(path.as_str()),
+                DirectiveOrigin::PathDirective { .. } => None,
+            };
+            if let Some(reason) = self.apply_directive(directive, entity_path) {
+                self.ineffective_directives.push(IneffectiveDirective {
+                    directive: directive.to_string(),
+                    origin: origin.clone(),
+                    reason: reason.to_string(),
+                });
+            }
+            self.add_directive_origin(directive, origin.clone());
+        }
+        self.normalize_directives();
+        self.ineffective_directives.sort();
+        self.ineffective_directives.dedup();
+    }
+
+    pub fn add_directive_origin(&mut self, directive: &Directive, origin: DirectiveOrigin) {
+        self.directive_origins
+            .entry(directive.to_string())
+            .or_default()
+            .insert(origin);
     }
 
     pub fn apply_directives<'a, T>(&mut self, directives: T)
     where
         T: IntoIterator<Item = &'a Directive> + Copy + std::fmt::Debug,
     {
-        self.apply_directives_with_path(directives, None);
+        for directive in directives.into_iter() {
+            self.apply_directive(directive, None);
+        }
+        self.normalize_directives();
     }
 
     pub fn merge(&mut self, mut other: TreeNode) {
@@ -137,5 +267,159 @@ impl TreeNode {
             .extend(std::mem::take(&mut other.attr_string_list_directives).into_iter());
         self.attr_string_list_directives.sort();
         self.attr_string_list_directives.dedup();
+
+        self.attr_scalar_directives
+            .extend(std::mem::take(&mut other.attr_scalar_directives));
+        self.attr_scalar_directives.sort();
+        self.attr_scalar_directives.dedup();
+
+        self.target_name_directives
+            .extend(std::mem::take(&mut other.target_name_directives));
+        self.target_name_directives.sort();
+        self.target_name_directives.dedup();
+
+        for (directive, origins) in std::mem::take(&mut other.directive_origins) {
+            self.directive_origins
+                .entry(directive)
+                .or_default()
+                .extend(origins);
+        }
+
+        self.ineffective_directives
+            .extend(std::mem::take(&mut other.ineffective_directives));
+        self.ineffective_directives.sort();
+        self.ineffective_directives.dedup();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_pattern_directives() {
+        let mut t = TreeNode {
+            defs: BTreeSet::from([
+                "com.foo.Bar".to_string(),
+                "com.foo.Bar$Companion".to_string(),
+            ]),
+            refs: HashSet::from([
+                "com.foo.internal.A".to_string(),
+                "com.foo.internal.b.B".to_string(),
+                "com.foo.Api".to_string(),
+                "com.foo.*".to_string(),
+                "com.foo.Impl".to_string(),
+            ]),
+            ..Default::default()
+        };
+        let directives = Directive::from_strings(&vec![
+            "unref: glob:com.foo.internal.*".to_string(),
+            r"undef: re:.*\$Companion".to_string(),
+            // a bare `*` only names the wildcard import
+            "unref: com.foo.*".to_string(),
+        ])
+        .unwrap();
+        t.apply_directives(&directives);
+
+        assert_eq!(t.defs, BTreeSet::from(["com.foo.Bar".to_string()]));
+        assert_eq!(
+            t.refs,
+            HashSet::from(["com.foo.Api".to_string(), "com.foo.Impl".to_string()])
+        );
+    }
+
+    #[test]
+    fn marks_inferred_binaries() {
+        let t = TreeNode::try_from(crate::api::extracted_data::DataBlock {
+            entity_path: "tools/run.py".to_string(),
+            defs: BTreeSet::from(["tools.run".to_string()]),
+            refs: HashSet::default(),
+            ignored_refs: BTreeSet::default(),
+            runtime_refs: BTreeSet::default(),
+            bzl_gen_build_commands: HashSet::from([
+                "binary_generate: run_bin".to_string(),
+                "binary_generate: other_bin".to_string(),
+            ]),
+            bzl_gen_build_command_lines: Default::default(),
+            inferred_bzl_gen_build_commands: BTreeSet::from([
+                "binary_generate: run_bin".to_string()
+            ]),
+        })
+        .unwrap();
+        let inferred: Vec<(&str, bool)> = t
+            .binary_ref_directives
+            .iter()
+            .map(|b| (b.binary_refs.binary_name.as_str(), b.inferred))
+            .collect();
+        assert_eq!(inferred, vec![("other_bin", false), ("run_bin", true)]);
+    }
+
+    #[test]
+    fn records_ineffective_directives() {
+        let mut t = TreeNode {
+            defs: BTreeSet::from(["com.foo.Bar".to_string()]),
+            refs: HashSet::from(["com.foo.Api".to_string()]),
+            ..Default::default()
+        };
+        let file = DirectiveOrigin::File {
+            path: "com/foo/Bar.scala".to_string(),
+            line: Some(3),
+        };
+        let prefix = DirectiveOrigin::PathDirective {
+            prefix: "com/foo".to_string(),
+        };
+        let parse = |s: &str| {
+            Directive::from_strings(&vec![s.to_string()])
+                .unwrap()
+                .remove(0)
+        };
+        t.apply_directives_with_origins(&[
+            (parse("unref: com.foo.Api"), file.clone()),
+            (parse("undef: com.foo.Missing"), file.clone()),
+            (parse("unref: glob:com.other.*"), prefix.clone()),
+            (parse("def: com.foo.Bar"), prefix.clone()),
+            (parse("no_binary"), file.clone()),
+            (parse("no_binary"), prefix.clone()),
+            (
+                parse("no_binary"),
+                DirectiveOrigin::File {
+                    path: "com/foo/main.py".to_string(),
+                    line: Some(1),
+                },
+            ),
+        ]);
+
+        assert!(t.refs.is_empty());
+        assert_eq!(
+            t.ineffective_directives,
+            vec![
+                IneffectiveDirective {
+                    directive: "def:com.foo.Bar".to_string(),
+                    origin: prefix.clone(),
+                    reason: "already defined".to_string(),
+                },
+                IneffectiveDirective {
+                    directive: "no_binary".to_string(),
+                    origin: file.clone(),
+                    reason: "only Python sources generate binaries by themselves".to_string(),
+                },
+                IneffectiveDirective {
+                    directive: "no_binary".to_string(),
+                    origin: prefix.clone(),
+                    reason: "only Python sources generate binaries by themselves".to_string(),
+                },
+                IneffectiveDirective {
+                    directive: "undef:com.foo.Missing".to_string(),
+                    origin: file.clone(),
+                    reason: "not defined".to_string(),
+                },
+                IneffectiveDirective {
+                    directive: "unref:glob:com.other.*".to_string(),
+                    origin: prefix.clone(),
+                    reason: "the pattern matched nothing".to_string(),
+                },
+            ]
+        );
+        assert_eq!(t.directive_origins.len(), 5);
     }
 }
@@ -0,0 +1,105 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// Replaces a dependency label with another one, e.g. to depend on `//platform/logging` instead
+/// of `@maven//:log4j`. `from` is either an exact label, a prefix ending in `*`
+/// (`@pip//*`) or a regular expression prefixed with `re:` (`re:@pip//(.*)`). For prefixes a
+/// `to` ending in `*` gets the rest of the label appended (`//third_party/python:*`), and
+/// regular expressions can refer to their groups (`//third_party/python:$1`).
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LabelRewrite {
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug)]
+enum LabelMatch {
+    Exact(String),
+    Prefix(String),
+    Pattern(Regex),
+}
+
+/// The `label_rewrites` of a project, the first one matching a label wins.
+#[derive(Debug, Default)]
+pub struct LabelRewriter {
+    rules: Vec<(LabelMatch, String)>,
+}
+
+impl LabelRewriter {
+    pub fn new(rewrites: &[LabelRewrite]) -> anyhow::Result<LabelRewriter> {
+        let mut rules = Vec::default();
+        for rewrite in rewrites {
+            let label_match = if let Some(re) = rewrite.from.strip_prefix("re:") {
+                LabelMatch::Pattern(Regex::new(&format!("^(?:{})$", re))?)
+            } else if let Some(prefix) = rewrite.from.strip_suffix('*') {
+                LabelMatch::Prefix(prefix.to_string())
+            } else {
+                LabelMatch::Exact(rewrite.from.clone())
+            };
+            rules.push((label_match, rewrite.to.clone()));
+        }
+        Ok(LabelRewriter { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The rewritten label, None when no rewrite matches it.
+    pub fn rewrite(&self, label: &str) -> Option<String> {
+        self.rules
+            .iter()
+            .find_map(|(label_match, to)| match label_match {
+                LabelMatch::Exact(from) => (from == label).then(|| to.clone()),
+                LabelMatch::Prefix(prefix) => {
+                    let rest = label.strip_prefix(prefix.as_str())?;
+                    match to.strip_suffix('*') {
+                        Some(to) => Some(format!("{}{}", to, rest)),
+                        None => Some(to.clone()),
+                    }
+                }
+                LabelMatch::Pattern(re) => re
+                    .is_match(label)
+                    .then(|| re.replace(label, to.as_str()).to_string()),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewriting() {
+        let rewrite = |from: &str, to: &str| LabelRewrite {
+            from: from.to_string(),
+            to: to.to_string(),
+        };
+        let rewriter = LabelRewriter::new(&[
+            rewrite("@maven//:log4j", "//platform/logging"),
+            rewrite("@pip//*", "//third_party/python:*"),
+            rewrite("@old//*", "//legacy"),
+            rewrite(r"re:@npm//(\w+)/(\w+)", "//third_party/js/$1:$2"),
+            rewrite("@pip//numpy", "//never/reached"),
+        ])
+        .unwrap();
+        assert_eq!(
+            rewriter.rewrite("@maven//:log4j"),
+            Some("//platform/logging".to_string())
+        );
+        assert_eq!(rewriter.rewrite("@maven//:log4j_api"), None);
+        assert_eq!(
+            rewriter.rewrite("@pip//numpy"),
+            Some("//third_party/python:numpy".to_string())
+        );
+        assert_eq!(rewriter.rewrite("@old//a:b"), Some("//legacy".to_string()));
+        assert_eq!(
+            rewriter.rewrite("@npm//react/dom"),
+            Some("//third_party/js/react:dom".to_string())
+        );
+        assert_eq!(rewriter.rewrite("@npm//react/dom/x"), None);
+        assert_eq!(rewriter.rewrite("//src/main:lib"), None);
+
+        assert!(LabelRewriter::new(&[rewrite("re:(", "x")]).is_err());
+    }
+}
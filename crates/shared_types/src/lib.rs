@@ -1,10 +1,13 @@
 pub mod api;
 pub mod build_config;
 pub mod directive;
+pub mod entity_pattern;
 pub mod internal_types;
+pub mod label_rewrite;
 pub mod module_config;
 mod project_conf;
 
 pub use directive::{Directive, EntityDirective, SrcDirective};
 pub use project_conf::{DirectiveConf, ProjectConf};
 pub mod serde_helpers;
+pub mod template;
@@ -21,9 +21,36 @@ pub struct ModuleConfig {
     #[serde(default)]
     pub circular_dependency_allow_list: Vec<String>,
 
+    /// Globs, relative to the working directory, of files to skip during extraction
+    /// and to leave out of generated `glob()`/`srcs`.
+    #[serde(default)]
+    pub exclude_globs: Vec<String>,
+
     /// When true, prepend `# buildifier: disable=format` on the first line of generated BUILD files.
     #[serde(default)]
     pub disable_format: bool,
+
+    /// When true, aggregated test directories get one test target per file matching
+    /// `test_globs` instead of a single target for the whole directory.
+    #[serde(default)]
+    pub per_file_tests: bool,
+
+    /// Extra attributes of the per-file test targets, by file pattern.
+    #[serde(default)]
+    pub test_attributes: Vec<TestAttributes>,
+
+    /// When set, the targets of this configuration go to their own section of BUILD files,
+    /// between the `BZL_GEN_BUILD_<TAG>_GENERATED_CODE` markers, as with `--overwrite <TAG>`.
+    #[serde(default)]
+    pub tag: Option<String>,
+}
+
+/// `attr.*` directives, e.g. `attr.string: size -> large`, for the per-file test targets of the
+/// files matching `glob`, relative to the working directory.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TestAttributes {
+    pub glob: String,
+    pub attributes: Vec<String>,
 }
 
 /// Prepends `# buildifier: disable=format` on the first line when disable_format is true.
@@ -49,12 +76,56 @@ impl ModuleConfig {
         self.test_roots.extend(other.test_roots.into_iter());
         self.test_roots.sort();
         self.test_roots.dedup();
+
+        self.exclude_globs.extend(other.exclude_globs);
+        self.exclude_globs.sort();
+        self.exclude_globs.dedup();
+
+        self.per_file_tests |= other.per_file_tests;
+        self.test_attributes.extend(other.test_attributes);
+        self.test_attributes.sort();
+        self.test_attributes.dedup();
+
+        match (&mut self.tag, other.tag) {
+            (t @ None, Some(o)) => *t = Some(o),
+            (Some(t), Some(o)) if *t != o => {
+                panic!("Unable to merge configurations tagged {} and {}", t, o)
+            }
+            _ => (),
+        };
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::maybe_add_buildifier_disable;
+    use super::{maybe_add_buildifier_disable, ModuleConfig};
+
+    #[test]
+    fn test_merge_tags() {
+        let tagged = |tag: Option<&str>| ModuleConfig {
+            tag: tag.map(|t| t.to_string()),
+            ..Default::default()
+        };
+        let mut config = tagged(None);
+        config.merge(tagged(Some("PY")));
+        assert_eq!(config.tag.as_deref(), Some("PY"));
+        config.merge(tagged(Some("PY")));
+        config.merge(tagged(None));
+        assert_eq!(config.tag.as_deref(), Some("PY"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Unable to merge configurations tagged PY and PROTO")]
+    fn test_merge_conflicting_tags() {
+        let mut config = ModuleConfig {
+            tag: Some("PY".to_string()),
+            ..Default::default()
+        };
+        config.merge(ModuleConfig {
+            tag: Some("PROTO".to_string()),
+            ..Default::default()
+        });
+    }
 
     #[test]
     fn test_maybe_add_buildifier_disable() {
@@ -5,7 +5,7 @@ use std::{
 
 use serde::{Deserialize, Serialize};
 
-use crate::module_config::ModuleConfig;
+use crate::{directive::DirectiveOrigin, label_rewrite::LabelRewrite, module_config::ModuleConfig};
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct ProjectConf {
@@ -17,6 +17,19 @@ pub struct ProjectConf {
 
     #[serde(default)]
     pub path_directives: Vec<DirectiveConf>,
+
+    /// Globs, relative to the working directory, of BUILD files print-build never deletes.
+    #[serde(default)]
+    pub keep_build_files: Vec<String>,
+
+    /// Rewrites applied to the dependencies of generated targets, see `LabelRewrite`.
+    #[serde(default)]
+    pub label_rewrites: Vec<LabelRewrite>,
+
+    /// Labels no generated target may depend on: exact, wildcards or `re:` regular expressions,
+    /// as in entity directives.
+    #[serde(default)]
+    pub banned_labels: Vec<String>,
 }
 impl ProjectConf {
     pub fn merge(&mut self, other: ProjectConf) {
@@ -29,6 +42,21 @@ impl ProjectConf {
         self.path_directives.sort();
         self.path_directives.dedup();
 
+        self.keep_build_files.extend(other.keep_build_files);
+        self.keep_build_files.sort();
+        self.keep_build_files.dedup();
+
+        // The first matching rewrite wins, so these keep their order.
+        for rewrite in other.label_rewrites {
+            if !self.label_rewrites.contains(&rewrite) {
+                self.label_rewrites.push(rewrite);
+            }
+        }
+
+        self.banned_labels.extend(other.banned_labels);
+        self.banned_labels.sort();
+        self.banned_labels.dedup();
+
         for (k, v) in other.configurations {
             let e = self.configurations.entry(k);
             match e {
@@ -86,12 +114,21 @@ impl DirectiveConf {
         }
     }
 
+    pub fn origin(&self) -> DirectiveOrigin {
+        DirectiveOrigin::PathDirective {
+            prefix: self.prefix.clone(),
+        }
+    }
+
     pub fn directives(&self) -> Arc<anyhow::Result<Vec<crate::Directive>>> {
         let mut mutex = self.directive_cache.lock().unwrap();
         if let Some(r) = mutex.as_ref() {
             return r.clone();
         }
-        let v = Arc::new(crate::Directive::from_strings(&self.directive_strings));
+        let v = Arc::new(crate::Directive::from_strings_with_origin(
+            &self.directive_strings,
+            &self.origin(),
+        ));
         *mutex = Some(v.clone());
         v
     }
@@ -169,20 +206,29 @@ mod tests {
                             }),
                             test: None,
                             binary_application: None,
-                            secondary_rules: BTreeMap::default()
+                            secondary_rules: BTreeMap::default(),
+                            secondary_rule_for: BTreeMap::default(),
+                            child_filegroup: Default::default()
                         },
                         main_roots: vec!["src/main/python".to_string()],
                         test_roots: vec!["src/test/python".to_string()],
                         test_globs: vec![],
                         circular_dependency_allow_list: vec![],
+                        exclude_globs: vec![],
                         disable_format: false,
+                        per_file_tests: false,
+                        test_attributes: vec![],
+                        tag: None,
                     }
                 )]),
                 includes: vec![],
                 path_directives: vec![DirectiveConf::new(
                     "module-a/src/test/scala/com/foo".to_string(),
                     vec!["runtime_ref:com.example.Bar".to_string()]
-                )]
+                )],
+                keep_build_files: vec![],
+                label_rewrites: vec![],
+                banned_labels: vec![],
             }
         );
     }
@@ -0,0 +1,221 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+
+/// A value of `extra_key_to_list` or `extra_key_to_value` with `${variable}` references, e.g.
+/// `${package}/docs` or `${deps|replace:_proto:_py}`. A value can use several variables, and each
+/// can be followed by filters separated by `|`:
+/// - `basename`: the part after the last `/` or `:`
+/// - `replace:from:to`: replaces every `from` with `to`
+/// - `prefix:text`: puts `text` in front
+///
+/// Variables holding a list (`srcs`, `deps`, ...) expand the value once per entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Variable { name: String, filters: Vec<Filter> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Filter {
+    Basename,
+    Replace(String, String),
+    Prefix(String),
+}
+
+impl Filter {
+    fn parse(filter: &str) -> Result<Filter> {
+        let (name, arg) = match filter.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (filter, None),
+        };
+        match (name, arg) {
+            ("basename", None) => Ok(Filter::Basename),
+            ("replace", Some(arg)) => match arg.split_once(':') {
+                Some((from, to)) if !from.is_empty() => {
+                    Ok(Filter::Replace(from.to_string(), to.to_string()))
+                }
+                _ => Err(anyhow!("replace needs two arguments: replace:from:to")),
+            },
+            ("prefix", Some(arg)) => Ok(Filter::Prefix(arg.to_string())),
+            _ => Err(anyhow!("Unknown filter {}", filter)),
+        }
+    }
+
+    fn apply(&self, value: &str) -> String {
+        match self {
+            Filter::Basename => value.rsplit(['/', ':']).next().unwrap_or(value).to_string(),
+            Filter::Replace(from, to) => value.replace(from.as_str(), to),
+            Filter::Prefix(prefix) => format!("{}{}", prefix, value),
+        }
+    }
+}
+
+/// The values variables of a template stand for, a plain value is a list of one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TemplateVariables {
+    variables: BTreeMap<String, Vec<String>>,
+}
+
+impl TemplateVariables {
+    pub fn insert(&mut self, name: &str, value: String) {
+        self.variables.insert(name.to_string(), vec![value]);
+    }
+
+    pub fn insert_list(&mut self, name: &str, values: Vec<String>) {
+        self.variables.insert(name.to_string(), values);
+    }
+}
+
+impl Template {
+    pub fn parse(value: &str) -> Result<Template> {
+        let mut parts = Vec::default();
+        let mut rest = value;
+        while let Some(start) = rest.find("${") {
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .map(|end| start + end)
+                .ok_or_else(|| anyhow!("Unclosed ${{ in {}", value))?;
+            let mut pieces = rest[start + 2..end].split('|');
+            let name = pieces.next().unwrap_or_default().trim().to_string();
+            if name.is_empty() {
+                return Err(anyhow!("Missing variable name in {}", value));
+            }
+            let filters = pieces
+                .map(|f| Filter::parse(f.trim()))
+                .collect::<Result<Vec<_>>>()
+                .map_err(|e| anyhow!("{} in {}", e, value))?;
+            parts.push(Part::Variable { name, filters });
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+        Ok(Template { parts })
+    }
+
+    /// Every value the template expands to, one for each combination of the entries of the list
+    /// variables it uses. A list variable with no entries gives no values at all.
+    pub fn expand(&self, variables: &TemplateVariables) -> Result<Vec<String>> {
+        let mut values = vec![String::default()];
+        for part in self.parts.iter() {
+            match part {
+                Part::Text(text) => values.iter_mut().for_each(|v| v.push_str(text)),
+                Part::Variable { name, filters } => {
+                    let entries = variables.variables.get(name).ok_or_else(|| {
+                        anyhow!(
+                            "Unknown variable ${{{}}}, expected one of: {}",
+                            name,
+                            variables
+                                .variables
+                                .keys()
+                                .cloned()
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        )
+                    })?;
+                    let entries: Vec<String> = entries
+                        .iter()
+                        .map(|e| filters.iter().fold(e.clone(), |acc, f| f.apply(&acc)))
+                        .collect();
+                    values = values
+                        .iter()
+                        .flat_map(|v| entries.iter().map(move |e| format!("{}{}", v, e)))
+                        .collect();
+                }
+            }
+        }
+        Ok(values)
+    }
+
+    /// Expands a template that has to give exactly one value, such as an `extra_key_to_value`.
+    pub fn expand_one(&self, variables: &TemplateVariables) -> Result<String> {
+        let mut values = self.expand(variables)?;
+        if values.len() != 1 {
+            return Err(anyhow!(
+                "Expected a single value but got {}: {:?}",
+                values.len(),
+                values
+            ));
+        }
+        Ok(values.remove(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables() -> TemplateVariables {
+        let mut variables = TemplateVariables::default();
+        variables.insert("name", "foo".to_string());
+        variables.insert("package", "com/example".to_string());
+        variables.insert_list(
+            "deps",
+            vec!["//a/b:b_proto".to_string(), "//c:c_proto".to_string()],
+        );
+        variables.insert_list("srcs", vec![]);
+        variables
+    }
+
+    fn expand(value: &str) -> Vec<String> {
+        Template::parse(value)
+            .unwrap()
+            .expand(&variables())
+            .unwrap()
+    }
+
+    #[test]
+    fn expanding() {
+        assert_eq!(expand("plain"), vec!["plain"]);
+        assert_eq!(expand(":${name}"), vec![":foo"]);
+        assert_eq!(
+            expand("${package}/docs/${name}.md"),
+            vec!["com/example/docs/foo.md"]
+        );
+        assert_eq!(
+            expand("${deps|replace:_proto:_py}"),
+            vec!["//a/b:b_py", "//c:c_py"]
+        );
+        assert_eq!(
+            expand("${deps|basename|prefix::}_java"),
+            vec![":b_proto_java", ":c_proto_java"]
+        );
+        assert_eq!(
+            expand("${name}_${deps|basename}"),
+            vec!["foo_b_proto", "foo_c_proto"]
+        );
+        assert!(expand("${srcs}").is_empty());
+    }
+
+    #[test]
+    fn errors() {
+        assert!(Template::parse("${name").is_err());
+        assert!(Template::parse("${}").is_err());
+        assert!(Template::parse("${name|upper}").is_err());
+        assert!(Template::parse("${name|replace:x}").is_err());
+        assert!(Template::parse("${nope}")
+            .unwrap()
+            .expand(&variables())
+            .is_err());
+        assert!(Template::parse("${deps}")
+            .unwrap()
+            .expand_one(&variables())
+            .is_err());
+        assert_eq!(
+            Template::parse("${package|basename}")
+                .unwrap()
+                .expand_one(&variables())
+                .unwrap(),
+            "example"
+        );
+    }
+}
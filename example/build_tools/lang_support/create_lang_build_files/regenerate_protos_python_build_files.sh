@@ -10,15 +10,12 @@ else
     REPO_ROOT="$( cd "$( dirname "${BASH_SOURCE[0]}" )" && cd ../../../ && pwd )"
 fi
 
-GEN_FLAVOR=python
+GEN_FLAVOR=protos_python
 source "$REPO_ROOT/build_tools/lang_support/create_lang_build_files/bzl_gen_build_common.sh"
 set -x
 
 bazel query '@pip//...' | grep "@pip.*:pkg" > $TMP_WORKING_STATE/external_targets
 
-bazel query 'kind("py_proto_library", com/...)' > /dev/null
-cat $OUTPUT_BASE/command.log | grep '//' >> $TMP_WORKING_STATE/external_targets
-
 CACHE_KEY="$(generate_cache_key $TMP_WORKING_STATE/external_targets $REPO_ROOT/WORKSPACE $REPO_ROOT/requirements_lock_3_10.txt)"
 rm -rf $TMP_WORKING_STATE/external_files &> /dev/null || true
 # try_fetch_from_remote_cache "remote_python_${CACHE_KEY}"
@@ -38,8 +35,7 @@ rm -rf $TMP_WORKING_STATE/external_files &> /dev/null || true
 # fi
 
 run_system_apps "build_tools/lang_support/create_lang_build_files/bazel_${GEN_FLAVOR}_modules.json" \
-  --no-aggregate-source \
-  --overwrite PY
+  --no-aggregate-source
 
 # If using WORKSPACE with pip_parse, you may need to set incompatible_generate_aliases = True.
 log "Rewriting python targets to --incompatible_generate_aliases form; @@rules_python~0.24.0~pip~pip_39_pandas// to @pip//pandas"